@@ -0,0 +1,78 @@
+//! The file allowlists of tenants, matched the same by the load balancer and counter_service.
+
+/// The corpus of requests without one.
+pub const DEFAULT_CORPUS: &str = "default";
+
+/// The name allowlists match: `file_name` in the default corpus, `corpus:file_name` in the others.
+pub fn qualified_name(corpus: &str, file_name: &str) -> String {
+    match corpus {
+        "" | DEFAULT_CORPUS => file_name.to_string(),
+        corpus => format!("{}:{}", corpus, file_name),
+    }
+}
+
+/// Glob match of the whole name, `*` matches any characters and `?` a single one, but neither matches `:`, so a
+/// pattern covers a single corpus: `*.txt` the default one, `books:*.txt` the `books` one.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // the last `*` seen and the name position it was tried at, to backtrack to
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == name[n] || (c == '?' && name[n] != ':') => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // no `*` before may take the `:` either
+                Some((star_p, star_n)) if name[star_n] != ':' => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                _ => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_qualified_name() {
+        assert_eq!(qualified_name("", "Titanic.txt"), "Titanic.txt");
+        assert_eq!(qualified_name("default", "Titanic.txt"), "Titanic.txt");
+        assert_eq!(qualified_name("books", "Titanic.txt"), "books:Titanic.txt");
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("text1.txt", "text1.txt"));
+        assert!(!matches("text1.txt", "text2.txt"));
+        assert!(matches("*", "any/file.txt"));
+        assert!(matches("acme/*.txt", "acme/report.txt"));
+        assert!(!matches("acme/*.txt", "acme/report.csv"));
+        assert!(matches("T?tanic.*", "Titanic.txt"));
+        assert!(matches("*a*b", "xaxxab"));
+        assert!(!matches("*a*b", "xaxxa"));
+        assert!(!matches("", "x"));
+    }
+
+    #[test]
+    fn test_matches_corpus() {
+        assert!(!matches("*.txt", "books:x.txt"));
+        assert!(!matches("*", "books:x.txt"));
+        assert!(!matches("?????x.txt", "books:x.txt"));
+        assert!(!matches("*:*", "x.txt"));
+        assert!(matches("books:*", "books:x.txt"));
+        assert!(matches("*:*.txt", "books:x.txt"));
+        assert!(!matches("books:*.txt", "books:x.csv"));
+    }
+}
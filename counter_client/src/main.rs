@@ -6,24 +6,27 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
 use colored::Colorize;
 use futures::future::join_all;
 use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
 use rand::seq::IndexedRandom;
-use rand::thread_rng;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::{Mutex, OnceCell};
 use tokio::time::Instant;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
 use tonic::Request;
-use tonic::transport::{Channel, Uri};
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Uri};
 
 use word_counter::counter_client::CounterClient;
 
-use crate::word_counter::{WordCountRequest, WordCountResponse};
+use crate::word_counter::{ListFilesRequest, ListFilesResponse, WordCountRequest, WordCountResponse};
 
 pub mod word_counter {
     include!("proto_gen/word_counter.rs");
@@ -34,6 +37,24 @@ pub mod word_counter {
 struct CliParams {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    tls: TlsParams,
+    #[arg(long, global = true, help = "api key of a tenant, required when the load balancer or server enforces auth")]
+    api_key: Option<String>,
+    #[arg(long, global = true, default_value = "", hide_default_value = true, help = "corpus of the file, the server's default corpus if not set")]
+    corpus: String,
+    #[arg(long, global = true, help = "count in texts with bytes invalid in their encoding, replaced rather than failing")]
+    lossy: bool,
+}
+
+#[derive(Args, Clone)]
+struct TlsParams {
+    #[arg(long, global = true, help = "CA certificate (PEM) of the server or load balancer, enables TLS")]
+    ca_cert: Option<PathBuf>,
+    #[arg(long, global = true, requires = "key", help = "client certificate (PEM), for mutual TLS")]
+    cert: Option<PathBuf>,
+    #[arg(long, global = true, requires = "cert", help = "private key (PEM) of the client certificate")]
+    key: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Clone)]
@@ -58,6 +79,8 @@ enum Commands {
         #[arg(long, default_value_t = false, help = "if use load balancer")]
         with_lb: bool,
     },
+    /// List the files of a corpus, from the server directly
+    List,
 }
 
 #[derive(Clone)]
@@ -65,41 +88,51 @@ struct ClientContext {
     params: CliParams,
     word_list: OnceCell<Vec<String>>,
     client: Arc<OnceCell<CounterClient<Channel>>>,
+    lb_tls: Option<TlsConnector>,
 }
 
 impl ClientContext {
-    fn new(params: CliParams) -> Self {
-        ClientContext {
+    fn new(params: CliParams) -> Result<Self> {
+        Ok(ClientContext {
+            lb_tls: lb_tls_connector(&params.tls).context("init load balancer TLS failed")?,
             params,
             word_list: OnceCell::new(),
             client: Arc::new(OnceCell::new()),
-        }
+        })
     }
 
-    async fn init_client() -> CounterClient<Channel> {
-        let channel = Self::init_channel().await
+    async fn init_client(tls: &TlsParams) -> CounterClient<Channel> {
+        let channel = Self::init_channel(tls).await
             .context("init RPC client failed")
             .unwrap_or_else(|e| { panic!("{:#?}", e); });
         CounterClient::new(channel)
     }
 
-    async fn init_channel() -> Result<Channel> {
-        let uri: Uri = Uri::from_str("http://server1:50051").context("parse server Uri failed")?;
-        let inner_endpoint = Channel::builder(uri)
+    async fn init_channel(tls: &TlsParams) -> Result<Channel> {
+        let scheme = if tls.ca_cert.is_some() { "https" } else { "http" };
+        let uri: Uri = Uri::from_str(&format!("{}://server1:50051", scheme)).context("parse server Uri failed")?;
+        let mut inner_endpoint = Channel::builder(uri)
             .connect_timeout(Duration::from_secs(5))
             .tcp_keepalive(Some(Duration::from_secs(30)))
             .timeout(Duration::from_secs(10));
+        if let Some(ca_cert) = &tls.ca_cert {
+            let mut tls_config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read(ca_cert)?));
+            if let (Some(cert), Some(key)) = (&tls.cert, &tls.key) {
+                tls_config = tls_config.identity(Identity::from_pem(read(cert)?, read(key)?));
+            }
+            inner_endpoint = inner_endpoint.tls_config(tls_config).context("configure RPC channel TLS failed")?;
+        }
         let channel = inner_endpoint.connect().await.context("RPC channel connect failed")?;
         Ok(channel)
     }
 
     async fn get_client(&self) -> CounterClient<Channel> {
-        self.client.deref().get_or_init(|| async { Self::init_client().await }).await.clone()
+        self.client.deref().get_or_init(|| async { Self::init_client(&self.params.tls).await }).await.clone()
     }
 
     async fn get_random_word(&self) -> String {
         let words: Vec<String> = self.word_list.get_or_init(|| async {
-            let word_list_path = PathBuf::from(&format!("{}", env::var("WORD_LIST_PATH").unwrap_or("src/orchard-street-medium.txt".to_string())));
+            let word_list_path = PathBuf::from(env::var("WORD_LIST_PATH").unwrap_or("src/orchard-street-medium.txt".to_string()));
             let file = File::open(word_list_path).await.context("word list file not found").unwrap();
             let reader = BufReader::new(file);
             let mut lines = reader.lines();
@@ -109,7 +142,7 @@ impl ClientContext {
             }
             words
         }).await.clone();
-        if let Some(random_word) = words.choose(&mut thread_rng()) { return String::from(random_word); };
+        if let Some(random_word) = words.choose(&mut rand::rng()) { return String::from(random_word); };
         panic!("empty word list");
     }
 
@@ -122,14 +155,14 @@ impl ClientContext {
 
     fn try_get_batch_num(&self) -> Option<usize> {
         if let Commands::Random { batch, .. } = &self.params.command {
-            return Some(batch.clone());
+            return Some(*batch);
         }
         None
     }
 
     fn try_get_interval(&self) -> Option<u64> {
         if let Commands::Random { interval, .. } = &self.params.command {
-            return Some(interval.clone());
+            return Some(*interval);
         }
         None
     }
@@ -138,13 +171,15 @@ impl ClientContext {
         match &self.params.command {
             Commands::Count { file_name, .. } => { file_name.clone() }
             Commands::Random { file_name, .. } => { file_name.clone() }
+            Commands::List => { String::new() }
         }
     }
 
     fn with_lb(&self) -> bool {
         match &self.params.command {
-            Commands::Count { with_lb, .. } => { with_lb.clone() }
-            Commands::Random { with_lb, .. } => { with_lb.clone() }
+            Commands::Count { with_lb, .. } => { *with_lb }
+            Commands::Random { with_lb, .. } => { *with_lb }
+            Commands::List => { false }
         }
     }
 }
@@ -152,7 +187,7 @@ impl ClientContext {
 #[tokio::main]
 async fn main() {
     let params = CliParams::parse();
-    let mut client_ctx = ClientContext::new(params);
+    let mut client_ctx = ClientContext::new(params).unwrap_or_else(|e| { panic!("{:#?}", e); });
     exec(&mut client_ctx).await
 }
 
@@ -164,6 +199,20 @@ async fn exec(client_ctx: &mut ClientContext) {
         Commands::Random { .. } => {
             exec_random_query(client_ctx).await
         }
+        Commands::List => {
+            exec_list(client_ctx).await
+        }
+    }
+}
+
+async fn exec_list(client_ctx: &mut ClientContext) {
+    match list_files(client_ctx).await {
+        Ok(resp) => {
+            for file_name in resp.file_names {
+                println!("{}", file_name);
+            }
+        }
+        Err(e) => println!("❌ {}, corpus: {}, err={:?}", "failed".red(), client_ctx.params.corpus, e),
     }
 }
 
@@ -191,7 +240,7 @@ async fn exec_random_query(client_ctx: &mut ClientContext) {
             *idx += 1;
             bar.set_prefix(format!("[{}/{}]", idx.to_string().blue(), total));
             bar.inc(1);
-            bar.set_message(format!("{}", state_message(req, resp, latency)));
+            bar.set_message(state_message(req, resp, latency));
         });
         handles.push(handle);
         thread::sleep(Duration::from_millis(interval_ms));
@@ -241,7 +290,7 @@ fn fmt_latency(latency: Duration) -> String {
 
 async fn call_count(client_ctx: &mut ClientContext, req: WordCountRequest) -> Result<WordCountResponse> {
     if client_ctx.with_lb() {
-        count_with_lb(req).await
+        count_with_lb(client_ctx.lb_tls.as_ref(), client_ctx.params.api_key.as_deref(), req).await
     } else {
         count_without_lb(client_ctx, req).await
     }
@@ -251,6 +300,8 @@ fn build_request(client_ctx: &ClientContext) -> WordCountRequest {
     WordCountRequest {
         word: client_ctx.try_get_query_word().unwrap(), // should not panic
         file_name: client_ctx.get_file_name().clone(),
+        corpus: client_ctx.params.corpus.clone(),
+        lossy: client_ctx.params.lossy,
     }
 }
 
@@ -258,22 +309,84 @@ async fn build_random_request(client_ctx: &ClientContext) -> WordCountRequest {
     WordCountRequest {
         word: client_ctx.get_random_word().await,
         file_name: client_ctx.get_file_name().clone(),
+        corpus: client_ctx.params.corpus.clone(),
+        lossy: client_ctx.params.lossy,
     }
 }
 
 // RPC
 async fn count_without_lb(client_ctx: &mut ClientContext, req: WordCountRequest) -> Result<WordCountResponse> {
-    let resp = client_ctx.get_client().await.count(Request::new(req)).await.context("call RPC method: count failed")?;
+    let req = authorized(client_ctx, req)?;
+    let resp = client_ctx.get_client().await.count(req).await.context("call RPC method: count failed")?;
+    Ok(resp.into_inner())
+}
+
+async fn list_files(client_ctx: &mut ClientContext) -> Result<ListFilesResponse> {
+    let req = authorized(client_ctx, ListFilesRequest { corpus: client_ctx.params.corpus.clone() })?;
+    let resp = client_ctx.get_client().await.list_files(req).await.context("call RPC method: list_files failed")?;
     Ok(resp.into_inner())
 }
 
+fn authorized<T>(client_ctx: &ClientContext, req: T) -> Result<Request<T>> {
+    let mut req = Request::new(req);
+    if let Some(api_key) = &client_ctx.params.api_key {
+        req.metadata_mut().insert("x-api-key", MetadataValue::try_from(api_key.as_str()).context("invalid api key")?);
+    }
+    Ok(req)
+}
+
+fn read(path: &PathBuf) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read TLS file:{:?}", path))
+}
+
+// None when the load balancer is reached in plaintext
+fn lb_tls_connector(tls: &TlsParams) -> Result<Option<TlsConnector>> {
+    let Some(ca_cert) = &tls.ca_cert else {
+        return Ok(None);
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut read(ca_cert)?.as_slice()) {
+        roots.add(cert.context("invalid CA certificate")?).context("invalid CA certificate")?;
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("TLS protocol versions unsupported")?
+        .with_root_certificates(roots);
+    let config = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => {
+            let certs = rustls_pemfile::certs(&mut read(cert)?.as_slice()).collect::<Result<Vec<_>, _>>().context("invalid client certificate")?;
+            let key = rustls_pemfile::private_key(&mut read(key)?.as_slice())
+                .context("invalid client key")?
+                .ok_or_else(|| anyhow!("no private key found in {:?}", key))?;
+            builder.with_client_auth_cert(certs, key).context("invalid client certificate or key")?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(Some(TlsConnector::from(Arc::new(config))))
+}
+
 // TCP
-async fn count_with_lb(req: WordCountRequest) -> Result<WordCountResponse> {
+async fn count_with_lb(tls: Option<&TlsConnector>, api_key: Option<&str>, req: WordCountRequest) -> Result<WordCountResponse> {
     let mut stream = TcpStream::connect("load_balancer:8080").await.context("init TCP stream failed")?;
+    match tls {
+        Some(connector) => {
+            let server_name = ServerName::try_from("load_balancer").context("invalid load balancer name")?;
+            let mut stream = connector.connect(server_name, stream).await.context("TLS handshake failed")?;
+            exchange(&mut stream, api_key, req).await
+        }
+        None => exchange(&mut stream, api_key, req).await,
+    }
+}
 
-    let message = serde_json::to_string(&req).context("TCP request serialize failed")?;
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, api_key: Option<&str>, req: WordCountRequest) -> Result<WordCountResponse> {
+    let mut message = serde_json::to_value(&req).context("TCP request serialize failed")?;
+    if let (Some(api_key), Some(fields)) = (api_key, message.as_object_mut()) {
+        fields.insert("api_key".to_string(), api_key.into());
+    }
+    let message = message.to_string();
     stream.write_u32(message.len() as u32).await.context("TCP stream fail to write message length")?;
     stream.write_all(message.as_bytes()).await.context("TCP stream write message failed")?;
+    stream.flush().await.context("TCP stream flush failed")?;
 
     let mut len_buf = [0u8; 4];
     stream.read_exact(&mut len_buf).await.context("failed to read response length")?;
@@ -6,6 +6,26 @@ pub struct WordCountRequest {
     pub word: ::prost::alloc::string::String,
     #[prost(string, tag = "2")]
     pub file_name: ::prost::alloc::string::String,
+    /// the default corpus when empty
+    #[prost(string, tag = "3")]
+    #[serde(default)]
+    pub corpus: ::prost::alloc::string::String,
+    /// bytes invalid in the detected encoding are replaced by U+FFFD rather than failing the count
+    #[prost(bool, tag = "4")]
+    #[serde(default)]
+    pub lossy: bool,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListFilesRequest {
+    /// the default corpus when empty
+    #[prost(string, tag = "1")]
+    pub corpus: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListFilesResponse {
+    /// paths relative to the corpus, sorted
+    #[prost(string, repeated, tag = "1")]
+    pub file_names: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[derive(serde::Serialize, serde::Deserialize)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -134,5 +154,29 @@ pub mod counter_client {
                 .insert(GrpcMethod::new("word_counter.Counter", "Count"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn list_files(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListFilesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListFilesResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/word_counter.Counter/ListFiles",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("word_counter.Counter", "ListFiles"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
@@ -0,0 +1,46 @@
+//! Throughput of the line reader against the mapped counter, on a large text of many lines and on one of a single line.
+//! Run with `cargo bench`.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use counter_service::extractor::Extractors;
+use counter_service::read_counter::ReadCounter;
+
+const TEXT_SIZE: usize = 64 * 1024 * 1024;
+
+// `texts/Titanic.txt` repeated to `TEXT_SIZE`, with its line breaks or without
+fn text_file(lines: bool) -> PathBuf {
+    let titanic = std::fs::read_to_string(Path::new("texts/Titanic.txt")).unwrap();
+    let titanic = if lines { titanic } else { titanic.replace(['\r', '\n'], " ") };
+    let name = if lines { "lines" } else { "single-line" };
+    let path = env::temp_dir().join(format!("read-counter-bench-{}-{}.txt", name, std::process::id()));
+    std::fs::write(&path, titanic.repeat(TEXT_SIZE / titanic.len() + 1)).unwrap();
+    path
+}
+
+fn bench_count(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let line_reader = ReadCounter::new(Extractors::default(), u64::MAX);
+    let mapped = ReadCounter::new(Extractors::default(), 0);
+    let mut group = c.benchmark_group("count");
+    group.sample_size(10);
+    for lines in [true, false] {
+        let path = text_file(lines);
+        let name = if lines { "lines" } else { "single_line" };
+        group.throughput(Throughput::Bytes(std::fs::metadata(&path).unwrap().len()));
+        for (counter_name, counter) in [("line_reader", &line_reader), ("mapped", &mapped)] {
+            group.bench_with_input(BenchmarkId::new(counter_name, name), &path, |b, path| {
+                b.iter(|| runtime.block_on(counter.count("Rose", path, false)).unwrap())
+            });
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_count);
+criterion_main!(benches);
@@ -9,6 +9,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .out_dir("src/proto_gen")
         .type_attribute("WordCountResponse", "#[derive(serde::Serialize, serde::Deserialize)]")
         .type_attribute("WordCountRequest", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // requests of clients not aware of corpora are for the default corpus
+        .field_attribute("WordCountRequest.corpus", "#[serde(default)]")
+        .field_attribute("WordCountRequest.lossy", "#[serde(default)]")
         .compile_protos(&[proto_file_path], &[proto_path])?;
     Ok(())
 }
\ No newline at end of file
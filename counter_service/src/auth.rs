@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+use allowlist::matches;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+
+// shared with the load balancer
+const METADATA_API_KEY: &str = "x-api-key";
+const METADATA_TENANT: &str = "x-tenant";
+
+/// The load balancer's `auth.toml`: tenants, their api keys and the files they may query, and the keys of
+/// services calling on behalf of tenants.
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    service_keys: Vec<String>,
+    #[serde(default)]
+    tenants: Vec<TenantEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenantEntry {
+    name: String,
+    api_keys: Vec<String>,
+    files: Vec<String>,
+}
+
+pub struct AuthPolicy {
+    service_keys: HashSet<String>,
+    by_key: HashMap<String, Arc<Tenant>>,
+    by_name: HashMap<String, Arc<Tenant>>,
+}
+
+#[derive(Debug)]
+pub struct Tenant {
+    name: String,
+    files: Vec<String>,
+}
+
+/// Who a Count is served for, set by `AuthInterceptor`.
+#[derive(Debug, Clone)]
+pub enum Caller {
+    // a service key without a tenant, such as the load balancer's deep health check
+    Service,
+    Tenant(Arc<Tenant>),
+}
+
+impl AuthPolicy {
+    /// Authentication is enabled by setting `AUTH_POLICY_PATH` to the load balancer's `auth.toml`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = env::var("AUTH_POLICY_PATH") else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(&path).with_context(|| format!("failed to read auth policy file:{:?}", path))?;
+        Self::parse(&content).with_context(|| format!("failed to parse auth policy file:{:?}", path)).map(Some)
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(content)?;
+        let mut policy = AuthPolicy {
+            service_keys: file.service_keys.into_iter().collect(),
+            by_key: HashMap::new(),
+            by_name: HashMap::new(),
+        };
+        for entry in file.tenants {
+            let tenant = Arc::new(Tenant { name: entry.name, files: entry.files });
+            for key in entry.api_keys {
+                policy.by_key.insert(key, Arc::clone(&tenant));
+            }
+            policy.by_name.insert(tenant.name.clone(), tenant);
+        }
+        Ok(policy)
+    }
+
+    // a service key acts for the tenant in `x-tenant`, a tenant key for its own tenant
+    fn caller(&self, metadata: &MetadataMap) -> Result<Caller, (Code, &'static str)> {
+        let key = metadata.get(METADATA_API_KEY).and_then(|key| key.to_str().ok())
+            .ok_or((Code::Unauthenticated, "missing api key"))?;
+        if self.service_keys.contains(key) {
+            return match metadata.get(METADATA_TENANT) {
+                Some(name) => name.to_str().ok().and_then(|name| self.by_name.get(name))
+                    .map(|tenant| Caller::Tenant(Arc::clone(tenant)))
+                    .ok_or((Code::PermissionDenied, "unknown tenant")),
+                None => Ok(Caller::Service),
+            };
+        }
+        self.by_key.get(key)
+            .map(|tenant| Caller::Tenant(Arc::clone(tenant)))
+            .ok_or((Code::Unauthenticated, "unknown api key"))
+    }
+}
+
+impl Caller {
+    pub fn allows(&self, file_name: &str) -> bool {
+        match self {
+            Caller::Service => true,
+            Caller::Tenant(tenant) => tenant.files.iter().any(|pattern| matches(pattern, file_name)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Caller::Service => "service",
+            Caller::Tenant(tenant) => &tenant.name,
+        }
+    }
+}
+
+/// Rejects Count calls without a known api key, and passes the `Caller` on in the request extensions.
+/// Every call is let through when no policy is set.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    policy: Option<Arc<AuthPolicy>>,
+}
+
+impl AuthInterceptor {
+    pub fn new(policy: Option<AuthPolicy>) -> Self {
+        AuthInterceptor { policy: policy.map(Arc::new) }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(policy) = &self.policy {
+            let caller = policy.caller(request.metadata()).map_err(|(code, message)| {
+                tracing::warn!("request rejected: {}", message);
+                Status::new(code, message)
+            })?;
+            request.extensions_mut().insert(caller);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POLICY: &str = "service_keys = [\"lb-key\"]\n\n[[tenants]]\nname = \"acme\"\napi_keys = [\"acme-key\"]\nfiles = [\"Titanic.txt\", \"acme/*.txt\"]";
+
+    fn call(metadata: &[(&'static str, &'static str)]) -> Result<Caller, Code> {
+        let mut interceptor = AuthInterceptor::new(Some(AuthPolicy::parse(POLICY).unwrap()));
+        let mut request = Request::new(());
+        for (name, value) in metadata {
+            request.metadata_mut().insert(*name, value.parse().unwrap());
+        }
+        interceptor.call(request)
+            .map(|request| request.extensions().get::<Caller>().cloned().unwrap())
+            .map_err(|status| status.code())
+    }
+
+    #[test]
+    fn test_interceptor() {
+        let caller = call(&[(METADATA_API_KEY, "acme-key")]).unwrap();
+        assert_eq!(caller.name(), "acme");
+        assert!(caller.allows("Titanic.txt"));
+        assert!(caller.allows("acme/q1.txt"));
+        assert!(!caller.allows("canary.txt"));
+
+        // the load balancer, on behalf of a tenant or for itself
+        let caller = call(&[(METADATA_API_KEY, "lb-key"), (METADATA_TENANT, "acme")]).unwrap();
+        assert!(!caller.allows("canary.txt"));
+        assert!(call(&[(METADATA_API_KEY, "lb-key")]).unwrap().allows("canary.txt"));
+
+        assert_eq!(call(&[]).unwrap_err(), Code::Unauthenticated);
+        assert_eq!(call(&[(METADATA_API_KEY, "unknown")]).unwrap_err(), Code::Unauthenticated);
+        // only a service key may act for a tenant
+        assert_eq!(call(&[(METADATA_API_KEY, "acme-key"), (METADATA_TENANT, "other")]).unwrap().name(), "acme");
+        assert_eq!(call(&[(METADATA_API_KEY, "lb-key"), (METADATA_TENANT, "other")]).unwrap_err(), Code::PermissionDenied);
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use counter_service::read_counter::text_extension;
+
+const DEFAULT_TEXT_PATH: &str = "../texts";
+const DEFAULT_TEXT_EXTENSIONS: &str = "txt";
+// the corpus of `TEXT_PATH`, used by requests without a corpus
+pub const DEFAULT_CORPUS: &str = allowlist::DEFAULT_CORPUS;
+
+/// Named corpora, each with its own text roots: `default` from `TEXT_PATH`, and the others from `CORPORA`.
+pub struct Corpora {
+    corpora: BTreeMap<String, Corpus>,
+}
+
+/// The text files of a corpus requests may count words in. A `file_name` is resolved under each root in turn, and only
+/// to a regular file with an allowed extension that is still inside that root once symlinks are followed. A compressed
+/// file is allowed by the extension under its compression one, e.g. `Titanic.txt.gz` by `txt`.
+pub struct Corpus {
+    roots: Vec<PathBuf>,
+    extensions: Vec<String>,
+}
+
+impl Corpora {
+    /// `TEXT_PATH` lists the roots of the default corpus, separated like `PATH` (default `../texts`). `CORPORA` adds
+    /// named ones as comma separated `name=roots`, e.g. `books=/data/books:/mnt/books,news=/data/news`.
+    /// `TEXT_EXTENSIONS` lists the extensions allowed in every corpus, comma separated (default `txt`).
+    pub fn from_env() -> Result<Self> {
+        let text_path = env::var("TEXT_PATH").unwrap_or(DEFAULT_TEXT_PATH.to_string());
+        let corpora = env::var("CORPORA").unwrap_or_default();
+        let extensions = env::var("TEXT_EXTENSIONS").unwrap_or(DEFAULT_TEXT_EXTENSIONS.to_string());
+        Self::parse(&text_path, &corpora, &extensions)
+    }
+
+    pub fn parse(text_path: &str, corpora: &str, extensions: &str) -> Result<Self> {
+        let extensions: Vec<String> = extensions.split(',').map(str::to_string).collect();
+        let default = Corpus::new(env::split_paths(text_path).collect(), extensions.clone()).context("invalid TEXT_PATH")?;
+        let mut parsed = BTreeMap::from([(DEFAULT_CORPUS.to_string(), default)]);
+        for entry in corpora.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (name, roots) = entry.split_once('=').ok_or_else(|| anyhow!("invalid CORPORA entry {:?}, expected name=roots", entry))?;
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Err(anyhow!("invalid corpus name {:?}, expected letters, digits, '_' and '-'", name));
+            }
+            let corpus = Corpus::new(env::split_paths(roots).collect(), extensions.clone())
+                .with_context(|| format!("invalid CORPORA entry {:?}", entry))?;
+            if parsed.insert(name.to_string(), corpus).is_some() {
+                return Err(anyhow!("duplicate corpus name {:?}", name));
+            }
+        }
+        Ok(Corpora { corpora: parsed })
+    }
+
+    // the default corpus for an empty name
+    pub fn get(&self, name: &str) -> Result<&Corpus> {
+        let name = if name.is_empty() { DEFAULT_CORPUS } else { name };
+        self.corpora.get(name).ok_or_else(|| anyhow!("unknown corpus: {}", name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Corpus)> {
+        self.corpora.iter()
+    }
+}
+
+impl Corpus {
+    fn new(roots: Vec<PathBuf>, extensions: Vec<String>) -> Result<Self> {
+        let roots: Vec<PathBuf> = roots.into_iter().filter(|root| !root.as_os_str().is_empty()).collect();
+        let extensions: Vec<String> = extensions.iter()
+            .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+            .filter(|extension| !extension.is_empty())
+            .collect();
+        if roots.is_empty() {
+            return Err(anyhow!("a corpus should have at least one root directory"));
+        }
+        if extensions.is_empty() {
+            return Err(anyhow!("TEXT_EXTENSIONS should name at least one extension"));
+        }
+        Ok(Corpus { roots, extensions })
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    // the canonical path of `file_name`, from the first root holding it
+    pub fn resolve(&self, file_name: &str) -> Result<PathBuf> {
+        let relative = Path::new(file_name);
+        // `..`, `.` and absolute paths are refused before touching the file system
+        if file_name.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(anyhow!("invalid file name: {}", file_name));
+        }
+        if !text_extension(relative).is_some_and(|extension| self.extensions.contains(&extension)) {
+            return Err(anyhow!("file extension not allowed: {}", file_name));
+        }
+        for root in &self.roots {
+            let root = match root.canonicalize() {
+                Ok(root) => root,
+                Err(e) => {
+                    tracing::warn!("text root {:?} unavailable, err={:?}", root, e);
+                    continue;
+                }
+            };
+            let path = match root.join(relative).canonicalize() {
+                Ok(path) => path,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("resolve file failed: {}", file_name)),
+            };
+            // a symlink may lead out of the root
+            if !path.starts_with(&root) {
+                return Err(anyhow!("file outside of text roots: {}", file_name));
+            }
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+        Err(anyhow!("file not exist: {}", file_name))
+    }
+
+    // the files `resolve` accepts, relative to their root, sorted and without the duplicates of later roots
+    pub fn list(&self) -> Vec<String> {
+        let mut names = vec![];
+        for root in &self.roots {
+            Self::walk(root, Path::new(""), &mut names);
+        }
+        names.retain(|name| self.resolve(name).is_ok());
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // symlinked directories are not entered, they may lead out of the root or into a cycle
+    fn walk(root: &Path, relative: &Path, names: &mut Vec<String>) {
+        let entries = match fs::read_dir(root.join(relative)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!("list text directory {:?} failed, err={:?}", root.join(relative), e);
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => Self::walk(root, &path, names),
+                Ok(_) => {
+                    if let Some(name) = path.to_str() {
+                        names.push(name.to_string());
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    // root/{a.txt, c.txt.gz, notes.md, notes.md.zst, sub/b.TXT, inner.txt -> sub/b.TXT, escape.txt -> ../secret.txt, dir.txt/}, secret.txt
+    fn corpus_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("corpus-{}-{}", name, std::process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(root.join("dir.txt")).unwrap();
+        for file in ["secret.txt", "root/a.txt", "root/notes.md", "root/sub/b.TXT", "root/c.txt.gz", "root/notes.md.zst"] {
+            fs::write(dir.join(file), "word").unwrap();
+        }
+        let _ = symlink(root.join("sub/b.TXT"), root.join("inner.txt"));
+        let _ = symlink(dir.join("secret.txt"), root.join("escape.txt"));
+        dir
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = corpus_dir("resolve");
+        let corpus = Corpus::new(vec![PathBuf::from("no_such_dir"), dir.join("root")], vec![".txt".to_string()]).unwrap();
+        let root = dir.join("root").canonicalize().unwrap();
+
+        assert_eq!(corpus.resolve("a.txt").unwrap(), root.join("a.txt"));
+        assert_eq!(corpus.resolve("sub/b.TXT").unwrap(), root.join("sub/b.TXT"));
+        assert_eq!(corpus.resolve("inner.txt").unwrap(), root.join("sub/b.TXT"));
+        assert_eq!(corpus.resolve("c.txt.gz").unwrap(), root.join("c.txt.gz"));
+
+        for (file_name, err) in [
+            ("../secret.txt", "invalid file name"),
+            ("sub/../../secret.txt", "invalid file name"),
+            ("./a.txt", "invalid file name"),
+            ("", "invalid file name"),
+            ("escape.txt", "file outside of text roots"),
+            ("notes.md", "file extension not allowed"),
+            ("notes.md.zst", "file extension not allowed"),
+            ("c.gz", "file extension not allowed"),
+            ("sub", "file extension not allowed"),
+            ("dir.txt", "file not exist"),
+            ("missing.txt", "file not exist"),
+        ] {
+            let result = corpus.resolve(file_name).map_err(|e| e.to_string());
+            assert!(result.as_ref().is_err_and(|e| e.starts_with(err)), "{:?} resolved to {:?}", file_name, result);
+        }
+        let absolute = dir.join("secret.txt");
+        assert!(corpus.resolve(absolute.to_str().unwrap()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = corpus_dir("list");
+        let corpus = Corpus::new(vec![dir.join("root"), PathBuf::from("texts")], vec!["txt".to_string()]).unwrap();
+        assert_eq!(corpus.list(), ["Titanic.txt", "a.txt", "c.txt.gz", "canary.txt", "inner.txt", "sub/b.TXT"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(Corpus::new(vec![], vec!["txt".to_string()]).is_err());
+        assert!(Corpus::new(vec![PathBuf::from("texts")], vec![" ".to_string()]).is_err());
+        let corpus = Corpus::new(vec![PathBuf::from("texts")], vec![" TXT".to_string(), "md".to_string()]).unwrap();
+        assert_eq!(corpus.extensions, ["txt", "md"]);
+        assert!(corpus.resolve("Titanic.txt").is_ok());
+    }
+
+    #[test]
+    fn test_corpora() {
+        let corpora = Corpora::parse("texts", "books=texts/../texts:no_such_dir, news=no_such_dir", "txt").unwrap();
+        assert_eq!(corpora.corpora.keys().collect::<Vec<_>>(), ["books", "default", "news"]);
+        assert!(corpora.get("").unwrap().resolve("Titanic.txt").is_ok());
+        assert!(corpora.get("books").unwrap().resolve("Titanic.txt").is_ok());
+        assert!(corpora.get("news").unwrap().resolve("Titanic.txt").is_err());
+        assert!(corpora.get("other").is_err());
+        assert_eq!(corpora.iter().map(|(_, corpus)| corpus.roots().len()).sum::<usize>(), 4);
+
+        assert!(Corpora::parse("texts", "books", "txt").is_err());
+        assert!(Corpora::parse("texts", "../books=texts", "txt").is_err());
+        assert!(Corpora::parse("texts", "default=texts", "txt").is_err());
+        assert!(Corpora::parse("", "", "txt").is_err());
+    }
+}
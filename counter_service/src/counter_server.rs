@@ -1,9 +1,10 @@
-use std::env;
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{anyhow, Context};
+use allowlist::qualified_name;
+use anyhow::anyhow;
 use anyhow::Result;
 use deadpool_redis::{Connection, Pool};
 use moka::future::Cache;
@@ -11,10 +12,12 @@ use redis::{cmd, RedisResult};
 use tokio::time::Instant;
 use tonic::{async_trait, Code, Request, Response, Status};
 
-use word_counter::{WordCountRequest, WordCountResponse};
+use word_counter::{ListFilesRequest, ListFilesResponse, WordCountRequest, WordCountResponse};
 
+use crate::auth::Caller;
+use crate::corpus::{Corpora, Corpus, DEFAULT_CORPUS};
 use crate::counter_server::word_counter::counter_server::Counter;
-use crate::read_counter::ReadCounter;
+use counter_service::read_counter::ReadCounter;
 
 pub mod word_counter {
     include!("proto_gen/word_counter.rs");
@@ -23,19 +26,23 @@ pub mod word_counter {
 const FAILED: i64 = -1;
 pub struct CounterService {
     redis_conn_pool: Pool,
+    corpora: Arc<Corpora>,
+    read_counter: ReadCounter,
     cache: Cache<String, i64>,
 }
 
 impl CounterService {
-    pub fn new(pool: Pool) -> Self {
+    pub fn new(pool: Pool, corpora: Arc<Corpora>) -> Self {
         CounterService {
             redis_conn_pool: pool,
+            corpora,
+            read_counter: ReadCounter::default(),
             cache: Cache::builder().max_capacity(32 * 1024 * 1024).build(), // with maximum 32mb
         }
     }
 
-    async fn count_from_file(&self, word: &str, file_path: &Path) -> i64 {
-        ReadCounter::count(word, file_path).await.unwrap_or_else(
+    async fn count_from_file(&self, word: &str, file_path: &Path, lossy: bool) -> i64 {
+        self.read_counter.count(word, file_path, lossy).await.unwrap_or_else(
             |e| {
                 tracing::error!("ReadCounter count failed, err={:?}", e);
                 FAILED
@@ -117,9 +124,16 @@ impl CounterService {
         Some(conn.unwrap())
     }
 
-    fn key(file_name: &str, word: &str) -> String {
-        let file_name = Path::new(file_name).file_stem().unwrap().to_str().unwrap();
-        format!("{}:{}", file_name, word)
+    // the whole relative path, so files of the same stem (`Titanic.txt`, `Titanic.md`, `Titanic.txt.gz`), or of the
+    // same path in another corpus, are counted apart. Lossy counts are kept apart too, a strict one may fail where they
+    // do not; `~` is not allowed in corpus names. Each part is prefixed with its length, words and file names may
+    // contain `:` and must not run into each other.
+    fn key(corpus: &str, file_name: &str, word: &str, lossy: bool) -> String {
+        let corpus = if corpus.is_empty() { DEFAULT_CORPUS } else { corpus };
+        let file_name: Vec<_> = Path::new(file_name).components().map(|component| component.as_os_str().to_string_lossy()).collect();
+        let file_name = file_name.join("/");
+        let lossy = if lossy { "lossy~" } else { "" };
+        format!("{}{}:{}{}:{}{}:{}", lossy, corpus.len(), corpus, file_name.len(), file_name, word.len(), word)
     }
 
     fn fmt_latency(latency: Duration) -> String {
@@ -132,16 +146,29 @@ impl CounterService {
 impl Counter for CounterService {
     async fn count(&self, request: Request<WordCountRequest>) -> std::result::Result<Response<WordCountResponse>, Status> {
         let start = Instant::now();
+        // the same file allowlist as the load balancer, for calls made to this service directly
+        let file_name = qualified_name(&request.get_ref().corpus, &request.get_ref().file_name);
+        if let Some(caller) = request.extensions().get::<Caller>().filter(|caller| !caller.allows(&file_name)) {
+            tracing::warn!("request rejected: file {} not allowed for {}", file_name, caller.name());
+            return Err(Status::permission_denied(format!("file not allowed: {}", file_name)));
+        }
         let req = request.into_inner();
         tracing::info!("request received: {:#?}", req);
-        if let Err(e) = req.check_params().context("request failed with invalid params") {
-            return Err(Status::new(Code::FailedPrecondition, format!("{:?}", e)));
-        }
-        let key = Self::key(&req.file_name, &req.word);
+        let resolved = req.check_params()
+            .and_then(|_| self.corpora.get(&req.corpus))
+            .and_then(|corpus| corpus.resolve(&req.file_name));
+        let file_path = match resolved {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                let e = e.context("request failed with invalid params");
+                return Err(Status::new(Code::FailedPrecondition, format!("{:?}", e)));
+            }
+        };
+        let key = Self::key(&req.corpus, &req.file_name, &req.word, req.lossy);
         let mut value = self.get_from_cache(&key).await;
         if value == FAILED {
             tracing::info!("cache missed, key: {}", key);
-            value = self.count_from_file(&req.word, &req.get_file_path()).await;
+            value = self.count_from_file(&req.word, &file_path, req.lossy).await;
             tracing::info!("count from file, [key: {}, value: {}]", key, value);
             self.set_cache(&key, value).await
         };
@@ -154,13 +181,24 @@ impl Counter for CounterService {
             log_id: "".to_string(),
         }))
     }
-}
 
-impl WordCountRequest {
-    pub fn get_file_path(&self) -> PathBuf {
-        PathBuf::from(&format!("{}/{}", env::var("TEXT_PATH").unwrap_or("../texts".to_string()), self.file_name))
+    async fn list_files(&self, request: Request<ListFilesRequest>) -> std::result::Result<Response<ListFilesResponse>, Status> {
+        let caller = request.extensions().get::<Caller>().cloned();
+        let corpus_name = request.into_inner().corpus;
+        let corpora = Arc::clone(&self.corpora);
+        let name = corpus_name.clone();
+        let file_names = tokio::task::spawn_blocking(move || corpora.get(&name).map(Corpus::list)).await
+            .map_err(|e| Status::internal(format!("list files failed: {:?}", e)))?
+            .map_err(|e| Status::new(Code::FailedPrecondition, format!("{:?}", e)))?;
+        // only the files the caller may count
+        let file_names = file_names.into_iter()
+            .filter(|file_name| caller.as_ref().is_none_or(|caller| caller.allows(&qualified_name(&corpus_name, file_name))))
+            .collect();
+        Ok(Response::new(ListFilesResponse { file_names }))
     }
+}
 
+impl WordCountRequest {
     pub fn get_file_name(&self) -> Option<&OsStr> {
         Path::new(&self.file_name).file_stem()
     }
@@ -175,9 +213,6 @@ impl WordCountRequest {
         if self.get_file_name().is_none() || self.get_file_name().unwrap().to_str().is_none() {
             return Err(anyhow!("invalid request: invalid file name: {}", self.file_name));
         }
-        if !self.get_file_path().exists() {
-            return Err(anyhow!("invalid request: file not exist: {}", self.file_name));
-        }
 
         Ok(())
     }
@@ -189,7 +224,16 @@ mod test {
 
     #[test]
     fn test_key() {
-        assert_eq!("Titanic:rose", CounterService::key("Titanic.txt", "rose"));
-        assert_eq!("Titanic:rose", CounterService::key("Titanic", "rose"));
+        assert_eq!("7:default11:Titanic.txt4:rose", CounterService::key("", "Titanic.txt", "rose", false));
+        assert_eq!("7:default10:Titanic.md4:rose", CounterService::key("default", "Titanic.md", "rose", false));
+        assert_eq!("7:default14:Titanic.txt.gz4:rose", CounterService::key("", "Titanic.txt.gz", "rose", false));
+        assert_eq!("5:books20:classics/Titanic.txt4:rose", CounterService::key("books", "classics//Titanic.txt", "rose", false));
+        assert_eq!("lossy~5:books11:Titanic.txt4:rose", CounterService::key("books", "Titanic.txt", "rose", true));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_key_with_colons() {
+        assert_ne!(CounterService::key("", "x.txt", "y.txt:rose", false), CounterService::key("", "x.txt:y.txt", "rose", false));
+        assert_ne!(CounterService::key("", "books:x.txt", "rose", false), CounterService::key("books", "x.txt", "rose", false));
+    }
+}
@@ -0,0 +1,218 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+/// The encoding of a text by its first bytes: its byte order mark, else UTF-16 when every other byte is NUL, else UTF-8
+/// when they are valid UTF-8, else Windows-1252, a superset of Latin-1. Bytes all ASCII are UTF-8 only until the first
+/// non-ASCII character of the text decides, see `detect_after_ascii`.
+pub fn detect(head: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+    if let Some(encoding) = detect_utf16(head) {
+        return encoding;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => UTF_8,
+        // a character cut at the end of the head
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+// UTF-16 without a byte order mark, by the NUL high bytes of mostly Latin text
+fn detect_utf16(head: &[u8]) -> Option<&'static Encoding> {
+    let units = head.len() / 2;
+    if units < 2 {
+        return None;
+    }
+    let even = head.iter().step_by(2).filter(|byte| **byte == 0).count();
+    let odd = head.iter().skip(1).step_by(2).filter(|byte| **byte == 0).count();
+    match (even, odd) {
+        (0, odd) if odd * 2 > units => Some(UTF_16LE),
+        (even, 0) if even * 2 > units => Some(UTF_16BE),
+        _ => None,
+    }
+}
+
+/// The encoding of a text ASCII so far by its first non-ASCII character in `bytes`: UTF-8 when that is valid UTF-8,
+/// else Windows-1252. `None` while there is none, or it is cut at their end and the next bytes decide, unless they are
+/// the `last` of the text.
+pub fn detect_after_ascii(bytes: &[u8], last: bool) -> Option<&'static Encoding> {
+    let Some(start) = first_non_ascii(bytes) else {
+        return last.then_some(UTF_8);
+    };
+    match std::str::from_utf8(&bytes[start..bytes.len().min(start + 4)]) {
+        Ok(_) => Some(UTF_8),
+        Err(e) if e.valid_up_to() > 0 => Some(UTF_8),
+        Err(e) if e.error_len().is_none() && !last => None,
+        Err(_) => Some(WINDOWS_1252),
+    }
+}
+
+// a whole file may be ASCII, blocks of it are checked at once
+fn first_non_ascii(bytes: &[u8]) -> Option<usize> {
+    const BLOCK_SIZE: usize = 4096;
+    let block = bytes.chunks(BLOCK_SIZE).position(|block| !block.is_ascii())? * BLOCK_SIZE;
+    bytes[block..].iter().position(|byte| !byte.is_ascii()).map(|position| block + position)
+}
+
+/// The whole of `content` transcoded to UTF-8 from its detected encoding.
+pub fn decode(content: &[u8], lossy: bool) -> Result<String> {
+    let mut decoder = LineDecoder::new(content, lossy);
+    decoder.decode(content, true, |_| {})?;
+    Ok(decoder.pending)
+}
+
+/// Transcodes a text read in chunks to UTF-8, line by line. Bytes invalid in its encoding fail the decoding, or are
+/// replaced by U+FFFD when `lossy`.
+pub struct LineDecoder {
+    decoder: Decoder,
+    lossy: bool,
+    // ASCII so far, read as UTF-8 until its first non-ASCII character decides
+    undecided: bool,
+    // the start of a non-ASCII character cut at the end of the last chunk, while undecided
+    cut: Vec<u8>,
+    // decoded, but not a whole line yet
+    pending: String,
+}
+
+impl LineDecoder {
+    // `head`, the first bytes of the text, are peeked to detect its encoding
+    pub fn new(head: &[u8], lossy: bool) -> Self {
+        let encoding = detect(head);
+        LineDecoder {
+            decoder: encoding.new_decoder_with_bom_removal(),
+            lossy,
+            undecided: encoding == UTF_8 && detect_after_ascii(head, false).is_none(),
+            cut: vec![],
+            pending: String::new(),
+        }
+    }
+
+    /// Decodes the next `chunk`, the last one when `last`, and passes each line it completes to `on_line`.
+    pub fn decode(&mut self, chunk: &[u8], last: bool, on_line: impl FnMut(&str)) -> Result<()> {
+        if !self.undecided {
+            return self.decode_chunk(chunk, last, on_line);
+        }
+        let chunk = match self.cut.is_empty() {
+            true => Cow::Borrowed(chunk),
+            false => Cow::Owned([std::mem::take(&mut self.cut).as_slice(), chunk].concat()),
+        };
+        match detect_after_ascii(&chunk, last) {
+            Some(encoding) => {
+                // only ASCII was decoded, the same in either encoding
+                if encoding != UTF_8 {
+                    self.decoder = encoding.new_decoder_without_bom_handling();
+                }
+                self.undecided = false;
+                self.decode_chunk(&chunk, last, on_line)
+            }
+            None => {
+                let ascii = first_non_ascii(&chunk).unwrap_or(chunk.len());
+                self.cut = chunk[ascii..].to_vec();
+                self.decode_chunk(&chunk[..ascii], false, on_line)
+            }
+        }
+    }
+
+    fn decode_chunk(&mut self, mut chunk: &[u8], last: bool, mut on_line: impl FnMut(&str)) -> Result<()> {
+        // the line pending before holds no line break, a long one is not searched again for each chunk
+        let decoded = self.pending.len();
+        loop {
+            if let Some(length) = self.decoder.max_utf8_buffer_length(chunk.len()) {
+                self.pending.reserve(length);
+            }
+            let (result, read, had_errors) = self.decoder.decode_to_string(chunk, &mut self.pending, last);
+            if had_errors && !self.lossy {
+                return Err(anyhow!("invalid {} text", self.decoder.encoding().name()));
+            }
+            chunk = &chunk[read..];
+            if result == CoderResult::InputEmpty {
+                break;
+            }
+        }
+        let complete = match last {
+            true => self.pending.len(),
+            false => self.pending[decoded..].rfind('\n').map_or(0, |newline| decoded + newline + 1),
+        };
+        self.pending[..complete].lines().for_each(&mut on_line);
+        if !last {
+            self.pending.drain(..complete);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // the encoding detected by the first bytes only, as when a file is read
+    fn lines(content: &[u8], chunk_size: usize, lossy: bool) -> Result<Vec<String>> {
+        let mut decoder = LineDecoder::new(&content[..content.len().min(8)], lossy);
+        let mut lines = vec![];
+        for chunk in content.chunks(chunk_size) {
+            decoder.decode(chunk, false, |line| lines.push(line.to_string()))?;
+        }
+        decoder.decode(&[], true, |line| lines.push(line.to_string()))?;
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"\xef\xbb\xbfrose"), UTF_8);
+        assert_eq!(detect(b"\xff\xfer\0"), UTF_16LE);
+        assert_eq!(detect(b"\xfe\xff\0r"), UTF_16BE);
+        assert_eq!(detect(b"r\0o\0s\0e\0"), UTF_16LE);
+        assert_eq!(detect(b"\0r\0o\0s\0e"), UTF_16BE);
+        assert_eq!(detect("rosé".as_bytes()), UTF_8);
+        // cut in the middle of the last `é`, after UTF-8 or ASCII
+        assert_eq!(detect(&"rosé rosé".as_bytes()[..10]), UTF_8);
+        assert_eq!(detect(&"rose rosé".as_bytes()[..9]), UTF_8);
+        assert_eq!(detect(b"ros\xe9 "), WINDOWS_1252);
+        assert_eq!(detect(b""), UTF_8);
+    }
+
+    #[test]
+    fn test_detect_after_ascii() {
+        assert_eq!(detect_after_ascii(b"rose", false), None);
+        assert_eq!(detect_after_ascii(b"rose", true), Some(UTF_8));
+        assert_eq!(detect_after_ascii("rose rosé".as_bytes(), false), Some(UTF_8));
+        assert_eq!(detect_after_ascii(b"rose ros\xe9 ", false), Some(WINDOWS_1252));
+        assert_eq!(detect_after_ascii(&"rose rosé".as_bytes()[..9], false), None);
+        assert_eq!(detect_after_ascii(&"rose rosé".as_bytes()[..9], true), Some(WINDOWS_1252));
+        assert_eq!(first_non_ascii(&[b"r".repeat(5000).as_slice(), "é".as_bytes()].concat()), Some(5000));
+    }
+
+    #[test]
+    fn test_decode() {
+        let utf16: Vec<u8> = "\u{feff}rosé\r\nand jack\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        for (content, expected) in [
+            ("rosé\nand jack".as_bytes(), ["rosé", "and jack"]),
+            (b"ros\xe9\r\nand jack\n", ["rosé", "and jack"]),
+            // ASCII in the first bytes the encoding is detected by
+            (b"rose and\nros\xe9", ["rose and", "rosé"]),
+            (b"rose and\njack ros\xc3\xa9", ["rose and", "jack rosé"]),
+            (b"\xef\xbb\xbfros\xc3\xa9\nand jack", ["rosé", "and jack"]),
+            (&utf16, ["rosé", "and jack"]),
+        ] {
+            // characters and line breaks cut across chunks
+            for chunk_size in [1, 3, 1024] {
+                assert_eq!(lines(content, chunk_size, false).unwrap(), expected, "{:?} by {}", content, chunk_size);
+            }
+        }
+        assert_eq!(decode(b"ros\xe9", false).unwrap(), "rosé");
+        assert_eq!(decode(b"ros\xc3", false).unwrap(), "ros\u{c3}");
+    }
+
+    #[test]
+    fn test_lossy() {
+        let content = b"ros\xc3\xa9\nand \xff jack\n";
+        assert!(lines(content, 4, false).is_err());
+        assert_eq!(lines(content, 4, true).unwrap(), ["rosé", "and \u{fffd} jack"]);
+        assert!(decode(b"\xff\xfer", false).is_err());
+        assert_eq!(decode(b"\xff\xfer", true).unwrap(), "\u{fffd}");
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use ego_tree::iter::Edge;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::name::LocalName;
+use quick_xml::Reader;
+use scraper::{Html, Node};
+use zip::ZipArchive;
+
+use crate::encoding::decode;
+use crate::read_counter::text_extension;
+
+// a document is parsed as a whole, the larger ones are refused rather than held in memory
+pub const MAX_DOCUMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+// elements whose content is not shown
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template"];
+// elements that start a new line, so that words of adjacent blocks are not glued together
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption", "figure", "footer",
+    "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main", "nav", "ol", "p", "pre", "section", "table",
+    "td", "th", "tr", "ul",
+];
+
+/// Extracts the text of a document format, which words are counted in instead of the raw file and its markup.
+pub trait Extractor: Send + Sync {
+    /// The text extensions the extractor is selected by, lowercase and without the dot.
+    fn extensions(&self) -> &[&'static str];
+
+    /// Whether the content is a binary container decoding its text itself, e.g. EPUB. Other contents are transcoded to
+    /// UTF-8 before extraction, like plain text.
+    fn is_binary(&self) -> bool {
+        false
+    }
+
+    /// The text of the whole `content`, lines separated by `\n`.
+    fn extract(&self, content: &[u8]) -> Result<String>;
+}
+
+/// The extractors by text extension, the one under a compression extension. Files without one are counted as plain
+/// text.
+pub struct Extractors {
+    extractors: HashMap<String, Arc<dyn Extractor>>,
+}
+
+impl Default for Extractors {
+    // the built-in HTML, Markdown and EPUB extractors
+    fn default() -> Self {
+        let mut extractors = Extractors { extractors: HashMap::new() };
+        extractors.register(Arc::new(HtmlExtractor));
+        extractors.register(Arc::new(MarkdownExtractor));
+        extractors.register(Arc::new(EpubExtractor));
+        extractors
+    }
+}
+
+impl Extractors {
+    // replaces the extractors registered before for the same extensions
+    pub fn register(&mut self, extractor: Arc<dyn Extractor>) {
+        for extension in extractor.extensions() {
+            self.extractors.insert(extension.to_string(), Arc::clone(&extractor));
+        }
+    }
+
+    pub fn get(&self, file_path: &Path) -> Option<Arc<dyn Extractor>> {
+        text_extension(file_path).and_then(|extension| self.extractors.get(&extension)).cloned()
+    }
+}
+
+pub struct HtmlExtractor;
+
+impl Extractor for HtmlExtractor {
+    fn extensions(&self) -> &[&'static str] {
+        &["html", "htm"]
+    }
+
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        let content = std::str::from_utf8(content).context("HTML document is not UTF-8")?;
+        Ok(html_text(&Html::parse_document(content)))
+    }
+}
+
+pub struct MarkdownExtractor;
+
+impl Extractor for MarkdownExtractor {
+    fn extensions(&self) -> &[&'static str] {
+        &["md", "markdown"]
+    }
+
+    // inline HTML tags are dropped, HTML blocks are reduced to their text
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        let content = std::str::from_utf8(content).context("Markdown document is not UTF-8")?;
+        let mut text = String::new();
+        let mut html = String::new();
+        for event in Parser::new_ext(content, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH) {
+            match event {
+                Event::Text(t) | Event::Code(t) => text.push_str(&t),
+                Event::Html(t) => html.push_str(&t),
+                Event::SoftBreak | Event::HardBreak | Event::Rule => text.push('\n'),
+                Event::Start(Tag::HtmlBlock) => html.clear(),
+                Event::End(TagEnd::HtmlBlock) => {
+                    text.push_str(&html_text(&Html::parse_fragment(&html)));
+                    text.push('\n');
+                }
+                Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::Item
+                           | TagEnd::TableHead | TagEnd::TableRow | TagEnd::TableCell | TagEnd::BlockQuote(_)) => {
+                    text.push('\n')
+                }
+                _ => {}
+            }
+        }
+        Ok(text)
+    }
+}
+
+pub struct EpubExtractor;
+
+impl Extractor for EpubExtractor {
+    fn extensions(&self) -> &[&'static str] {
+        &["epub"]
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+
+    // the XHTML documents of the spine, in reading order
+    fn extract(&self, content: &[u8]) -> Result<String> {
+        epub_text(content, MAX_DOCUMENT_SIZE)
+    }
+}
+
+// at most `max_size` bytes are decompressed over all entries, a spine listing a large document many times included
+fn epub_text(content: &[u8], max_size: u64) -> Result<String> {
+    let mut archive = ZipArchive::new(Cursor::new(content)).context("invalid EPUB archive")?;
+    let mut budget = max_size;
+    let container = read_entry(&mut archive, "META-INF/container.xml", &mut budget)?;
+    let package_path = xml_elements(&container, "rootfile")?.into_iter()
+        .find_map(|mut attributes| attributes.remove("full-path"))
+        .ok_or_else(|| anyhow!("EPUB container names no package document"))?;
+    let package = read_entry(&mut archive, &package_path, &mut budget)?;
+    let manifest: HashMap<String, String> = xml_elements(&package, "item")?.into_iter()
+        .filter_map(|mut attributes| Some((attributes.remove("id")?, attributes.remove("href")?)))
+        .collect();
+    let package_dir = package_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    let mut text = String::new();
+    for idref in xml_elements(&package, "itemref")?.into_iter().filter_map(|mut attributes| attributes.remove("idref")) {
+        let href = manifest.get(&idref).ok_or_else(|| anyhow!("EPUB spine item {:?} not in the manifest", idref))?;
+        let document = read_entry(&mut archive, &entry_name(package_dir, href), &mut budget)?;
+        text.push_str(&xhtml_text(&document).with_context(|| format!("invalid EPUB document: {}", href))?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+// the text of an HTML document or fragment, entities decoded by the parser
+fn html_text(html: &Html) -> String {
+    let mut text = TextBuilder::default();
+    for edge in html.tree.root().traverse() {
+        match edge {
+            Edge::Open(node) => match node.value() {
+                Node::Element(element) => text.open(element.name()),
+                Node::Text(t) => text.push(t),
+                _ => {}
+            },
+            Edge::Close(node) => {
+                if let Node::Element(element) = node.value() {
+                    text.close(element.name());
+                }
+            }
+        }
+    }
+    text.text
+}
+
+// XHTML is parsed as XML, an HTML parser would take `<title/>` for an unclosed title swallowing the body
+fn xhtml_text(document: &str) -> Result<String> {
+    let mut reader = Reader::from_str(document);
+    reader.config_mut().expand_empty_elements = true;
+    reader.config_mut().check_end_names = false;
+    let mut text = TextBuilder::default();
+    loop {
+        match reader.read_event()? {
+            XmlEvent::Start(element) => text.open(&local_name(element.local_name())),
+            XmlEvent::End(element) => text.close(&local_name(element.local_name())),
+            XmlEvent::Text(t) => text.push(&t.unescape_with(resolve_predefined_entity)?),
+            XmlEvent::CData(t) => text.push(&String::from_utf8_lossy(&t)),
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(text.text)
+}
+
+// the attributes of each `name` element of an XML document, by local name
+fn xml_elements(document: &str, name: &str) -> Result<Vec<HashMap<String, String>>> {
+    let mut reader = Reader::from_str(document);
+    let mut elements = vec![];
+    loop {
+        match reader.read_event()? {
+            XmlEvent::Start(element) | XmlEvent::Empty(element) if local_name(element.local_name()) == name => {
+                let mut attributes = HashMap::new();
+                for attribute in element.attributes() {
+                    let attribute = attribute?;
+                    attributes.insert(local_name(attribute.key.local_name()), attribute.unescape_value()?.to_string());
+                }
+                elements.push(attributes);
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(elements)
+}
+
+fn local_name(name: LocalName) -> String {
+    String::from_utf8_lossy(name.as_ref()).to_lowercase()
+}
+
+// `budget` is what is left to decompress, the entry's size is taken from it
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str, budget: &mut u64) -> Result<String> {
+    let entry = archive.by_name(name).with_context(|| format!("EPUB entry not found: {}", name))?;
+    // the declared size may lie, the read one is what counts
+    let mut content = vec![];
+    entry.take(*budget + 1).read_to_end(&mut content).with_context(|| format!("fail to read EPUB entry: {}", name))?;
+    *budget = budget.checked_sub(content.len() as u64)
+        .ok_or_else(|| anyhow!("EPUB content larger than {} bytes, at entry {}", MAX_DOCUMENT_SIZE, name))?;
+    // XHTML may be UTF-16
+    decode(&content, false).with_context(|| format!("fail to decode EPUB entry: {}", name))
+}
+
+// an `href` of the package document resolved against its directory, without the fragment
+fn entry_name(package_dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut segments: Vec<&str> = package_dir.split('/').filter(|segment| !segment.is_empty()).collect();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+// the text of the elements opened and closed in document order, but for the hidden ones
+#[derive(Default)]
+struct TextBuilder {
+    text: String,
+    // how deep inside a hidden element
+    hidden: usize,
+}
+
+impl TextBuilder {
+    fn open(&mut self, name: &str) {
+        if self.hidden > 0 || HIDDEN_ELEMENTS.contains(&name) {
+            self.hidden += 1;
+        } else if BLOCK_ELEMENTS.contains(&name) {
+            self.text.push('\n');
+        }
+    }
+
+    fn close(&mut self, name: &str) {
+        if self.hidden > 0 {
+            self.hidden -= 1;
+        } else if BLOCK_ELEMENTS.contains(&name) {
+            self.text.push('\n');
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.hidden == 0 {
+            self.text.push_str(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    fn words(text: &str) -> Vec<&str> {
+        text.split_whitespace().collect()
+    }
+
+    #[test]
+    fn test_html() {
+        let html = br#"<!DOCTYPE html><html><head><title>rose</title><style>p { color: red }</style></head>
+            <body><p>Ro<b>se</b> &amp; Jack</p><p>rose</p><script>var rose = 1;</script><br>jack&#39;s</body></html>"#;
+        let text = HtmlExtractor.extract(html).unwrap();
+        assert_eq!(words(&text), ["Rose", "&", "Jack", "rose", "jack's"]);
+        assert!(!text.contains("Jackrose"));
+        assert!(HtmlExtractor.extract(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_markdown() {
+        let markdown = b"# Rose\n\nrose **and** `jack`\n<!-- rose -->\n\n<div>\n<p>jack</p>\n</div>\n\n| a | b |\n|---|---|\n| rose | jack |\n";
+        let text = MarkdownExtractor.extract(markdown).unwrap();
+        assert_eq!(words(&text), ["Rose", "rose", "and", "jack", "jack", "a", "b", "rose", "jack"]);
+    }
+
+    fn epub(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_epub() {
+        let container = r#"<?xml version="1.0"?><container xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+            <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+        let package = r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf"><manifest>
+            <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>
+            <item id="one" href="text/../one.xhtml#start" media-type="application/xhtml+xml"/>
+            </manifest><spine><itemref idref="one"/><itemref idref="two"/></spine></package>"#;
+        let one = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title/></head><body><p>rose&nbsp;and</p><p>jack</p></body></html>"#;
+        let two = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p>the <i>end</i></p></body></html>"#;
+        let content = epub(&[
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", container),
+            ("OEBPS/content.opf", package),
+            ("OEBPS/one.xhtml", one),
+            ("OEBPS/text/two.xhtml", two),
+        ]);
+        let text = EpubExtractor.extract(&content).unwrap();
+        assert_eq!(words(&text), ["rose", "and", "jack", "the", "end"]);
+
+        let missing = epub(&[("META-INF/container.xml", container), ("OEBPS/content.opf", package)]);
+        assert!(EpubExtractor.extract(&missing).is_err());
+        assert!(EpubExtractor.extract(b"rose").is_err());
+
+        // the entries decompressed are bounded all together, not one by one
+        let size = (container.len() + package.len() + one.len() + two.len()) as u64;
+        assert!(epub_text(&content, size).is_ok());
+        assert!(epub_text(&content, size - 1).is_err());
+        let repeated = package.replace("<itemref idref=\"two\"/>", &"<itemref idref=\"two\"/>".repeat(100));
+        let content = epub(&[("META-INF/container.xml", container), ("OEBPS/content.opf", &repeated), ("OEBPS/one.xhtml", one), ("OEBPS/text/two.xhtml", two)]);
+        assert!(epub_text(&content, size * 10).is_err());
+    }
+
+    struct Upper;
+
+    impl Extractor for Upper {
+        fn extensions(&self) -> &[&'static str] {
+            &["txt", "md"]
+        }
+
+        fn extract(&self, content: &[u8]) -> Result<String> {
+            Ok(String::from_utf8_lossy(content).to_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        let mut extractors = Extractors::default();
+        assert!(extractors.get(Path::new("a/b.HTML")).is_some());
+        assert!(extractors.get(Path::new("b.md.gz")).is_some());
+        assert!(extractors.get(Path::new("b.epub")).is_some());
+        assert!(extractors.get(Path::new("b.txt")).is_none());
+        assert!(extractors.get(Path::new("html")).is_none());
+
+        extractors.register(Arc::new(Upper));
+        assert_eq!(extractors.get(Path::new("b.txt")).unwrap().extract(b"rose").unwrap(), "ROSE");
+        assert_eq!(extractors.get(Path::new("b.md")).unwrap().extract(b"# rose").unwrap(), "# ROSE");
+        assert!(extractors.get(Path::new("b.html")).is_some());
+    }
+
+    #[test]
+    fn test_entry_name() {
+        assert_eq!(entry_name("OEBPS", "text/one.xhtml#start"), "OEBPS/text/one.xhtml");
+        assert_eq!(entry_name("OEBPS/text", "../one.xhtml"), "OEBPS/one.xhtml");
+        assert_eq!(entry_name("", "./one.xhtml"), "one.xhtml");
+    }
+}
@@ -0,0 +1,135 @@
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use deadpool_redis::Pool;
+use redis::cmd;
+use tokio::task::JoinHandle;
+use tonic_health::server::HealthReporter;
+
+use crate::corpus::{Corpora, Corpus};
+use crate::counter_server::CounterService;
+use crate::counter_server::word_counter::counter_server::CounterServer;
+
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keeps the tonic-health status of the Counter service in line with what it needs to serve requests:
+/// a Redis connection from the pool and a readable text root in every corpus. The load balancer stops routing to this
+/// instance while either is unavailable.
+pub struct HealthMonitor {
+    pool: Pool,
+    corpora: Arc<Corpora>,
+    reporter: HealthReporter,
+    interval: Duration,
+}
+
+impl HealthMonitor {
+    /// `HEALTH_CHECK_INTERVAL_SECS` (default 5) sets how often the dependencies are probed.
+    pub fn from_env(pool: Pool, corpora: Arc<Corpora>, reporter: HealthReporter) -> Result<Self> {
+        let interval = match env::var("HEALTH_CHECK_INTERVAL_SECS") {
+            Ok(value) => value.parse().map_err(|_| anyhow!("invalid HEALTH_CHECK_INTERVAL_SECS: {}", value))?,
+            Err(_) => DEFAULT_INTERVAL_SECS,
+        };
+        if interval == 0 {
+            return Err(anyhow!("HEALTH_CHECK_INTERVAL_SECS should be positive"));
+        }
+        Ok(HealthMonitor { pool, corpora, reporter, interval: Duration::from_secs(interval) })
+    }
+
+    pub fn start(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            let mut serving = None;
+            loop {
+                interval.tick().await;
+                let healthy = self.probe().await;
+                if serving != Some(healthy) {
+                    self.report(healthy).await;
+                    serving = Some(healthy);
+                }
+            }
+        })
+    }
+
+    async fn probe(&self) -> bool {
+        let mut healthy = true;
+        if let Err(e) = self.probe_redis().await {
+            tracing::warn!("health probe failed, err={:?}", e);
+            healthy = false;
+        }
+        for (name, corpus) in self.corpora.iter() {
+            if let Err(e) = Self::probe_corpus(corpus).await {
+                tracing::warn!("health probe failed, corpus={}, err={:?}", name, e);
+                healthy = false;
+            }
+        }
+        healthy
+    }
+
+    // files are looked up in every root in turn, so a corpus is served while any of its roots is readable
+    async fn probe_corpus(corpus: &Corpus) -> Result<()> {
+        let mut readable = false;
+        for root in corpus.roots() {
+            match Self::probe_text_path(root).await {
+                Ok(()) => readable = true,
+                Err(e) => tracing::warn!("text root unavailable, err={:?}", e),
+            }
+        }
+        if !readable {
+            return Err(anyhow!("no readable text root in {:?}", corpus.roots()));
+        }
+        Ok(())
+    }
+
+    async fn probe_redis(&self) -> Result<()> {
+        let ping = async {
+            let mut conn = self.pool.get().await.context("get redis connection from pool failed")?;
+            cmd("PING").query_async::<()>(&mut conn).await.context("redis PING failed")
+        };
+        tokio::time::timeout(PROBE_TIMEOUT, ping).await.context("redis PING timed out")?
+    }
+
+    async fn probe_text_path(path: &Path) -> Result<()> {
+        let mut entries = tokio::fs::read_dir(path).await
+            .with_context(|| format!("TEXT_PATH {:?} is not readable", path))?;
+        entries.next_entry().await
+            .with_context(|| format!("TEXT_PATH {:?} is not readable", path))?;
+        Ok(())
+    }
+
+    async fn report(&mut self, healthy: bool) {
+        if healthy {
+            tracing::info!("CounterServer serving");
+            self.reporter.set_serving::<CounterServer<CounterService>>().await;
+        } else {
+            tracing::warn!("CounterServer not serving");
+            self.reporter.set_not_serving::<CounterServer<CounterService>>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::corpus::Corpora;
+    use crate::health::HealthMonitor;
+
+    #[tokio::test]
+    async fn test_probe_text_path() {
+        assert!(HealthMonitor::probe_text_path(Path::new("texts")).await.is_ok());
+        assert!(HealthMonitor::probe_text_path(Path::new("no_such_dir")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_corpus() {
+        let corpora = Corpora::parse("texts", "books=no_such_dir:texts,news=no_such_dir", "txt").unwrap();
+        assert!(HealthMonitor::probe_corpus(corpora.get("").unwrap()).await.is_ok());
+        // one missing root of several is not unhealthy
+        assert!(HealthMonitor::probe_corpus(corpora.get("books").unwrap()).await.is_ok());
+        assert!(HealthMonitor::probe_corpus(corpora.get("news").unwrap()).await.is_err());
+    }
+}
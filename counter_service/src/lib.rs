@@ -0,0 +1,5 @@
+//! Counting a word in a text file, shared by the service and its benchmarks.
+pub mod encoding;
+pub mod extractor;
+pub mod mapped_counter;
+pub mod read_counter;
@@ -1,18 +1,33 @@
 use std::env;
+use std::fs;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use deadpool_redis::{Config, Pool, Runtime};
-use tonic::transport::Server;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use tonic::transport::server::Router;
+use tonic_health::server::HealthReporter;
 use tracing_appender::non_blocking::WorkerGuard;
 
+use crate::auth::{AuthInterceptor, AuthPolicy};
+use crate::corpus::Corpora;
 use crate::counter_server::CounterService;
 use crate::counter_server::word_counter::counter_server::CounterServer;
+use crate::health::HealthMonitor;
+use crate::registry::Registration;
 
+mod auth;
+mod corpus;
 mod counter_server;
-mod read_counter;
+mod health;
+mod registry;
+
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -26,23 +41,114 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // init server
     let addr: SocketAddr = init_socket_addr("0.0.0.0:50051");
-    let server = init_server(pool).await;
+    let shutdown_timeout = shutdown_timeout()?;
+    let (server, health_monitor, mut health_reporter) = init_server(pool.clone())?;
+    let health_monitor = health_monitor.start();
     tracing::info!("CounterServer listening on {}", addr);
-    server.serve(addr).await.unwrap_or_else(|e| {
-        tracing::error!("CounterServer serve failed, err={:?}", e)
+
+    // register to the load balancer
+    let registration = Registration::from_env(pool)?.map(Arc::new);
+    let heartbeat = match &registration {
+        Some(registration) => {
+            match registration.register().await {
+                Ok(_) => tracing::info!("registered to load balancer registry"),
+                Err(e) => tracing::error!("register to load balancer failed, err={:?}", e),
+            }
+            Some(registration.heartbeat())
+        }
+        None => None,
+    };
+
+    // on a signal, the load balancer is told to stop routing here before the server stops accepting
+    let (draining, drain_started) = oneshot::channel();
+    let serve = server.serve_with_shutdown(addr, async move {
+        shutdown_signal().await;
+        health_monitor.abort();
+        health_reporter.set_not_serving::<CounterServer<CounterService>>().await;
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.abort();
+        }
+        if let Some(registration) = registration {
+            registration.deregister().await;
+        }
+        let _ = draining.send(());
     });
+    tokio::pin!(serve);
+
+    let served = tokio::select! {
+        served = &mut serve => served,
+        Ok(()) = drain_started => {
+            tracing::info!("CounterServer draining in-flight requests");
+            match tokio::time::timeout(shutdown_timeout, &mut serve).await {
+                Ok(served) => served,
+                Err(_) => {
+                    tracing::warn!("CounterServer drain timeout {:?} exceeded, in-flight requests cut", shutdown_timeout);
+                    Ok(())
+                }
+            }
+        }
+    };
+    served.unwrap_or_else(|e| tracing::error!("CounterServer serve failed, err={:?}", e));
+    tracing::info!("CounterServer exit");
     Ok(())
 }
 
-async fn init_server(pool: Pool) -> Router {
-    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
-    health_reporter
-        .set_serving::<CounterServer<CounterService>>()
-        .await;
-    let counter_service = CounterService::new(pool);
-    Server::builder()
+// `SHUTDOWN_TIMEOUT_SECS` (default 30) bounds how long in-flight requests may take to finish on shutdown
+fn shutdown_timeout() -> anyhow::Result<Duration> {
+    let timeout = match env::var("SHUTDOWN_TIMEOUT_SECS") {
+        Ok(value) => value.parse().map_err(|_| anyhow!("invalid SHUTDOWN_TIMEOUT_SECS: {}", value))?,
+        Err(_) => DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+    };
+    if timeout == 0 {
+        return Err(anyhow!("SHUTDOWN_TIMEOUT_SECS should be positive"));
+    }
+    Ok(Duration::from_secs(timeout))
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("register SIGTERM handler failed");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    tracing::info!("CounterServer shutting down");
+}
+
+// the Counter service reports SERVING once the health monitor finds its dependencies available,
+// the returned reporter marks it NOT_SERVING on shutdown
+fn init_server(pool: Pool) -> anyhow::Result<(Router, HealthMonitor, HealthReporter)> {
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let corpora = Arc::new(Corpora::from_env()?);
+    let health_monitor = HealthMonitor::from_env(pool.clone(), Arc::clone(&corpora), health_reporter.clone())?;
+    let counter_service = CounterService::new(pool, corpora);
+    let auth_policy = AuthPolicy::from_env()?;
+    if auth_policy.is_some() {
+        tracing::info!("CounterServer authentication enabled");
+    }
+    let mut builder = Server::builder();
+    if let Some(tls) = tls_config()? {
+        builder = builder.tls_config(tls).context("configure server TLS failed")?;
+        tracing::info!("CounterServer TLS enabled");
+    }
+    let router = builder
         .add_service(health_service)
-        .add_service(CounterServer::new(counter_service))
+        .add_service(CounterServer::with_interceptor(counter_service, AuthInterceptor::new(auth_policy)));
+    Ok((router, health_monitor, health_reporter))
+}
+
+// TLS with the certificate at `TLS_CERT_PATH` and its key at `TLS_KEY_PATH`, plaintext when no certificate is set.
+// Clients must present a certificate signed by the CA at `TLS_CLIENT_CA_PATH` when it is set (mutual TLS).
+fn tls_config() -> anyhow::Result<Option<ServerTlsConfig>> {
+    let Ok(cert_path) = env::var("TLS_CERT_PATH") else {
+        return Ok(None);
+    };
+    let key_path = env::var("TLS_KEY_PATH").map_err(|_| anyhow!("TLS_KEY_PATH is required with TLS_CERT_PATH"))?;
+    let read = |path: &str| fs::read(path).with_context(|| format!("failed to read TLS file:{:?}", path));
+    let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(read(&cert_path)?, read(&key_path)?));
+    if let Ok(client_ca_path) = env::var("TLS_CLIENT_CA_PATH") {
+        tls = tls.client_ca_root(Certificate::from_pem(read(&client_ca_path)?));
+    }
+    Ok(Some(tls))
 }
 
 fn init_logger() -> WorkerGuard {
@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use encoding_rs::UTF_8;
+use memchr::memchr;
+use memchr::memmem::Finder;
+use memmap2::Mmap;
+
+use crate::encoding::detect_after_ascii;
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+// smaller chunks cost more in tasks than they gain in parallelism
+const MIN_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Counts a word in a UTF-8 text mapped in memory, split into chunks counted in parallel on the blocking thread pool.
+/// Nothing is buffered, not even a very long line, and the count is the one of reading the text line by line.
+pub struct MappedCounter;
+
+impl MappedCounter {
+    // a word spanning lines never matches line by line, and one of replacement characters matches the bytes they
+    // replace in a lossy count, both are left to the line reader
+    pub fn supports(word: &str) -> bool {
+        !word.is_empty() && !word.contains(['\n', '\r', '\u{fffd}'])
+    }
+
+    // bytes invalid in UTF-8 fail the count, or never match when `lossy`, as they would once replaced by U+FFFD. `None`
+    // for a text ASCII in its first bytes and not UTF-8 by its first non-ASCII character, to be read line by line.
+    pub async fn count(word: &str, file_path: &Path, lossy: bool) -> Result<Option<i64>> {
+        let path = PathBuf::from(file_path);
+        let pattern = word.as_bytes().to_vec();
+        let mapped = tokio::task::spawn_blocking(move || -> Result<_> {
+            let file = File::open(&path).context(format!("fail to open file: {:?}", path))?;
+            // SAFETY: texts must not be modified in place while they are served, they are replaced by renaming a new
+            // file over them, which leaves this mapping to the old one. One truncated meanwhile would raise SIGBUS on
+            // the next read of its lost pages, killing the whole process.
+            let mmap = unsafe { Mmap::map(&file) }.context(format!("fail to map file: {:?}", path))?;
+            let parallelism = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+            let chunk_size = (mmap.len() / parallelism).max(MIN_CHUNK_SIZE);
+            let start = if mmap.starts_with(UTF8_BOM) { UTF8_BOM.len() } else { 0 };
+            if detect_after_ascii(&mmap[start..], true) != Some(UTF_8) {
+                return Ok(None);
+            }
+            let chunks = chunks(&mmap, start, &pattern, chunk_size);
+            Ok(Some((Arc::new(mmap), chunks)))
+        }).await.context("map file panicked")??;
+        let Some((mmap, chunks)) = mapped else {
+            return Ok(None);
+        };
+
+        let tasks: Vec<_> = chunks.into_iter()
+            .map(|chunk| {
+                let mmap = Arc::clone(&mmap);
+                let word = word.to_string();
+                tokio::task::spawn_blocking(move || count_chunk(&mmap, chunk, &word, lossy))
+            })
+            .collect();
+        let mut count = 0;
+        for task in tasks {
+            count += task.await.context("count chunk panicked")?.context(format!("fail to count file: {:?}", file_path))?;
+        }
+        Ok(Some(count))
+    }
+}
+
+// `data` from `start` cut about every `chunk_size` bytes, on character boundaries. A word overlapping itself, like `aa`,
+// is counted line by line without overlaps, where a match starts depends on the ones before it in its line, so its
+// chunks end at line ends. Any other word is matched at every occurrence, and may be cut anywhere.
+fn chunks(data: &[u8], start: usize, word: &[u8], chunk_size: usize) -> Vec<Range<usize>> {
+    let overlapping = (1..word.len()).any(|length| word[..length] == word[word.len() - length..]);
+    let mut chunks = vec![];
+    let mut start = start;
+    while start < data.len() {
+        let mut end = (start + chunk_size).min(data.len());
+        if overlapping {
+            end = memchr(b'\n', &data[end..]).map_or(data.len(), |newline| end + newline + 1);
+        } else {
+            // not inside a character
+            while end < data.len() && data[end] & 0xc0 == 0x80 {
+                end += 1;
+            }
+        }
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+// the matches starting in the chunk, a match straddling its end included
+fn count_chunk(data: &[u8], chunk: Range<usize>, word: &str, lossy: bool) -> Result<i64> {
+    if !lossy {
+        std::str::from_utf8(&data[chunk.clone()]).map_err(|e| anyhow!("invalid UTF-8 text at byte {}", chunk.start + e.valid_up_to()))?;
+    }
+    // a match ending past it starts before it ends, it holds no `\n` to have been cut at
+    let end = (chunk.end + word.len() - 1).min(data.len());
+    Ok(Finder::new(word).find_iter(&data[chunk.start..end]).count() as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // the count of reading the text line by line
+    fn line_count(text: &str, word: &str) -> i64 {
+        text.lines().map(|line| line.matches(word).count() as i64).sum()
+    }
+
+    fn chunked_count(text: &str, word: &str, chunk_size: usize) -> i64 {
+        chunks(text.as_bytes(), 0, word.as_bytes(), chunk_size).into_iter()
+            .map(|chunk| count_chunk(text.as_bytes(), chunk, word, false).unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_chunks() {
+        let text = "rosé and jack\naaaa\nrosé\n";
+        for chunk_size in 1..text.len() + 1 {
+            for word in ["rosé", "jack", "é", "aa", "a", "and jack\na"] {
+                let chunks = chunks(text.as_bytes(), 0, word.as_bytes(), chunk_size);
+                assert_eq!(chunks.first().map(|chunk| chunk.start), Some(0));
+                assert_eq!(chunks.last().map(|chunk| chunk.end), Some(text.len()));
+                assert!(chunks.windows(2).all(|pair| pair[0].end == pair[1].start));
+                assert!(chunks.iter().all(|chunk| text.is_char_boundary(chunk.start)), "{:?} by {}", word, chunk_size);
+                if MappedCounter::supports(word) {
+                    assert_eq!(chunked_count(text, word, chunk_size), line_count(text, word), "{:?} by {}", word, chunk_size);
+                }
+            }
+        }
+        // cut at line ends only
+        assert_eq!(chunks(b"aaaa\naaaa", 0, b"aa", 2), [0..5, 5..9]);
+        assert_eq!(chunks(b"ro rose", 3, b"ro", 2), [3..5, 5..7]);
+        assert!(chunks(b"", 0, b"ro", 2).is_empty());
+    }
+
+    #[test]
+    fn test_count_chunk() {
+        let data = b"rose \xff rose";
+        assert!(count_chunk(data, 0..data.len(), "rose", false).is_err());
+        assert_eq!(count_chunk(data, 0..data.len(), "rose", true).unwrap(), 2);
+        assert_eq!(count_chunk(data, 0..6, "rose", true).unwrap(), 1);
+        assert!(!MappedCounter::supports("rose\n"));
+        assert!(!MappedCounter::supports(""));
+    }
+
+    #[tokio::test]
+    async fn test_count() {
+        let path = std::env::temp_dir().join(format!("mapped-counter-{}.txt", std::process::id()));
+        // a single line, larger than a chunk
+        let text = format!("\u{feff}{}", "rosé and jack ".repeat(MIN_CHUNK_SIZE / 4));
+        tokio::fs::write(&path, &text).await.unwrap();
+        assert_eq!(MappedCounter::count("rosé", &path, false).await.unwrap(), Some(line_count(&text, "rosé")));
+        assert_eq!(MappedCounter::count("jack rosé", &path, false).await.unwrap(), Some(line_count(&text, "jack rosé")));
+        // Windows-1252 after the ASCII the encoding is detected by
+        tokio::fs::write(&path, [b"rose ".repeat(MIN_CHUNK_SIZE).as_slice(), b"ros\xe9"].concat()).await.unwrap();
+        assert_eq!(MappedCounter::count("rose", &path, false).await.unwrap(), None);
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
@@ -1,23 +1,294 @@
 use std::path::Path;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, ZstdDecoder};
+use encoding_rs::UTF_8;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
 
-#[derive(Default)]
-pub struct ReadCounter {}
+use crate::encoding::{self, decode, LineDecoder};
+use crate::extractor::{Extractors, MAX_DOCUMENT_SIZE};
+use crate::mapped_counter::MappedCounter;
+
+// plain UTF-8 texts from this size on are counted mapped in memory, in parallel
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+// the first bytes the encoding is detected by, as many as the line reader buffers at first
+const HEAD_SIZE: u64 = 8 * 1024;
+
+/// Counts a word line by line. Plain text is streamed, documents with an extractor, e.g. HTML, are counted in the text
+/// extracted from them rather than in their markup. Texts are transcoded to UTF-8 from their detected encoding, and
+/// large UTF-8 ones are counted by the `MappedCounter` instead, to the same count.
+pub struct ReadCounter {
+    extractors: Extractors,
+    mmap_threshold: u64,
+}
+
+/// How a text file is compressed, decompressed while it is read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    // by the extension of the file, or else by its first bytes
+    fn detect(file_path: &Path, head: &[u8]) -> Option<Self> {
+        file_path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+            .or_else(|| Self::from_magic(head))
+    }
+}
+
+// the extension of the text, under the one of its compression: `txt` for `Titanic.txt.gz`
+pub fn text_extension(file_path: &Path) -> Option<String> {
+    let extension = file_path.extension()?.to_str()?;
+    let extension = match Compression::from_extension(extension) {
+        Some(_) => Path::new(file_path.file_stem()?).extension()?.to_str()?,
+        None => extension,
+    };
+    Some(extension.to_lowercase())
+}
+
+impl Default for ReadCounter {
+    fn default() -> Self {
+        Self::new(Extractors::default(), MMAP_THRESHOLD)
+    }
+}
 
 impl ReadCounter {
-    pub(crate) async fn count(word: &str, file_path: &Path) -> Result<i64> {
+    pub fn new(extractors: Extractors, mmap_threshold: u64) -> Self {
+        ReadCounter { extractors, mmap_threshold }
+    }
+
+    // bytes invalid in the encoding of the text fail the count, or are replaced by U+FFFD when `lossy`
+    pub async fn count(&self, word: &str, file_path: &Path, lossy: bool) -> Result<i64> {
+        if self.is_mapped(word, file_path).await? {
+            if let Some(count) = MappedCounter::count(word, file_path, lossy).await? {
+                return Ok(count);
+            }
+        }
+        let mut reader = Self::open(file_path).await?;
+        let Some(extractor) = self.extractors.get(file_path) else {
+            let head = reader.fill_buf().await.context(format!("fail to read file: {:?}", file_path))?;
+            let mut decoder = LineDecoder::new(head, lossy);
+            let mut count: i64 = 0;
+            loop {
+                let chunk = reader.fill_buf().await.context("some error occur while reading file.")?;
+                let (read, last) = (chunk.len(), chunk.is_empty());
+                decoder.decode(chunk, last, |line| count += line.matches(word).count() as i64)
+                    .context(format!("fail to decode file: {:?}", file_path))?;
+                reader.consume(read);
+                if last {
+                    return Ok(count);
+                }
+            }
+        };
+
+        let mut content = vec![];
+        reader.take(MAX_DOCUMENT_SIZE + 1).read_to_end(&mut content).await
+            .context(format!("fail to read file: {:?}", file_path))?;
+        if content.len() as u64 > MAX_DOCUMENT_SIZE {
+            return Err(anyhow!("document larger than {} bytes: {:?}", MAX_DOCUMENT_SIZE, file_path));
+        }
+        // parsing is CPU bound, kept off the runtime threads
+        let text = tokio::task::spawn_blocking(move || match extractor.is_binary() {
+            true => extractor.extract(&content),
+            false => extractor.extract(decode(&content, lossy)?.as_bytes()),
+        }).await
+            .context("extractor panicked")?
+            .context(format!("fail to extract text from: {:?}", file_path))?;
+        Ok(text.lines().map(|line| line.matches(word).count() as i64).sum())
+    }
+
+    // large UTF-8 texts, neither compressed nor documents
+    async fn is_mapped(&self, word: &str, file_path: &Path) -> Result<bool> {
+        if !MappedCounter::supports(word) || self.extractors.get(file_path).is_some() {
+            return Ok(false);
+        }
         let file = File::open(file_path).await.context(format!("fail to open file: {:?}", file_path))?;
-        let reader = BufReader::new(file);
+        if file.metadata().await.context(format!("fail to read file: {:?}", file_path))?.len() < self.mmap_threshold {
+            return Ok(false);
+        }
+        let mut head = vec![];
+        file.take(HEAD_SIZE).read_to_end(&mut head).await.context(format!("fail to read file: {:?}", file_path))?;
+        Ok(Compression::detect(file_path, &head).is_none() && encoding::detect(&head) == UTF_8)
+    }
 
-        let mut count: i64 = 0;
-        let mut lines = reader.lines();
-        while let Some(line) = lines.next_line().await.context("some error occur while reading file.")? {
-            count += line.matches(word).count() as i64;
+    // decompressed if need be
+    async fn open(file_path: &Path) -> Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+        let file = File::open(file_path).await.context(format!("fail to open file: {:?}", file_path))?;
+        let mut reader = BufReader::new(file);
+        // peeked, not consumed
+        let head = reader.fill_buf().await.context(format!("fail to read file: {:?}", file_path))?;
+        // streamed through the decoder, the file is never decompressed as a whole. Archives concatenated from
+        // several compressed streams, as `cat a.gz b.gz` makes, are read to the end.
+        let reader: Box<dyn AsyncBufRead + Unpin + Send> = match Compression::detect(file_path, head) {
+            Some(Compression::Gzip) => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+            Some(Compression::Zstd) => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+            Some(Compression::Bzip2) => {
+                let mut decoder = BzDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+            None => Box::new(reader),
+        };
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use async_compression::tokio::write::{BzEncoder, GzipEncoder, ZstdEncoder};
+    use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+    use super::*;
+
+    const TEXT: &str = "rose and jack\nrose\n";
+
+    async fn compress<W: AsyncWrite + Unpin>(encoder: W) -> W {
+        compress_text(encoder, TEXT).await
+    }
+
+    async fn compress_text<W: AsyncWrite + Unpin>(mut encoder: W, text: &str) -> W {
+        encoder.write_all(text.as_bytes()).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder
+    }
+
+    #[tokio::test]
+    async fn test_count_compressed() {
+        let dir = env::temp_dir().join(format!("read-counter-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let gzip = compress(GzipEncoder::new(vec![])).await.into_inner();
+        let files = [
+            ("plain.txt", TEXT.as_bytes().to_vec()),
+            ("text.txt.gz", gzip.clone()),
+            ("text.txt.zst", compress(ZstdEncoder::new(vec![])).await.into_inner()),
+            ("text.txt.bz2", compress(BzEncoder::new(vec![])).await.into_inner()),
+            // compressed without saying so
+            ("gzip.txt", gzip.clone()),
+            ("members.txt.gz", [gzip.clone(), gzip].concat()),
+        ];
+        for (file_name, content) in files {
+            let path = dir.join(file_name);
+            tokio::fs::write(&path, content).await.unwrap();
+            let expected = if file_name == "members.txt.gz" { 4 } else { 2 };
+            assert_eq!(ReadCounter::default().count("rose", &path, false).await.unwrap(), expected, "{}", file_name);
+        }
+
+        tokio::fs::write(dir.join("corrupt.txt.gz"), TEXT).await.unwrap();
+        assert!(ReadCounter::default().count("rose", &dir.join("corrupt.txt.gz"), false).await.is_err());
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_document() {
+        let dir = env::temp_dir().join(format!("read-counter-document-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let html = "<html><head><title>rose</title></head><body><p>rose and <b>jack</b></p><p>rose</p></body></html>";
+        tokio::fs::write(dir.join("text.html"), html).await.unwrap();
+        tokio::fs::write(dir.join("text.html.gz"), compress_text(GzipEncoder::new(vec![]), html).await.into_inner()).await.unwrap();
+        tokio::fs::write(dir.join("text.md"), "# rose\n\n[jack](rose.html) and *rose*\n").await.unwrap();
+        // counted in the markup, as plain text
+        tokio::fs::write(dir.join("text.txt"), html).await.unwrap();
+
+        let counter = ReadCounter::default();
+        for (file_name, expected) in [("text.html", 2), ("text.html.gz", 2), ("text.md", 2), ("text.txt", 3)] {
+            assert_eq!(counter.count("rose", &dir.join(file_name), false).await.unwrap(), expected, "{}", file_name);
+        }
+        tokio::fs::write(dir.join("broken.html"), [0xff, 0xfe, 0xfd]).await.unwrap();
+        assert!(counter.count("rose", &dir.join("broken.html"), false).await.is_err());
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_encoded() {
+        let dir = env::temp_dir().join(format!("read-counter-encoded-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let utf16: Vec<u8> = "\u{feff}rosé and rose\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        tokio::fs::write(dir.join("latin1.txt"), b"ros\xe9 and rose\n").await.unwrap();
+        tokio::fs::write(dir.join("utf16.txt"), utf16).await.unwrap();
+        tokio::fs::write(dir.join("latin1.html"), b"<p>ros\xe9 and rose</p>").await.unwrap();
+        // UTF-8 by its first bytes, until an invalid one further on
+        let invalid = ["rosé\n".repeat(2000).as_bytes(), b"rose \xff ros\xc3\xa9\n"].concat();
+        tokio::fs::write(dir.join("invalid.txt"), invalid).await.unwrap();
+        // ASCII in the first bytes the encoding is detected by, and Windows-1252 after them
+        let late_latin1 = ["rose\n".repeat(4000).as_bytes(), b"ros\xe9\n"].concat();
+        tokio::fs::write(dir.join("late-latin1.txt"), late_latin1).await.unwrap();
+
+        let counter = ReadCounter::default();
+        for file_name in ["latin1.txt", "utf16.txt", "latin1.html", "late-latin1.txt"] {
+            assert_eq!(counter.count("rosé", &dir.join(file_name), false).await.unwrap(), 1, "{}", file_name);
         }
+        assert!(counter.count("rosé", &dir.join("invalid.txt"), false).await.is_err());
+        assert_eq!(counter.count("rosé", &dir.join("invalid.txt"), true).await.unwrap(), 2001);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_mapped() {
+        let dir = env::temp_dir().join(format!("read-counter-mapped-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("text.txt"), TEXT).await.unwrap();
+        tokio::fs::write(dir.join("latin1.txt"), b"ros\xe9 and rose\n").await.unwrap();
+        tokio::fs::write(dir.join("text.txt.gz"), compress(GzipEncoder::new(vec![])).await.into_inner()).await.unwrap();
+        // UTF-8 by its first non-ASCII character
+        let invalid = ["é rose\n".repeat(2000).as_bytes(), b"rose \xff\n"].concat();
+        tokio::fs::write(dir.join("invalid.txt"), invalid).await.unwrap();
+
+        let counter = ReadCounter::new(Extractors::default(), 0);
+        assert!(counter.is_mapped("rose", &dir.join("text.txt")).await.unwrap());
+        assert!(!counter.is_mapped("rose\n", &dir.join("text.txt")).await.unwrap());
+        assert!(!counter.is_mapped("rose", &dir.join("latin1.txt")).await.unwrap());
+        assert!(!counter.is_mapped("rose", &dir.join("text.txt.gz")).await.unwrap());
+        assert!(!ReadCounter::default().is_mapped("rose", &dir.join("text.txt")).await.unwrap());
+
+        assert_eq!(counter.count("rose", &dir.join("text.txt"), false).await.unwrap(), 2);
+        assert_eq!(counter.count("rosé", &dir.join("latin1.txt"), false).await.unwrap(), 1);
+        assert!(counter.count("rose", &dir.join("invalid.txt"), false).await.is_err());
+        assert_eq!(counter.count("rose", &dir.join("invalid.txt"), true).await.unwrap(), 2001);
+        let late_latin1 = ["rose\n".repeat(4000).as_bytes(), b"ros\xe9\n"].concat();
+        tokio::fs::write(dir.join("late-latin1.txt"), late_latin1).await.unwrap();
+        assert!(counter.is_mapped("rosé", &dir.join("late-latin1.txt")).await.unwrap());
+        assert_eq!(counter.count("rosé", &dir.join("late-latin1.txt"), false).await.unwrap(), 1);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
 
-        Ok(count)
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(Path::new("a.txt.GZ"), b""), Some(Compression::Gzip));
+        assert_eq!(Compression::detect(Path::new("a.txt"), &[0x28, 0xb5, 0x2f, 0xfd, 0]), Some(Compression::Zstd));
+        assert_eq!(Compression::detect(Path::new("a.txt"), b"BZh91AY"), Some(Compression::Bzip2));
+        assert_eq!(Compression::detect(Path::new("a.txt"), b"rose"), None);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,121 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use deadpool_redis::Pool;
+use redis::cmd;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+// shared with the load balancer
+const REGISTRY_KEY_PREFIX: &str = "word_counter:registry:";
+const DEFAULT_PORT: u16 = 50051;
+const DEFAULT_TTL_SECS: u64 = 15;
+// the load balancer skips entries weighted out of 1..=MAX_WEIGHT
+const MAX_WEIGHT: u8 = 100;
+
+/// Endpoint entry read by the load balancer, in the format of its `endpoints.toml`.
+#[derive(Debug, Serialize)]
+struct RegistryEntry {
+    name: String,
+    host: String,
+    port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<u8>,
+}
+
+/// Registers this instance in redis so the load balancer routes to it. The key expires after `ttl`
+/// unless refreshed by the heartbeat, so a crashed instance drops out on its own.
+pub struct Registration {
+    pool: Pool,
+    entry: RegistryEntry,
+    ttl: Duration,
+}
+
+impl Registration {
+    /// Registration is enabled by setting `REGISTRY_HOST` to the address the load balancer can reach this
+    /// instance at. `REGISTRY_NAME` (default: the host), `REGISTRY_PORT`, `REGISTRY_WEIGHT` and
+    /// `REGISTRY_TTL_SECS` are optional.
+    pub fn from_env(pool: Pool) -> Result<Option<Self>> {
+        let Ok(host) = env::var("REGISTRY_HOST") else {
+            return Ok(None);
+        };
+        let entry = RegistryEntry {
+            name: env::var("REGISTRY_NAME").unwrap_or_else(|_| host.clone()),
+            host,
+            port: Self::parse_env("REGISTRY_PORT")?.unwrap_or(DEFAULT_PORT),
+            weight: Self::parse_env("REGISTRY_WEIGHT")?,
+        };
+        if entry.weight.is_some_and(|weight| weight == 0 || weight > MAX_WEIGHT) {
+            return Err(anyhow!("REGISTRY_WEIGHT should be in the range 1..={}", MAX_WEIGHT));
+        }
+        let ttl = Duration::from_secs(Self::parse_env("REGISTRY_TTL_SECS")?.unwrap_or(DEFAULT_TTL_SECS));
+        if ttl.is_zero() {
+            return Err(anyhow!("REGISTRY_TTL_SECS should be positive"));
+        }
+        Ok(Some(Registration { pool, entry, ttl }))
+    }
+
+    fn parse_env<T: std::str::FromStr>(key: &str) -> Result<Option<T>> {
+        match env::var(key) {
+            Ok(value) => value.parse().map(Some).map_err(|_| anyhow!("invalid {}: {}", key, value)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("{}{}", REGISTRY_KEY_PREFIX, self.entry.name)
+    }
+
+    pub async fn register(&self) -> Result<()> {
+        let value = serde_json::to_string(&self.entry).context("serialize registry entry failed")?;
+        let mut conn = self.pool.get().await.context("get redis connection from pool failed")?;
+        cmd("SET")
+            .arg(self.key())
+            .arg(value)
+            .arg("EX")
+            .arg(self.ttl.as_secs())
+            .query_async::<()>(&mut conn)
+            .await
+            .context("set registry entry failed")
+    }
+
+    // refresh the entry well before it expires, a few missed beats are tolerated
+    pub fn heartbeat(self: &Arc<Self>) -> JoinHandle<()> {
+        let registration = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(registration.ttl / 3);
+            loop {
+                interval.tick().await;
+                registration.register().await.unwrap_or_else(|e| {
+                    tracing::error!("registry heartbeat failed, err={:?}", e)
+                });
+            }
+        })
+    }
+
+    pub async fn deregister(&self) {
+        let Ok(mut conn) = self.pool.get().await else {
+            tracing::error!("deregister failed: get redis conn failed.");
+            return;
+        };
+        cmd("DEL")
+            .arg(self.key())
+            .query_async::<()>(&mut conn)
+            .await
+            .unwrap_or_else(|e| tracing::error!("deregister failed, err={:?}", e));
+        tracing::info!("deregistered from load balancer registry: {}", self.key());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::registry::RegistryEntry;
+
+    #[test]
+    fn test_entry() {
+        let entry = RegistryEntry { name: "server4".to_string(), host: "server4".to_string(), port: 50051, weight: None };
+        assert_eq!(serde_json::to_string(&entry).unwrap(), r#"{"name":"server4","host":"server4","port":50051}"#);
+    }
+}
@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+# Generates a CA and certificates for local TLS testing into ./certs (or the directory given as $1):
+#   server.pem / server.key               counter_service, for server1..3 and localhost
+#   load_balancer.pem / load_balancer.key load balancer listener, and its client certificate towards counter_service
+#   client.pem / client.key               counter_client
+# Not for production use.
+set -euo pipefail
+
+dir="${1:-certs}"
+days=365
+mkdir -p "$dir"
+cd "$dir"
+
+openssl req -x509 -newkey rsa:2048 -nodes -days "$days" -subj "/CN=word_counter test CA" \
+  -keyout ca.key -out ca.pem 2>/dev/null
+
+issue() {
+  local name=$1 usage=$2 san=$3
+  openssl req -newkey rsa:2048 -nodes -subj "/CN=$name" -keyout "$name.key" -out "$name.csr" 2>/dev/null
+  openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial -days "$days" -out "$name.pem" \
+    -extfile <(printf "basicConstraints=CA:FALSE\nextendedKeyUsage=%s\nsubjectAltName=%s\n" "$usage" "$san") 2>/dev/null
+  rm "$name.csr"
+}
+
+issue server serverAuth \
+  "DNS:server1,DNS:server2,DNS:server3,DNS:localhost,IP:192.168.1.10,IP:192.168.1.11,IP:192.168.1.12,IP:127.0.0.1"
+issue load_balancer serverAuth,clientAuth "DNS:load_balancer,DNS:localhost,IP:127.0.0.1"
+issue client clientAuth "DNS:client"
+rm -f ca.srl
+
+echo "certificates written to $(pwd)"
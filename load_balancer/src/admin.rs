@@ -0,0 +1,311 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use warp::{Filter, Rejection, Reply};
+use warp::http::StatusCode;
+use warp::reject::Reject;
+
+use crate::AppBuilder;
+use crate::connection::ConnectionState;
+use crate::consts::{MAX_WEIGHT, STRATEGIES, WEIGHTED_ROUND_ROBIN};
+use crate::endpoint::Endpoint;
+use crate::load_balancer::LoadBalancer;
+use crate::model::load_balancer_config::LBConfig;
+
+/// Admin HTTP API served next to `/metrics`, every route requires `Authorization: Bearer <admin_token>`.
+///
+/// * `GET  /admin/endpoints` list endpoints with health, connection state, drain state, weight and in-flight requests
+/// * `POST /admin/endpoints/{name}/drain` stop routing new requests to an endpoint
+/// * `POST /admin/endpoints/{name}/undrain` route requests to a drained endpoint again
+/// * `PUT  /admin/endpoints/{name}/weight` change the weight, body `{"weight": 50}`
+/// * `GET  /admin/strategy` show the active strategy
+/// * `PUT  /admin/strategy` switch strategy, body in the format of `load_balancer.toml`, e.g. `{"strategy": "RoundRobin"}`
+pub struct AdminApi;
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Serialize)]
+struct EndpointStatus {
+    name: String,
+    addr: String,
+    healthy: bool,
+    connection: ConnectionState,
+    drained: bool,
+    weight: Option<u8>,
+    in_flight: usize,
+}
+
+#[derive(Serialize)]
+struct StrategyStatus {
+    strategy: String,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct WeightUpdate {
+    weight: u8,
+}
+
+type Lb = Arc<Box<dyn LoadBalancer>>;
+
+impl AdminApi {
+    pub fn routes(lb: Lb, token: String) -> impl Filter<Extract=(impl Reply, ), Error=Rejection> + Clone {
+        let list_endpoints = warp::path!("endpoints")
+            .and(warp::get())
+            .and(Self::with_lb(&lb))
+            .map(Self::list_endpoints);
+        let drain = warp::path!("endpoints" / String / "drain")
+            .and(warp::post())
+            .and(Self::with_lb(&lb))
+            .map(|name: String, lb: Lb| Self::set_drained(lb, &name, true));
+        let undrain = warp::path!("endpoints" / String / "undrain")
+            .and(warp::post())
+            .and(Self::with_lb(&lb))
+            .map(|name: String, lb: Lb| Self::set_drained(lb, &name, false));
+        let weight = warp::path!("endpoints" / String / "weight")
+            .and(warp::put())
+            .and(Self::with_lb(&lb))
+            .and(warp::body::json())
+            .map(|name: String, lb: Lb, update: WeightUpdate| Self::set_weight(lb, &name, update.weight));
+        let get_strategy = warp::path!("strategy")
+            .and(warp::get())
+            .and(Self::with_lb(&lb))
+            .then(Self::get_strategy);
+        let set_strategy = warp::path!("strategy")
+            .and(warp::put())
+            .and(Self::with_lb(&lb))
+            .and(warp::body::json())
+            .then(Self::set_strategy);
+
+        warp::path("admin")
+            .and(Self::authorized(Arc::new(token)))
+            .and(
+                list_endpoints
+                    .or(drain).unify()
+                    .or(undrain).unify()
+                    .or(weight).unify()
+                    .or(get_strategy).unify()
+                    .or(set_strategy).unify()
+            )
+            .recover(Self::handle_rejection)
+            .unify()
+    }
+
+    fn with_lb(lb: &Lb) -> impl Filter<Extract=(Lb, ), Error=Infallible> + Clone {
+        let lb = Arc::clone(lb);
+        warp::any().map(move || Arc::clone(&lb))
+    }
+
+    fn authorized(token: Arc<String>) -> impl Filter<Extract=(), Error=Rejection> + Clone {
+        warp::header::optional::<String>("authorization")
+            .and_then(move |header: Option<String>| {
+                let token = Arc::clone(&token);
+                async move {
+                    let provided = header.as_deref().and_then(|header| header.strip_prefix("Bearer "));
+                    match provided {
+                        Some(provided) if Self::token_eq(provided, &token) => Ok(()),
+                        _ => Err(warp::reject::custom(Unauthorized)),
+                    }
+                }
+            })
+            .untuple_one()
+    }
+
+    // compare without short-circuiting so response timing does not leak the token
+    fn token_eq(provided: &str, expected: &str) -> bool {
+        provided.len() == expected.len()
+            && provided.bytes().zip(expected.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    async fn handle_rejection(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+        if rejection.find::<Unauthorized>().is_some() {
+            return Ok(Self::error(StatusCode::UNAUTHORIZED, "missing or invalid admin token"));
+        }
+        if let Some(err) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+            return Ok(Self::error(StatusCode::BAD_REQUEST, &err.to_string()));
+        }
+        Err(rejection)
+    }
+
+    fn error(status: StatusCode, message: &str) -> warp::reply::Response {
+        let body = warp::reply::json(&ErrorMessage { message: message.to_string() });
+        warp::reply::with_status(body, status).into_response()
+    }
+
+    fn find_endpoint(lb: &Lb, name: &str) -> Option<Arc<Box<dyn Endpoint>>> {
+        lb.endpoints().into_iter().find(|endpoint| endpoint.name() == name)
+    }
+
+    fn status(endpoint: &Arc<Box<dyn Endpoint>>) -> EndpointStatus {
+        EndpointStatus {
+            name: endpoint.name(),
+            addr: endpoint.addr().to_string(),
+            healthy: endpoint.health_report(),
+            connection: endpoint.connection_state(),
+            drained: endpoint.is_drained(),
+            weight: endpoint.weight(),
+            in_flight: endpoint.in_flight(),
+        }
+    }
+
+    fn list_endpoints(lb: Lb) -> warp::reply::Response {
+        let endpoints: Vec<EndpointStatus> = lb.endpoints().iter().map(Self::status).collect();
+        warp::reply::json(&endpoints).into_response()
+    }
+
+    fn set_drained(lb: Lb, name: &str, drained: bool) -> warp::reply::Response {
+        let Some(endpoint) = Self::find_endpoint(&lb, name) else {
+            return Self::error(StatusCode::NOT_FOUND, &format!("endpoint not found: {}", name));
+        };
+        endpoint.set_drained(drained);
+        tracing::info!("[Admin] endpoint {} drained={}", name, drained);
+        warp::reply::json(&Self::status(&endpoint)).into_response()
+    }
+
+    fn set_weight(lb: Lb, name: &str, weight: u8) -> warp::reply::Response {
+        if weight == 0 || weight > MAX_WEIGHT {
+            return Self::error(StatusCode::BAD_REQUEST, &format!("weight should be in range [1, {}]", MAX_WEIGHT));
+        }
+        let Some(endpoint) = Self::find_endpoint(&lb, name) else {
+            return Self::error(StatusCode::NOT_FOUND, &format!("endpoint not found: {}", name));
+        };
+        endpoint.set_weight(Some(weight));
+        tracing::info!("[Admin] endpoint {} weight={}", name, weight);
+        warp::reply::json(&Self::status(&endpoint)).into_response()
+    }
+
+    async fn get_strategy(lb: Lb) -> warp::reply::Response {
+        warp::reply::json(&StrategyStatus { strategy: lb.strategy_name().await }).into_response()
+    }
+
+    async fn set_strategy(lb: Lb, config: LBConfig) -> warp::reply::Response {
+        let strategy = config.strategy();
+        if !STRATEGIES.contains(&strategy.as_str()) {
+            return Self::error(StatusCode::BAD_REQUEST, &format!("unknown strategy: {}, supported: {:?}", strategy, STRATEGIES));
+        }
+        if strategy == WEIGHTED_ROUND_ROBIN {
+            if let Some(endpoint) = lb.endpoints().iter().find(|endpoint| endpoint.weight().is_none()) {
+                return Self::error(StatusCode::BAD_REQUEST, &format!("endpoint {}: weight is required by {}", endpoint.name(), WEIGHTED_ROUND_ROBIN));
+            }
+        }
+        lb.set_strategy(AppBuilder::strategy(&config)).await;
+        Self::get_strategy(lb).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use crate::endpoint::MockEndpoint;
+    use crate::load_balancer::LoadBalancerImpl;
+    use crate::strategy::round_robin::RoundRobin;
+
+    use super::*;
+
+    const TOKEN: &str = "secret";
+
+    fn lb(endpoint: MockEndpoint) -> Lb {
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(endpoint))];
+        Arc::new(Box::new(LoadBalancerImpl::new(endpoints, Box::new(RoundRobin::new(None)))))
+    }
+
+    fn endpoint() -> MockEndpoint {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(|| "s1".to_string());
+        endpoint.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080));
+        endpoint.expect_health_report().returning(|| true);
+        endpoint.expect_connection_state().returning(|| ConnectionState::Connected);
+        endpoint.expect_is_drained().returning(|| false);
+        endpoint.expect_weight().returning(|| Some(10));
+        endpoint.expect_in_flight().returning(|| 2);
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized() {
+        let api = AdminApi::routes(lb(endpoint()), TOKEN.to_string());
+        let resp = warp::test::request().path("/admin/endpoints").reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = warp::test::request().path("/admin/endpoints").header("authorization", "Bearer wrong!").reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_list_endpoints() {
+        let api = AdminApi::routes(lb(endpoint()), TOKEN.to_string());
+        let resp = warp::test::request().path("/admin/endpoints").header("authorization", "Bearer secret").reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body[0]["name"], "s1");
+        assert_eq!(body[0]["addr"], "127.0.0.1:8080");
+        assert_eq!(body[0]["healthy"], true);
+        assert_eq!(body[0]["connection"], "Connected");
+        assert_eq!(body[0]["weight"], 10);
+        assert_eq!(body[0]["in_flight"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let mut endpoint = endpoint();
+        endpoint.expect_set_drained().withf(|drained| *drained).times(1).return_const(());
+        let api = AdminApi::routes(lb(endpoint), TOKEN.to_string());
+        let resp = warp::test::request().method("POST").path("/admin/endpoints/s1/drain").header("authorization", "Bearer secret").reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = warp::test::request().method("POST").path("/admin/endpoints/s9/drain").header("authorization", "Bearer secret").reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_set_weight() {
+        let mut endpoint = endpoint();
+        endpoint.expect_set_weight().withf(|weight| *weight == Some(50)).times(1).return_const(());
+        let api = AdminApi::routes(lb(endpoint), TOKEN.to_string());
+        let resp = warp::test::request().method("PUT").path("/admin/endpoints/s1/weight").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"weight": 50})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = warp::test::request().method("PUT").path("/admin/endpoints/s1/weight").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"weight": 0})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_set_strategy() {
+        let api = AdminApi::routes(lb(endpoint()), TOKEN.to_string());
+        let resp = warp::test::request().method("PUT").path("/admin/strategy").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"strategy": "HashByRequest"})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["strategy"], "HashByRequest");
+
+        let resp = warp::test::request().method("PUT").path("/admin/strategy").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"strategy": "Random"})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = warp::test::request().method("PUT").path("/admin/strategy").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"strategy": "WeightedRoundRobin"})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_set_strategy_without_weight() {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(|| "s1".to_string());
+        endpoint.expect_weight().returning(|| None);
+        let lb = lb(endpoint);
+        let api = AdminApi::routes(lb.clone(), TOKEN.to_string());
+        let resp = warp::test::request().method("PUT").path("/admin/strategy").header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"strategy": "WeightedRoundRobin"})).reply(&api).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(resp.body()).contains("s1"));
+        assert_eq!(lb.strategy_name().await, "RoundRobin");
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use allowlist::{matches, qualified_name};
+
+use crate::consts::{AUTH_FORBIDDEN, AUTH_UNAUTHENTICATED};
+use crate::metrics::AuthCounter;
+use crate::model::auth_config::AuthConfig;
+
+/// Authenticates requests by the `api_key` of their JSON body and authorizes the file they query,
+/// following the tenants of `auth.toml`.
+pub struct Authenticator {
+    tenants: HashMap<String, Arc<Tenant>>,
+}
+
+#[derive(Debug)]
+pub struct Tenant {
+    name: String,
+    files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthenticated,
+    Forbidden(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated => write!(f, "missing or unknown api key"),
+            AuthError::Forbidden(file_name) => write!(f, "file {:?} not allowed", file_name),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl Tenant {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn allows(&self, file_name: &str) -> bool {
+        self.files.iter().any(|pattern| matches(pattern, file_name))
+    }
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        let mut tenants = HashMap::new();
+        for tenant_config in config.tenants() {
+            let tenant = Arc::new(Tenant { name: tenant_config.name().to_string(), files: tenant_config.files().to_vec() });
+            for key in tenant_config.api_keys() {
+                tenants.insert(key.clone(), Arc::clone(&tenant));
+            }
+        }
+        Authenticator { tenants }
+    }
+
+    // Takes the `api_key` out of the request, so it is neither logged nor forwarded, and returns the request
+    // left with the tenant allowed to query its file.
+    pub fn authorize(&self, req: &str) -> Result<(String, Arc<Tenant>), AuthError> {
+        let result = self.try_authorize(req);
+        if let Err(err) = &result {
+            AuthCounter::rejected(match err {
+                AuthError::Unauthenticated => AUTH_UNAUTHENTICATED,
+                AuthError::Forbidden(_) => AUTH_FORBIDDEN,
+            });
+        }
+        result
+    }
+
+    fn try_authorize(&self, req: &str) -> Result<(String, Arc<Tenant>), AuthError> {
+        let Ok(serde_json::Value::Object(mut fields)) = serde_json::from_str(req) else {
+            return Err(AuthError::Unauthenticated);
+        };
+        let tenant = fields.remove("api_key")
+            .and_then(|key| key.as_str().and_then(|key| self.tenants.get(key)).cloned())
+            .ok_or(AuthError::Unauthenticated)?;
+        let field = |name: &str| fields.get(name).and_then(|value| value.as_str()).unwrap_or_default();
+        let file_name = qualified_name(field("corpus"), field("file_name"));
+        if !tenant.allows(&file_name) {
+            return Err(AuthError::Forbidden(file_name));
+        }
+        let req = serde_json::Value::Object(fields).to_string();
+        Ok((req, tenant))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let authenticator = Authenticator::new(&AuthConfig::load(Path::new("src/config_test/auth_test.toml")).unwrap());
+        let (req, tenant) = authenticator.authorize(r#"{"word":"a","file_name":"acme/q1.txt","api_key":"acme-key-2"}"#).unwrap();
+        assert_eq!(tenant.name(), "acme");
+        assert_eq!(req, r#"{"file_name":"acme/q1.txt","word":"a"}"#);
+
+        let err = authenticator.authorize(r#"{"word":"a","file_name":"other.txt","api_key":"acme-key-1"}"#).unwrap_err();
+        assert!(matches!(err, AuthError::Forbidden(file_name) if file_name == "other.txt"));
+        // allowlists name files of other corpora with the corpus
+        let req = r#"{"word":"a","file_name":"q1.txt","corpus":"acme","api_key":"acme-key-1"}"#;
+        assert!(authenticator.authorize(req).is_ok());
+        let req = r#"{"word":"a","file_name":"text1.txt","corpus":"books","api_key":"acme-key-1"}"#;
+        assert!(matches!(authenticator.authorize(req), Err(AuthError::Forbidden(file_name)) if file_name == "books:text1.txt"));
+        // `*` covers the default corpus only
+        assert!(authenticator.authorize(r#"{"word":"a","file_name":"text1.txt","api_key":"admin-key"}"#).is_ok());
+        let req = r#"{"word":"a","file_name":"text1.txt","corpus":"books","api_key":"admin-key"}"#;
+        assert!(matches!(authenticator.authorize(req), Err(AuthError::Forbidden(_))));
+        // service keys are for counter_service only
+        for req in [r#"{"word":"a","file_name":"text1.txt","api_key":"lb-key"}"#, r#"{"word":"a","file_name":"text1.txt"}"#, "not json"] {
+            assert!(matches!(authenticator.authorize(req), Err(AuthError::Unauthenticated)));
+        }
+    }
+}
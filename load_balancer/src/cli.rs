@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser};
+
+use crate::consts::{CONFIG_PATH_ENDPOINTS, CONFIG_PATH_LOAD_BALANCER, CONFIG_PATH_SERVER};
+use crate::model::endpoints_config::EndpointPoolConfig;
+use crate::model::load_balancer_config::{LBConfig, LBOverrides};
+use crate::model::server_config::{ServerConfig, ServerOverrides};
+use crate::model::unified_config::UnifiedConfig;
+
+/// Load balancer of the word counter service.
+///
+/// Every option can also be set by the environment variable shown next to it. A value is taken from,
+/// highest precedence first: the command line, the environment, the config file, the built-in default.
+#[derive(Debug, Parser, Clone)]
+#[command(version)]
+pub struct Cli {
+    /// Validate the config files, print the effective config and exit
+    #[arg(long)]
+    pub check_config: bool,
+    #[command(flatten)]
+    pub paths: ConfigPaths,
+    #[command(flatten, next_help_heading = "server.toml overrides")]
+    pub server: ServerOverrides,
+    #[command(flatten, next_help_heading = "load_balancer.toml overrides")]
+    pub lb: LBOverrides,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ConfigPaths {
+    /// Path of a single config file, used instead of the three files below
+    #[arg(long, env = "LB_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Profile of the single config file to apply, e.g. dev, docker or test
+    #[arg(long, env = "LB_PROFILE", requires = "config")]
+    pub profile: Option<String>,
+    /// Path of server.toml
+    #[arg(long, env = "LB_SERVER_CONFIG", default_value = CONFIG_PATH_SERVER)]
+    pub server_config: PathBuf,
+    /// Path of load_balancer.toml
+    #[arg(long, env = "LB_LOAD_BALANCER_CONFIG", default_value = CONFIG_PATH_LOAD_BALANCER)]
+    pub load_balancer_config: PathBuf,
+    /// Path of endpoints.toml
+    #[arg(long, env = "LB_ENDPOINTS_CONFIG", default_value = CONFIG_PATH_ENDPOINTS)]
+    pub endpoints_config: PathBuf,
+}
+
+impl Cli {
+    pub fn server_config(&self) -> Result<ServerConfig> {
+        match self.unified_config()? {
+            Some(config) => config.server_config(&self.server),
+            None => ServerConfig::load(&self.paths.server_config, &self.server),
+        }
+    }
+
+    pub fn lb_config(&self) -> Result<LBConfig> {
+        match self.unified_config()? {
+            Some(config) => config.lb_config(&self.lb),
+            None => LBConfig::load(&self.paths.load_balancer_config, &self.lb),
+        }
+    }
+
+    pub fn pool_config(&self, strategy: &str) -> Result<EndpointPoolConfig> {
+        match self.unified_config()? {
+            Some(config) => config.pool_config(strategy),
+            None => EndpointPoolConfig::load(&self.paths.endpoints_config, strategy),
+        }
+    }
+
+    // files that hold the endpoints and strategy, watched by the hot reload
+    pub fn reloadable_files(&self) -> Vec<PathBuf> {
+        let Some(path) = &self.paths.config else {
+            return vec![self.paths.endpoints_config.clone(), self.paths.load_balancer_config.clone()];
+        };
+        // includes are only known once the file parses
+        match self.unified_config() {
+            Ok(Some(config)) => config.files().to_vec(),
+            _ => vec![path.clone()],
+        }
+    }
+
+    // describes where the effective config comes from, for the startup log
+    pub fn config_source(&self, path: &Path) -> String {
+        match (&self.paths.config, &self.paths.profile) {
+            (Some(config), Some(profile)) => format!("{:?} (profile {})", config, profile),
+            (Some(config), None) => format!("{:?}", config),
+            _ => format!("{:?}", path),
+        }
+    }
+
+    fn unified_config(&self) -> Result<Option<UnifiedConfig>> {
+        let Some(path) = &self.paths.config else {
+            return Ok(None);
+        };
+        UnifiedConfig::load(path, self.paths.profile.as_deref())
+            .context("load config failed")
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::try_parse_from([
+            "load_balancer",
+            "--server-config", "src/config_test/server_test.toml",
+            "--load-balancer-config", "src/config_test/load_balancer_test.toml",
+            "--port", "9000",
+            "--strategy", "StickySession",
+        ]).unwrap();
+        assert_eq!(cli.paths.endpoints_config, PathBuf::from(CONFIG_PATH_ENDPOINTS));
+        assert_eq!(cli.server_config().unwrap().port(), 9000);
+        assert_eq!(cli.lb_config().unwrap().strategy(), "StickySession");
+
+        assert!(Cli::try_parse_from(["load_balancer", "--strategy", "LeastConn"]).is_err());
+        assert!(Cli::try_parse_from(["load_balancer", "--profile", "test"]).is_err());
+        assert!(Cli::try_parse_from(["load_balancer", "--sticky-session-ttl-secs", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_unified() {
+        let cli = Cli::try_parse_from([
+            "load_balancer",
+            "--config", "src/config_test/unified_test.toml",
+            "--profile", "test",
+            "--metrics-port", "9001",
+        ]).unwrap();
+        let server_config = cli.server_config().unwrap();
+        assert_eq!(server_config.port(), 18080);
+        assert_eq!(server_config.metrics_port(), 9001);
+        assert_eq!(cli.lb_config().unwrap().strategy(), "RoundRobin");
+        assert_eq!(cli.reloadable_files().len(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::consts::{AIMD_DECREASE_FACTOR, SHED_QUEUE_FULL, SHED_QUEUE_TIMEOUT};
+use crate::metrics::ConcurrencyMetrics;
+use crate::model::server_config::ConcurrencyConfig;
+
+/// Bounds the requests processed at once. Requests over the limit wait in a bounded queue, and are shed
+/// with "server busy" when the queue is full or they waited `queue_timeout`.
+///
+/// With a latency target the limit is adaptive (AIMD): every request finishing within the target raises it
+/// by `1 / limit`, i.e. by about one per limit's worth of requests, every slower one cuts it by
+/// `AIMD_DECREASE_FACTOR`, always within `min_concurrent_requests..=max_concurrent_requests`.
+pub struct ConcurrencyLimiter {
+    config: ConcurrencyConfig,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+struct State {
+    limit: f64,
+    in_flight: usize,
+    queued: usize,
+}
+
+/// A request was shed by a concurrency limit.
+#[derive(Debug)]
+pub struct ServerBusy(pub &'static str);
+
+impl fmt::Display for ServerBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "server busy: {}", self.0)
+    }
+}
+
+impl std::error::Error for ServerBusy {}
+
+pub struct Permit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    started: Instant,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: ConcurrencyConfig) -> Self {
+        let limit = config.max_concurrent_requests() as f64;
+        ConcurrencyMetrics::limit(limit as i64);
+        ConcurrencyLimiter { config, state: Mutex::new(State { limit, in_flight: 0, queued: 0 }), released: Notify::new() }
+    }
+
+    pub async fn acquire(&self) -> Result<Permit<'_>, ServerBusy> {
+        let deadline = tokio::time::Instant::now() + self.config.queue_timeout();
+        let mut queued = false;
+        loop {
+            {
+                let mut state = self.lock();
+                if (state.in_flight as f64) < state.limit.floor() {
+                    state.in_flight += 1;
+                    if queued {
+                        state.queued -= 1;
+                        ConcurrencyMetrics::queue_depth(state.queued);
+                    }
+                    return Ok(Permit { limiter: self, started: Instant::now() });
+                }
+                if !queued {
+                    if state.queued >= self.config.max_queue() {
+                        ConcurrencyMetrics::shed(SHED_QUEUE_FULL);
+                        return Err(ServerBusy(SHED_QUEUE_FULL));
+                    }
+                    state.queued += 1;
+                    queued = true;
+                    ConcurrencyMetrics::queue_depth(state.queued);
+                }
+            }
+            // a release before this wait is not lost, Notify keeps one wakeup
+            if tokio::time::timeout_at(deadline, self.released.notified()).await.is_err() {
+                let mut state = self.lock();
+                state.queued -= 1;
+                ConcurrencyMetrics::queue_depth(state.queued);
+                ConcurrencyMetrics::shed(SHED_QUEUE_TIMEOUT);
+                return Err(ServerBusy(SHED_QUEUE_TIMEOUT));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn limit(&self) -> usize {
+        self.lock().limit.floor() as usize
+    }
+
+    fn release(&self, latency: Duration) {
+        let mut state = self.lock();
+        state.in_flight -= 1;
+        if let Some(target) = self.config.latency_target() {
+            let (min, max) = (self.config.min_concurrent_requests() as f64, self.config.max_concurrent_requests() as f64);
+            let limit = if latency <= target {
+                state.limit + 1.0 / state.limit
+            } else {
+                state.limit * AIMD_DECREASE_FACTOR
+            };
+            let limit = limit.clamp(min, max);
+            if limit.floor() != state.limit.floor() {
+                ConcurrencyMetrics::limit(limit.floor() as i64);
+            }
+            state.limit = limit;
+        }
+        drop(state);
+        self.released.notify_one();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release(self.started.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limiter(config: &str) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::new(toml::from_str(config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let limiter = limiter("max_concurrent_requests = 1\nmax_queue = 1\nqueue_timeout_ms = 50");
+        let permit = limiter.acquire().await.unwrap();
+        // the second request waits, the third finds the queue full
+        let (queued, full) = tokio::join!(limiter.acquire(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            limiter.acquire().await.err().map(|busy| busy.0)
+        });
+        assert_eq!(queued.err().map(|busy| busy.0), Some(SHED_QUEUE_TIMEOUT));
+        assert_eq!(full, Some(SHED_QUEUE_FULL));
+
+        // a released permit goes to the waiting request
+        let (queued, _) = tokio::join!(limiter.acquire(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(permit)
+        });
+        assert!(queued.is_ok());
+    }
+
+    #[test]
+    fn test_aimd() {
+        let limiter = limiter("max_concurrent_requests = 10\nmin_concurrent_requests = 2\nlatency_target_ms = 1");
+        assert_eq!(limiter.limit(), 10);
+        // slow requests cut the limit, down to the minimum
+        for _ in 0..20 {
+            limiter.lock().in_flight += 1;
+            limiter.release(Duration::from_millis(5));
+        }
+        assert_eq!(limiter.limit(), 2);
+        // fast ones raise it again, up to the maximum
+        for _ in 0..100 {
+            limiter.lock().in_flight += 1;
+            limiter.release(Duration::ZERO);
+        }
+        assert_eq!(limiter.limit(), 10);
+    }
+}
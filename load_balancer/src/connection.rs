@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::consts::{RECONNECT_BACKOFF_MAX, RECONNECT_BACKOFF_MIN};
+use crate::metrics::EndpointGauge;
+
+/// Transport-level state of an endpoint, tracked apart from its health: a connected server may still
+/// report NOT_SERVING, and an unreachable one is unhealthy without ever answering a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[repr(u8)]
+pub enum ConnectionState {
+    Disconnected = 0,
+    Connecting = 1,
+    Connected = 2,
+}
+
+impl From<u8> for ConnectionState {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ConnectionState::Connecting,
+            2 => ConnectionState::Connected,
+            _ => ConnectionState::Disconnected,
+        }
+    }
+}
+
+/// Connection state of one endpoint with exponential backoff between reconnect attempts.
+pub struct Connection {
+    server_name: String,
+    state: AtomicU8,
+    backoff: Mutex<Backoff>,
+}
+
+struct Backoff {
+    delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Connection {
+    pub fn new(server_name: &str) -> Self {
+        let connection = Connection {
+            server_name: server_name.to_string(),
+            state: AtomicU8::new(ConnectionState::Connecting as u8),
+            backoff: Mutex::new(Backoff { delay: RECONNECT_BACKOFF_MIN, retry_at: None }),
+        };
+        EndpointGauge::connection_state(server_name, ConnectionState::Connecting);
+        connection
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        ConnectionState::from(self.state.load(Ordering::SeqCst))
+    }
+
+    // whether a (re)connect attempt is allowed now, always true unless disconnected
+    pub fn attempt_due(&self) -> bool {
+        let backoff = self.backoff.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        backoff.retry_at.is_none_or(|retry_at| Instant::now() >= retry_at)
+    }
+
+    pub fn mark_connected(&self) {
+        let mut backoff = self.backoff.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        backoff.delay = RECONNECT_BACKOFF_MIN;
+        backoff.retry_at = None;
+        self.set_state(ConnectionState::Connected);
+    }
+
+    // requests failing at once over the same lost connection count as a single attempt
+    pub fn mark_disconnected(&self) {
+        let mut backoff = self.backoff.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if backoff.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return;
+        }
+        backoff.retry_at = Some(Instant::now() + backoff.delay);
+        tracing::warn!("[Connection] server {} disconnected, retry in {:?}", self.server_name, backoff.delay);
+        backoff.delay = (backoff.delay * 2).min(RECONNECT_BACKOFF_MAX);
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+    // the delay of the next backoff
+    #[cfg(test)]
+    pub fn delay(&self) -> Duration {
+        self.backoff.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).delay
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous = ConnectionState::from(self.state.swap(state as u8, Ordering::SeqCst));
+        if previous != state {
+            tracing::info!("[Connection] server {} connection state {:?} -> {:?}", self.server_name, previous, state);
+            EndpointGauge::connection_state(&self.server_name, state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let connection = Connection::new("s1");
+        assert_eq!(connection.state(), ConnectionState::Connecting);
+        assert!(connection.attempt_due());
+
+        connection.mark_disconnected();
+        assert_eq!(connection.state(), ConnectionState::Disconnected);
+        assert!(!connection.attempt_due());
+        thread::sleep(RECONNECT_BACKOFF_MIN);
+        assert!(connection.attempt_due());
+
+        // the delay doubles on every failed attempt, up to the max
+        connection.mark_disconnected();
+        assert_eq!(connection.backoff.lock().unwrap().delay, RECONNECT_BACKOFF_MIN * 4);
+        // not again for failures while the backoff runs
+        connection.mark_disconnected();
+        assert_eq!(connection.backoff.lock().unwrap().delay, RECONNECT_BACKOFF_MIN * 4);
+        for _ in 0..32 {
+            connection.backoff.lock().unwrap().retry_at = None;
+            connection.mark_disconnected();
+        }
+        assert_eq!(connection.backoff.lock().unwrap().delay, RECONNECT_BACKOFF_MAX);
+
+        connection.mark_connected();
+        assert_eq!(connection.state(), ConnectionState::Connected);
+        assert!(connection.attempt_due());
+        assert_eq!(connection.backoff.lock().unwrap().delay, RECONNECT_BACKOFF_MIN);
+    }
+}
@@ -5,13 +5,86 @@ use std::time::Duration;
 pub const DEFAULT_IP_ADDR: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
 pub const DEFAULT_PORT: u16 = 8080;
 pub const DEFAULT_METRICS_PORT: u16 = 8081;
-pub const HEALTH_CHECK_INTERVAL_MS: Duration = Duration::from_millis(500);
+// how long in-flight requests may take to finish on shutdown
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+// a client connecting to the TLS listener must complete its handshake within this time
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// largest request body read from a client, the length prefix of a larger one is not trusted to allocate
+pub const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+pub const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(100);
+pub const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+// endpoint connection defaults, overridable in endpoints.toml
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_millis(50);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(8);
+// Avoid blocking load balancer health checks due to slow/unhealthy instances.
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_millis(100);
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+// largest flow-control window allowed by HTTP/2
+pub const MAX_HTTP2_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+// concurrency limiting
+pub const DEFAULT_QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+pub const AIMD_DECREASE_FACTOR: f64 = 0.9;
+// `reason` label of the shed counter
+pub const SHED_QUEUE_FULL: &str = "queue_full";
+pub const SHED_QUEUE_TIMEOUT: &str = "queue_timeout";
+pub const SHED_ENDPOINTS_BUSY: &str = "endpoints_busy";
+
+// request hedging
+pub const DEFAULT_HEDGE_BUDGET_PERCENT: u32 = 10;
+// hedges saved up while requests are fast, caps a burst of hedges
+pub const HEDGE_BUDGET_MAX: f64 = 10.0;
+// recent latencies the hedge delay percentile is taken from
+pub const HEDGE_LATENCY_WINDOW: usize = 1000;
+// the percentile is first taken, and then refreshed, after this many latencies
+pub const HEDGE_PERCENTILE_REFRESH: usize = 100;
+// `outcome` label of the hedged requests counter
+pub const HEDGE_SENT: &str = "sent";
+pub const HEDGE_WON: &str = "won";
+pub const HEDGE_BUDGET_EXHAUSTED: &str = "budget_exhausted";
+
+// rate limiting
+pub const RATE_LIMIT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+// `status_code` of WordCountResponse, 0 is success
+pub const STATUS_FAILED: i64 = -1;
+pub const STATUS_UNAUTHORIZED: i64 = 401;
+pub const STATUS_FORBIDDEN: i64 = 403;
+pub const STATUS_RATE_LIMITED: i64 = 429;
+pub const STATUS_SERVER_BUSY: i64 = 503;
+
+// authentication
+// gRPC metadata sent to counter_service, shared with it
+pub const METADATA_API_KEY: &str = "x-api-key";
+pub const METADATA_TENANT: &str = "x-tenant";
+// `reason` label of the auth rejected counter
+pub const AUTH_UNAUTHENTICATED: &str = "unauthenticated";
+pub const AUTH_FORBIDDEN: &str = "forbidden";
+
+// health check defaults, overridable in endpoints.toml
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_HEALTH_CHECK_RISE: u32 = 2;
+pub const DEFAULT_HEALTH_CHECK_FALL: u32 = 3;
+
+// discovery
+pub const DEFAULT_DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+// shared with counter_service
+pub const REGISTRY_KEY_PREFIX: &str = "word_counter:registry:";
+
+// hot reload
+pub const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+pub const ENDPOINT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 // strategy
 pub const DEFAULT_STRATEGY: &str = "RoundRobin";
 pub const ROUND_ROBIN: &str = "RoundRobin";
 pub const WEIGHTED_ROUND_ROBIN: &str = "WeightedRoundRobin";
 pub const HASH_BY_REQUEST: &str = "HashByRequest";
+pub const STICKY_SESSION: &str = "StickySession";
+pub const DEFAULT_STICKY_SESSION_TTL: Duration = Duration::from_secs(300);
+pub const STRATEGIES: &[&str] = &[ROUND_ROBIN, WEIGHTED_ROUND_ROBIN, HASH_BY_REQUEST, STICKY_SESSION];
+pub const MAX_WEIGHT: u8 = 100;
 
 // config files
 pub const CONFIG_PATH_ENDPOINTS: &str = "src/config/endpoints.toml";
@@ -20,4 +93,15 @@ pub const CONFIG_PATH_SERVER: &str = "src/config/server.toml";
 
 // metrics
 pub const COUNTER_QUERY: &str = "query";
-pub const COUNTER_LATENCY: &str = "latency";
\ No newline at end of file
+pub const COUNTER_LATENCY: &str = "latency";
+pub const GAUGE_CONNECTION_STATE: &str = "connection_state";
+pub const GAUGE_HEALTH: &str = "health";
+pub const GAUGE_PANIC_MODE: &str = "panic_mode";
+pub const HISTOGRAM_HEALTH_CHECK_DURATION: &str = "health_check_duration";
+pub const COUNTER_HEALTH_CHECK_MISSED_INTERVALS: &str = "health_check_missed_intervals";
+pub const COUNTER_RATE_LIMITED: &str = "rate_limited";
+pub const COUNTER_SHED: &str = "shed";
+pub const GAUGE_QUEUE_DEPTH: &str = "queue_depth";
+pub const GAUGE_CONCURRENCY_LIMIT: &str = "concurrency_limit";
+pub const COUNTER_HEDGED: &str = "hedged_requests";
+pub const COUNTER_AUTH_REJECTED: &str = "auth_rejected";
\ No newline at end of file
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use mockall::automock;
+
+use crate::model::endpoints_config::{DiscoveryConfig, EndpointConfig};
+
+#[automock]
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    // A and AAAA records of a host
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>>;
+    // (target host, port) of each SRV record
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<(String, u16)>>;
+}
+
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsResolver {
+    pub fn new(config: &DiscoveryConfig) -> Result<Self> {
+        let resolver = match config.nameserver() {
+            Some(nameserver) => {
+                let nameservers = NameServerConfigGroup::from_ips_clear(&[nameserver.ip()], nameserver.port(), true);
+                TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], nameservers), ResolverOpts::default())
+            }
+            None => TokioAsyncResolver::tokio_from_system_conf().context("read system resolver config failed")?,
+        };
+        Ok(DnsResolver { resolver })
+    }
+}
+
+#[async_trait]
+impl Resolver for DnsResolver {
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let lookup = self.resolver.lookup_ip(host).await
+            .with_context(|| format!("lookup ip of {} failed", host))?;
+        Ok(lookup.iter().collect())
+    }
+
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<(String, u16)>> {
+        let lookup = self.resolver.srv_lookup(name).await
+            .with_context(|| format!("lookup srv of {} failed", name))?;
+        Ok(lookup.iter().map(|srv| (srv.target().to_utf8(), srv.port())).collect())
+    }
+}
+
+/// Expands endpoint configs addressed by `host` or `srv` into one concrete endpoint per address.
+pub struct Discovery {
+    resolver: Box<dyn Resolver>,
+}
+
+impl Discovery {
+    pub fn new(config: &DiscoveryConfig) -> Result<Self> {
+        Ok(Self::with_resolver(Box::new(DnsResolver::new(config)?)))
+    }
+
+    pub fn with_resolver(resolver: Box<dyn Resolver>) -> Self {
+        Discovery { resolver }
+    }
+
+    pub async fn resolve(&self, config: &EndpointConfig) -> Result<Vec<EndpointConfig>> {
+        if config.ip().is_some() {
+            config.port().ok_or_else(|| anyhow!("endpoint {}: port is required", config.name()))?;
+            return Ok(vec![config.clone()]);
+        }
+
+        let mut addrs = BTreeSet::new();
+        if let Some(srv) = config.srv() {
+            for (target, port) in self.resolver.lookup_srv(srv).await? {
+                for ip in self.resolver.lookup_ip(&target).await? {
+                    addrs.insert(SocketAddr::new(ip, port));
+                }
+            }
+        } else if let Some(host) = config.host() {
+            let port = config.port().ok_or_else(|| anyhow!("endpoint {}: port is required", config.name()))?;
+            for ip in self.resolver.lookup_ip(host).await? {
+                addrs.insert(SocketAddr::new(ip, port));
+            }
+        } else {
+            return Err(anyhow!("endpoint {}: one of ip, host or srv is required", config.name()));
+        }
+
+        if addrs.is_empty() {
+            return Err(anyhow!("endpoint {}: no address resolved", config.name()));
+        }
+        Ok(addrs.into_iter().map(|addr| config.resolved(addr)).collect())
+    }
+
+    // endpoints that fail to resolve are skipped, they are added by a later refresh
+    pub async fn resolve_all(&self, configs: Vec<EndpointConfig>) -> Vec<EndpointConfig> {
+        let mut resolved = vec![];
+        for config in configs {
+            match self.resolve(&config).await {
+                Ok(configs) => resolved.extend(configs),
+                Err(err) => tracing::error!(?err, "[Discovery] resolve endpoint failed"),
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::consts::DEFAULT_STRATEGY;
+    use crate::model::endpoints_config::EndpointPoolConfig;
+
+    use super::*;
+
+    fn configs() -> Vec<EndpointConfig> {
+        EndpointPoolConfig::load(Path::new("src/config_test/endpoints_discovery_test.toml"), DEFAULT_STRATEGY)
+            .unwrap()
+            .endpoint_configs()
+    }
+
+    fn stub_resolver() -> MockResolver {
+        let mut resolver = MockResolver::new();
+        resolver.expect_lookup_ip().returning(|host| match host {
+            "counter.local" => Ok(vec!["10.0.0.2".parse().unwrap(), "10.0.0.1".parse().unwrap()]),
+            "a.counter.local." => Ok(vec!["10.0.1.1".parse().unwrap()]),
+            "b.counter.local." => Ok(vec!["fd00::1".parse().unwrap()]),
+            _ => Err(anyhow!("NXDOMAIN")),
+        });
+        resolver.expect_lookup_srv().returning(|name| match name {
+            "_grpc._tcp.counter.local" => Ok(vec![("a.counter.local.".to_string(), 50051), ("b.counter.local.".to_string(), 50052)]),
+            _ => Err(anyhow!("NXDOMAIN")),
+        });
+        resolver
+    }
+
+    #[tokio::test]
+    async fn test_resolve_static() {
+        let discovery = Discovery::with_resolver(Box::new(MockResolver::new()));
+        let resolved = discovery.resolve(&configs()[0]).await.unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name(), "v6");
+        assert_eq!(resolved[0].get_socket_addr(), "[fd00::10]:50051".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_host() {
+        let discovery = Discovery::with_resolver(Box::new(stub_resolver()));
+        let resolved = discovery.resolve(&configs()[1]).await.unwrap();
+        let names: Vec<String> = resolved.iter().map(|config| config.name()).collect();
+        assert_eq!(names, vec!["dns@10.0.0.1:50051", "dns@10.0.0.2:50051"]);
+        assert!(resolved.iter().all(|config| config.weight() == Some(20)));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_srv() {
+        let discovery = Discovery::with_resolver(Box::new(stub_resolver()));
+        let resolved = discovery.resolve(&configs()[2]).await.unwrap();
+        let addrs: Vec<SocketAddr> = resolved.iter().map(|config| config.get_socket_addr()).collect();
+        assert_eq!(addrs, vec!["10.0.1.1:50051".parse().unwrap(), "[fd00::1]:50052".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_all_skips_failed() {
+        let mut resolver = MockResolver::new();
+        resolver.expect_lookup_ip().returning(|_| Err(anyhow!("NXDOMAIN")));
+        resolver.expect_lookup_srv().returning(|_| Err(anyhow!("NXDOMAIN")));
+        let discovery = Discovery::with_resolver(Box::new(resolver));
+        let resolved = discovery.resolve_all(configs()).await;
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name(), "v6");
+    }
+}
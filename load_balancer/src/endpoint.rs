@@ -1,13 +1,14 @@
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use mockall::automock;
 use once_cell::sync::OnceCell;
-use tonic::Request;
+use tonic::{Code, Request, Status};
+use tonic::metadata::MetadataValue;
 use tonic::transport::{Channel, Uri};
 use tonic_health::pb::health_check_response::ServingStatus;
 use tonic_health::pb::health_client::HealthClient;
@@ -16,8 +17,12 @@ use tonic_health::pb::HealthCheckRequest;
 use word_counter::counter_client::CounterClient;
 use word_counter::WordCountRequest;
 
-use crate::metrics::QueryCounter;
-use crate::model::endpoints_config::EndpointConfig;
+use crate::connection::{Connection, ConnectionState};
+use crate::consts::{METADATA_API_KEY, METADATA_TENANT};
+use crate::health::HealthState;
+use crate::metrics::{EndpointGauge, QueryCounter};
+use crate::model::endpoints_config::{ConnectionConfig, EndpointConfig, HealthCheckConfig};
+use crate::tls;
 
 pub mod word_counter {
     include!("generated/word_counter.rs");
@@ -32,9 +37,21 @@ pub trait Endpoint: Send + Sync {
 
     // for weighted-round-robin
     fn weight(&self) -> Option<u8>;
-    async fn handle(&self, req: &str) -> Result<String>;
+    fn set_weight(&self, weight: Option<u8>);
+    // requests forwarded to this endpoint and not yet answered
+    fn in_flight(&self) -> usize;
+    // false once `max_in_flight` requests are in flight, or while reconnecting after the connection was lost
+    fn has_capacity(&self) -> bool;
+    // a drained endpoint receives no new requests, regardless of its health
+    fn set_drained(&self, drained: bool);
+    fn is_drained(&self) -> bool;
+    // `tenant` is the authenticated tenant the request is sent on behalf of
+    async fn handle(&self, req: &str, tenant: Option<&str>) -> Result<String>;
     async fn health_check(&self);
     fn health_report(&self) -> bool;
+    fn connection_state(&self) -> ConnectionState;
+    fn connection_config(&self) -> ConnectionConfig;
+    fn health_check_config(&self) -> HealthCheckConfig;
 }
 
 pub struct WordCountServer {
@@ -42,33 +59,76 @@ pub struct WordCountServer {
     counter_client: OnceCell<CounterClient<Channel>>,
     health_client: OnceCell<HealthClient<Channel>>,
     channel: Option<Channel>,
-    is_health: AtomicBool,
+    connection: Connection,
+    health: HealthState,
+    weight: RwLock<Option<u8>>,
+    in_flight: AtomicUsize,
+    drained: AtomicBool,
+}
+
+struct InFlightGuard<'a> {
+    in_flight: &'a AtomicUsize,
+}
+
+impl<'a> InFlightGuard<'a> {
+    fn new(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { in_flight }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl WordCountServer {
+    // never waits for the server: the channel connects lazily and reconnects on the next call after a failure
     pub async fn build(&mut self) -> Result<()> {
-        self.connect_channel().await?;
+        self.connect_channel()?;
         self.create_counter_client()?;
         self.create_health_client()?;
         Ok(())
     }
     pub fn new(config: EndpointConfig) -> Self {
         WordCountServer {
+            weight: RwLock::new(config.weight()),
+            in_flight: AtomicUsize::default(),
+            drained: AtomicBool::default(),
+            connection: Connection::new(&config.name()),
             config,
             counter_client: OnceCell::new(),
             health_client: OnceCell::new(),
             channel: None,
-            is_health: AtomicBool::default(),
+            health: HealthState::new(),
         }
     }
 
-    async fn connect_channel(&mut self) -> Result<()> {
-        let uri: Uri = Uri::from_str(&format!("http://{}", self.config.get_socket_addr())).context("format endpoint uri failed")?;
-        // hardcoded connection configs, todo!
-        let inner_endpoint = Channel::builder(uri)
-            .connect_timeout(Duration::from_millis(50))
-            .tcp_keepalive(Some(Duration::from_secs(30)));
-        self.channel = Some(inner_endpoint.connect().await.context("rpc channel connect failed")?);
+    fn connect_channel(&mut self) -> Result<()> {
+        let connection = self.config.connection();
+        let scheme = if connection.tls().is_some() { "https" } else { "http" };
+        let uri: Uri = Uri::from_str(&format!("{}://{}", scheme, self.config.get_socket_addr())).context("format endpoint uri failed")?;
+        let mut inner_endpoint = Channel::builder(uri)
+            .connect_timeout(connection.connect_timeout())
+            .tcp_keepalive(Some(connection.tcp_keepalive()))
+            .initial_stream_window_size(connection.initial_stream_window_size())
+            .initial_connection_window_size(connection.initial_connection_window_size());
+        if let Some(limit) = connection.concurrency_limit() {
+            inner_endpoint = inner_endpoint.concurrency_limit(limit);
+        }
+        if let Some(tls) = connection.tls() {
+            inner_endpoint = inner_endpoint.tls_config(tls::client_config(&tls)?).context("configure channel TLS failed")?;
+        }
+        if let Some(interval) = connection.keepalive_interval() {
+            inner_endpoint = inner_endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(connection.keepalive_while_idle());
+            if let Some(timeout) = connection.keepalive_timeout() {
+                inner_endpoint = inner_endpoint.keep_alive_timeout(timeout);
+            }
+        }
+        self.channel = Some(inner_endpoint.connect_lazy());
         Ok(())
     }
 
@@ -109,17 +169,81 @@ impl WordCountServer {
         Ok(Request::new(req))
     }
 
+    // the load balancer's api key, and the tenant counter_service should enforce the file allowlist of
+    fn authorize<T>(&self, req: &mut Request<T>, tenant: Option<&str>) -> Result<()> {
+        if let Some(api_key) = self.config.connection().api_key() {
+            req.metadata_mut().insert(METADATA_API_KEY, MetadataValue::try_from(api_key).context("invalid api_key")?);
+        }
+        if let Some(tenant) = tenant {
+            req.metadata_mut().insert(METADATA_TENANT, MetadataValue::try_from(tenant).context("invalid tenant name")?);
+        }
+        Ok(())
+    }
+
+    // only a transport failure means the connection is lost, other errors come from a connected server
+    fn update_connection_state(&self, status: &Status) {
+        if status.code() == Code::Unavailable {
+            self.connection.mark_disconnected();
+        }
+    }
+
+    // a SERVING server must also answer a Count of the canary file, so a broken dependency shows up as unhealthy
+    async fn deep_health_check(&self) -> bool {
+        let health_check = self.config.health_check();
+        let Some(canary) = health_check.canary_request() else {
+            return true;
+        };
+        let mut metrics_guard = QueryCounter::new(&self.name(), "DeepHealthCheck");
+        let mut req = Request::new(canary);
+        if let Err(err) = self.authorize(&mut req, None) {
+            tracing::warn!("[LoadBalancer] deep health check failed, endpoint={}, err={:?}", self.name(), err);
+            return false;
+        }
+        req.set_timeout(health_check.timeout().unwrap_or(self.config.connection().health_check_timeout()));
+        let Some(mut client) = self.counter_client() else {
+            return false;
+        };
+        let resp = match client.count(req).await {
+            Ok(resp) => resp.into_inner(),
+            Err(err) => {
+                tracing::warn!("[LoadBalancer] deep health check failed, endpoint={}, err={:?}", self.name(), err);
+                self.update_connection_state(&err);
+                return false;
+            }
+        };
+        let healthy = resp.status_code == 0
+            && health_check.expected_count().is_none_or(|expected| expected == resp.count);
+        if healthy {
+            metrics_guard.mark_success();
+        } else {
+            tracing::warn!("[LoadBalancer] deep health check failed, endpoint={}, response={:?}", self.name(), resp);
+        }
+        healthy
+    }
+
+    // a single result only moves the health after `rise` or `fall` consecutive ones, see `HealthState`
     fn update_health_status(&self, status: i32) {
-        let updated = ServingStatus::try_from(status)
-            .map_or(false, |status| status == ServingStatus::Serving);
-        self.is_health.store(updated, Ordering::SeqCst);
-        if !self.is_health.load(Ordering::SeqCst) {
-            self.log_unhealthy_instance()
+        let serving = ServingStatus::try_from(status)
+            .is_ok_and(|status| status == ServingStatus::Serving);
+        let health_check = self.config.health_check();
+        let was_healthy = self.health.is_healthy();
+        if self.connection.state() == ConnectionState::Disconnected {
+            self.health.mark_unhealthy();
+        } else {
+            self.health.record(serving, health_check.rise(), health_check.fall());
+        }
+        let healthy = self.health.is_healthy();
+        EndpointGauge::health(&self.name(), healthy);
+        if !serving {
+            self.log_unhealthy_instance(healthy)
+        }
+        if healthy != was_healthy {
+            tracing::info!("[LoadBalancer] downstream instance [name:{}, addr:{}] turned {}", self.name(), self.addr(), if healthy { "healthy" } else { "unhealthy" });
         }
     }
 
-    fn log_unhealthy_instance(&self) {
-        tracing::warn!("[LoadBalancer] unhealthy downstream instance:[name:{}, addr{}]",self.name(),self.addr());
+    fn log_unhealthy_instance(&self, healthy: bool) {
+        tracing::warn!("[LoadBalancer] failed health check of downstream instance:[name:{}, addr{}], still healthy: {}", self.name(), self.addr(), healthy);
     }
 }
 
@@ -134,21 +258,48 @@ impl Endpoint for WordCountServer {
     }
 
     fn weight(&self) -> Option<u8> {
-        return self.config.weight();
+        *self.weight.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn set_weight(&self, weight: Option<u8>) {
+        *self.weight.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = weight;
     }
 
-    async fn handle(&self, req: &str) -> Result<String> {
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    // the channel would reconnect on every request, they are refused until the reconnect backoff elapses
+    fn has_capacity(&self) -> bool {
+        self.connection.attempt_due() && self.config.connection().max_in_flight().is_none_or(|max| self.in_flight() < max)
+    }
+
+    fn set_drained(&self, drained: bool) {
+        self.drained.store(drained, Ordering::SeqCst);
+    }
+
+    fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::SeqCst)
+    }
+
+    async fn handle(&self, req: &str, tenant: Option<&str>) -> Result<String> {
+        let _in_flight_guard = InFlightGuard::new(&self.in_flight);
         // metrics
         let mut metrics_guard = QueryCounter::new(&self.name(), "WordCount");
 
         let mut req = Self::parse(req)
             .context(format!("Endpoint handle failed, endpoint name={}, addr={:?}", self.config.name(), self.config.get_socket_addr()))?;
-        req.set_timeout(Duration::from_secs(8));
+        self.authorize(&mut req, tenant)?;
+        req.set_timeout(self.config.connection().request_timeout());
         let mut client = self.counter_client().ok_or_else(|| anyhow!("handle request failed"))?;
-        let resp = client.count(req).await.context("call count service failed")?;
+        let resp = client.count(req).await
+            .inspect_err(|status| self.update_connection_state(status))
+            .context("call count service failed")?;
         let resp = serde_json::to_string(resp.get_ref()).context("serialize response failed")?;
 
         metrics_guard.mark_success();
+        // an answer proves the connection, also when no health check runs
+        self.connection.mark_connected();
         Ok(resp)
     }
 
@@ -157,27 +308,64 @@ impl Endpoint for WordCountServer {
         let mut metrics_guard = QueryCounter::new(&self.name(), "HealthCheck");
 
         let mut req = Request::new(HealthCheckRequest::default());
-        req.set_timeout(Duration::from_millis(100)); // Avoid blocking load balancer health checks due to slow/unhealthy instances.
+        req.set_timeout(self.config.connection().health_check_timeout());
         let mut status = ServingStatus::NotServing as i32;
+        // a disconnected server stays unhealthy until its reconnect backoff elapses
+        if !self.connection.attempt_due() {
+            self.update_health_status(status);
+            return;
+        }
         if let Some(mut health_client) = self.health_client() {
-            if let Ok(response) = health_client.check(req).await {
-                metrics_guard.mark_success();
-                status = response.into_inner().status;
+            match health_client.check(req).await {
+                Ok(response) => {
+                    metrics_guard.mark_success();
+                    self.connection.mark_connected();
+                    status = response.into_inner().status;
+                }
+                Err(err) => self.update_connection_state(&err),
             }
         }
+        if status == ServingStatus::Serving as i32 && !self.deep_health_check().await {
+            status = ServingStatus::NotServing as i32;
+        }
         self.update_health_status(status);
-
-        return;
     }
 
     fn health_report(&self) -> bool {
-        return self.is_health.load(Ordering::SeqCst);
+        self.health.is_healthy()
+    }
+
+    fn connection_state(&self) -> ConnectionState {
+        self.connection.state()
+    }
+
+    fn connection_config(&self) -> ConnectionConfig {
+        self.config.connection()
+    }
+
+    fn health_check_config(&self) -> HealthCheckConfig {
+        self.config.health_check()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::endpoint::WordCountServer;
+    use std::convert::Infallible;
+    use std::fs;
+
+    use tokio::net::TcpListener;
+    use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+    use tonic::codec::ProstCodec;
+    use tonic::server::{Grpc, NamedService, UnaryService};
+    use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+    use tonic::transport::server::TcpIncoming;
+
+    use crate::connection::ConnectionState;
+    use crate::consts::RECONNECT_BACKOFF_MIN;
+    use crate::endpoint::{Endpoint, WordCountServer};
+    use crate::endpoint::word_counter::{WordCountRequest, WordCountResponse};
+    use crate::model::endpoints_config::EndpointConfig;
+    use crate::tls::test_certs::TestCerts;
 
     #[test]
     fn test_parse() {
@@ -187,6 +375,10 @@ mod test {
         let req = req.unwrap().into_inner();
         assert_eq!(req.word, "world");
         assert_eq!(req.file_name, "text1.txt");
+        assert_eq!(req.corpus, "");
+
+        let req = WordCountServer::parse("{\"word\":\"world\", \"file_name\":\"text1.txt\", \"corpus\":\"books\"}").unwrap();
+        assert_eq!(req.into_inner().corpus, "books");
     }
 
     #[test]
@@ -195,4 +387,109 @@ mod test {
         let req = WordCountServer::parse(req);
         assert!(req.is_err());
     }
+
+    // a counter_service answering every Count with 1, the load balancer is generated without the server side
+    #[derive(Clone)]
+    struct FakeCounter;
+
+    impl NamedService for FakeCounter {
+        const NAME: &'static str = "word_counter.Counter";
+    }
+
+    impl<B> Service<http::Request<B>> for FakeCounter
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            Box::pin(async move { Ok(Grpc::new(ProstCodec::default()).unary(FakeCount, req).await) })
+        }
+    }
+
+    struct FakeCount;
+
+    impl UnaryService<WordCountRequest> for FakeCount {
+        type Response = WordCountResponse;
+        type Future = BoxFuture<tonic::Response<WordCountResponse>, tonic::Status>;
+
+        fn call(&mut self, _request: tonic::Request<WordCountRequest>) -> Self::Future {
+            Box::pin(async { Ok(tonic::Response::new(WordCountResponse { count: 1, ..Default::default() })) })
+        }
+    }
+
+    // without health checks, a successful request alone resets the connection
+    #[tokio::test]
+    async fn test_handle_marks_connected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = Server::builder().add_service(FakeCounter);
+        tokio::spawn(router.serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap()));
+
+        let config = format!("name = \"s1\"\nip = \"127.0.0.1\"\nport = {}\n[connection]\nconnect_timeout_ms = 2000", port);
+        let mut server = WordCountServer::new(toml::from_str(&config).unwrap());
+        assert!(server.build().await.is_ok());
+        server.connection.mark_disconnected();
+        assert_eq!(server.connection.delay(), RECONNECT_BACKOFF_MIN * 2);
+
+        let resp = server.handle("{\"word\":\"world\", \"file_name\":\"text1.txt\"}", None).await.unwrap();
+        assert!(resp.contains("\"count\":1"), "{}", resp);
+        assert_eq!(server.connection_state(), ConnectionState::Connected);
+        assert_eq!(server.connection.delay(), RECONNECT_BACKOFF_MIN);
+    }
+
+    #[tokio::test]
+    async fn test_build_unreachable() {
+        let config: EndpointConfig = toml::from_str("name = \"down\"\nip = \"127.0.0.1\"\nport = 1").unwrap();
+        let mut server = WordCountServer::new(config);
+        assert!(server.build().await.is_ok());
+        assert_eq!(server.connection_state(), ConnectionState::Connecting);
+
+        assert!(server.has_capacity());
+
+        server.health_check().await;
+        assert_eq!(server.connection_state(), ConnectionState::Disconnected);
+        assert!(!server.health_report());
+        // not picked for requests until the reconnect backoff elapses
+        assert!(!server.has_capacity());
+        tokio::time::sleep(RECONNECT_BACKOFF_MIN).await;
+        assert!(server.has_capacity());
+    }
+
+    // the health service behind mutual TLS, reachable only with the client certificate
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let certs = TestCerts::generate("endpoint");
+        let identity = Identity::from_pem(fs::read(certs.path("server.pem")).unwrap(), fs::read(certs.path("server.key")).unwrap());
+        let tls = ServerTlsConfig::new()
+            .identity(identity)
+            .client_ca_root(Certificate::from_pem(fs::read(certs.path("ca.pem")).unwrap()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (_, health_service) = tonic_health::server::health_reporter();
+        let router = Server::builder().tls_config(tls).unwrap().add_service(health_service);
+        tokio::spawn(router.serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap()));
+
+        let endpoint = |tls: &str| {
+            let config = format!("name = \"s1\"\nip = \"127.0.0.1\"\nport = {}\n[connection]\nconnect_timeout_ms = 2000\nhealth_check_timeout_ms = 2000\n{}", port, tls);
+            WordCountServer::new(toml::from_str(&config).unwrap())
+        };
+        let mut server = endpoint(&format!("tls_ca_cert = {:?}\ntls_cert = {:?}\ntls_key = {:?}",
+                                           certs.path("ca.pem"), certs.path("client.pem"), certs.path("client.key")));
+        assert!(server.build().await.is_ok());
+        server.health_check().await;
+        assert!(server.health_report());
+
+        let mut server = endpoint(&format!("tls_ca_cert = {:?}", certs.path("ca.pem")));
+        assert!(server.build().await.is_ok());
+        server.health_check().await;
+        assert!(!server.health_report());
+    }
 }
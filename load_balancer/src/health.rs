@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::endpoint::Endpoint;
+use crate::metrics::HealthCheckMetrics;
+
+/// Health of one endpoint with hysteresis: it turns unhealthy after `fall` consecutive failed checks and
+/// healthy again after `rise` consecutive successful ones, so a single slow probe does not flap it.
+/// The first check decides the initial state on its own.
+pub struct HealthState {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    healthy: Option<bool>,
+    // consecutive results contradicting the current state
+    streak: u32,
+}
+
+impl HealthState {
+    pub fn new() -> Self {
+        HealthState { inner: Mutex::new(Inner { healthy: None, streak: 0 }) }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.lock().healthy.unwrap_or_default()
+    }
+
+    // records a check result and returns the health after it
+    pub fn record(&self, success: bool, rise: u32, fall: u32) -> bool {
+        let mut inner = self.lock();
+        match inner.healthy {
+            Some(healthy) if healthy == success => inner.streak = 0,
+            Some(healthy) => {
+                inner.streak += 1;
+                if inner.streak >= if healthy { fall } else { rise } {
+                    inner.healthy = Some(success);
+                    inner.streak = 0;
+                }
+            }
+            None => inner.healthy = Some(success),
+        }
+        inner.healthy.unwrap_or_default()
+    }
+
+    // a lost connection is unhealthy at once, there is nothing to smooth out
+    pub fn mark_unhealthy(&self) {
+        let mut inner = self.lock();
+        inner.healthy = Some(false);
+        inner.streak = 0;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Runs the health checks of every endpoint, each in its own task on its own schedule, so a slow endpoint
+/// delays nobody else. Tasks follow the endpoint set through `sync` and all stop on `stop`.
+pub struct HealthSupervisor {
+    shutdown: CancellationToken,
+    // None until started, keyed by endpoint identity so endpoints kept across a reload keep their task
+    tasks: Mutex<Option<HashMap<usize, CheckTask>>>,
+}
+
+struct CheckTask {
+    cancel: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+impl HealthSupervisor {
+    pub fn new() -> Self {
+        HealthSupervisor { shutdown: CancellationToken::new(), tasks: Mutex::new(None) }
+    }
+
+    pub fn start(&self, endpoints: &[Arc<Box<dyn Endpoint>>]) {
+        if self.shutdown.is_cancelled() {
+            return;
+        }
+        self.lock().get_or_insert_with(HashMap::new);
+        self.sync(endpoints);
+    }
+
+    // starts a task for every new endpoint and stops those of endpoints no longer in the set
+    pub fn sync(&self, endpoints: &[Arc<Box<dyn Endpoint>>]) {
+        let mut guard = self.lock();
+        let Some(tasks) = guard.as_mut() else {
+            return;
+        };
+        let mut current = HashMap::new();
+        for endpoint in endpoints {
+            let key = Self::key(endpoint);
+            // a task that ended (a check panicked) is started again
+            let task = tasks.remove(&key).filter(|task| !task.handle.is_finished()).unwrap_or_else(|| {
+                let cancel = self.shutdown.child_token();
+                let handle = tokio::spawn(Self::run(Arc::clone(endpoint), cancel.clone()));
+                CheckTask { cancel, handle }
+            });
+            current.insert(key, task);
+        }
+        for (_, task) in tasks.drain() {
+            task.cancel.cancel();
+        }
+        *tasks = current;
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+        if let Some(tasks) = self.lock().take() {
+            tracing::info!("[HealthSupervisor] stopped {} health check tasks", tasks.len());
+        }
+    }
+
+    #[cfg(test)]
+    fn running(&self) -> usize {
+        self.lock().as_ref().map_or(0, |tasks| tasks.values().filter(|task| !task.handle.is_finished()).count())
+    }
+
+    async fn run(endpoint: Arc<Box<dyn Endpoint>>, cancel: CancellationToken) {
+        let name = endpoint.name();
+        let mut next = Instant::now();
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = sleep_until(next) => {}
+            }
+            // read on every round, the settings may change with a reload
+            let config = endpoint.health_check_config();
+            let interval = config.interval().max(Duration::from_millis(1));
+            let started = Instant::now();
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = endpoint.health_check() => {}
+            }
+            let elapsed = started.elapsed();
+            HealthCheckMetrics::duration(&name, elapsed);
+            // a late start or a slow check lets whole intervals pass without a check
+            let missed = ((started - next + elapsed).as_millis() / interval.as_millis()) as u64;
+            if missed > 0 {
+                tracing::warn!("[HealthSupervisor] endpoint {} missed {} health check intervals, last check took {:?}", name, missed, elapsed);
+                HealthCheckMetrics::missed_intervals(&name, missed);
+            }
+            next = Instant::now() + jittered(interval, config.jitter());
+        }
+    }
+
+    fn key(endpoint: &Arc<Box<dyn Endpoint>>) -> usize {
+        Arc::as_ptr(endpoint) as usize
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<HashMap<usize, CheckTask>>> {
+        self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// spreads the checks of endpoints sharing an interval, instead of probing all of them at once
+fn jittered(interval: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return interval;
+    }
+    let offset = rand::thread_rng().gen_range(0..=jitter.as_millis() as u64 * 2);
+    (interval + Duration::from_millis(offset)).saturating_sub(jitter)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::endpoint::MockEndpoint;
+
+    use super::*;
+
+    #[test]
+    fn test_rise_fall() {
+        let health = HealthState::new();
+        assert!(!health.is_healthy());
+        assert!(health.record(true, 2, 3));
+
+        // fall = 3: two failures are tolerated, the third turns it unhealthy
+        assert!(health.record(false, 2, 3));
+        assert!(health.record(false, 2, 3));
+        assert!(!health.record(false, 2, 3));
+
+        // rise = 2, a failure in between restarts the count
+        assert!(!health.record(true, 2, 3));
+        assert!(!health.record(false, 2, 3));
+        assert!(!health.record(true, 2, 3));
+        assert!(health.record(true, 2, 3));
+
+        health.mark_unhealthy();
+        assert!(!health.is_healthy());
+    }
+
+    fn endpoint(checks: Arc<AtomicUsize>) -> Arc<Box<dyn Endpoint>> {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(|| "s1".to_string());
+        endpoint.expect_health_check_config()
+            .returning(|| toml::from_str("interval_ms = 20\njitter_ms = 0").unwrap());
+        endpoint.expect_health_check().returning(move || {
+            checks.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {})
+        });
+        Arc::new(Box::new(endpoint))
+    }
+
+    #[tokio::test]
+    async fn test_supervisor() {
+        let checks1 = Arc::new(AtomicUsize::default());
+        let checks2 = Arc::new(AtomicUsize::default());
+        let endpoint1 = endpoint(Arc::clone(&checks1));
+        let endpoint2 = endpoint(Arc::clone(&checks2));
+        let supervisor = HealthSupervisor::new();
+        // nothing runs before start
+        supervisor.sync(&[Arc::clone(&endpoint1)]);
+        assert_eq!(supervisor.running(), 0);
+
+        supervisor.start(&[Arc::clone(&endpoint1)]);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(checks1.load(Ordering::SeqCst) >= 2);
+
+        // the task of the removed endpoint stops, one starts for the new endpoint
+        supervisor.sync(&[Arc::clone(&endpoint2)]);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let removed = checks1.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(checks1.load(Ordering::SeqCst), removed);
+        assert!(checks2.load(Ordering::SeqCst) >= 2);
+        assert_eq!(supervisor.running(), 1);
+
+        supervisor.stop();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let stopped = checks2.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(checks2.load(Ordering::SeqCst), stopped);
+        supervisor.start(&[endpoint1]);
+        assert_eq!(supervisor.running(), 0);
+    }
+
+    #[test]
+    fn test_jittered() {
+        assert_eq!(jittered(Duration::from_millis(500), Duration::ZERO), Duration::from_millis(500));
+        for _ in 0..100 {
+            let interval = jittered(Duration::from_millis(500), Duration::from_millis(50));
+            assert!(interval >= Duration::from_millis(450) && interval <= Duration::from_millis(550));
+        }
+    }
+}
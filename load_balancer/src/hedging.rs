@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::consts::{HEDGE_BUDGET_MAX, HEDGE_LATENCY_WINDOW, HEDGE_PERCENTILE_REFRESH};
+use crate::model::load_balancer_config::HedgingConfig;
+
+/// When to hedge a request, and whether the budget allows it: every request earns `budget_percent` of a hedge,
+/// up to `HEDGE_BUDGET_MAX` saved, and each hedge sent spends one, so hedges add at most that share of load.
+pub struct Hedging {
+    config: HedgingConfig,
+    state: Mutex<HedgingState>,
+}
+
+struct HedgingState {
+    latencies: VecDeque<Duration>,
+    // latencies recorded since the percentile was taken
+    recorded: usize,
+    percentile_delay: Option<Duration>,
+    budget: f64,
+}
+
+impl Hedging {
+    pub fn new(config: HedgingConfig) -> Self {
+        Hedging {
+            config,
+            state: Mutex::new(HedgingState {
+                latencies: VecDeque::with_capacity(HEDGE_LATENCY_WINDOW),
+                recorded: 0,
+                percentile_delay: None,
+                budget: HEDGE_BUDGET_MAX,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &HedgingConfig {
+        &self.config
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HedgingState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // how long a request runs before it is hedged, None while the percentile is not taken yet and no delay is set
+    pub fn delay(&self) -> Option<Duration> {
+        self.lock().percentile_delay.or(self.config.delay())
+    }
+
+    // called once per request, with its latency when it succeeded
+    pub fn record(&self, latency: Option<Duration>) {
+        let mut state = self.lock();
+        state.budget = (state.budget + self.config.budget_ratio()).min(HEDGE_BUDGET_MAX);
+        let (Some(latency), Some(percentile)) = (latency, self.config.percentile()) else {
+            return;
+        };
+        if state.latencies.len() == HEDGE_LATENCY_WINDOW {
+            state.latencies.pop_front();
+        }
+        state.latencies.push_back(latency);
+        state.recorded += 1;
+        if state.recorded >= HEDGE_PERCENTILE_REFRESH {
+            let mut latencies: Vec<Duration> = state.latencies.iter().copied().collect();
+            latencies.sort_unstable();
+            let index = ((latencies.len() as f64 * percentile / 100.0).ceil() as usize).clamp(1, latencies.len()) - 1;
+            state.percentile_delay = Some(latencies[index]);
+            state.recorded = 0;
+        }
+    }
+
+    // spends a hedge, false when the budget is used up
+    pub fn try_spend(&self) -> bool {
+        let mut state = self.lock();
+        if state.budget < 1.0 {
+            return false;
+        }
+        state.budget -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_toml(content: &str) -> Hedging {
+        Hedging::new(toml::from_str(content).unwrap())
+    }
+
+    #[test]
+    fn test_percentile_delay() {
+        let hedging = from_toml("delay_ms = 50\npercentile = 90");
+        assert_eq!(hedging.delay(), Some(Duration::from_millis(50)));
+
+        for ms in 1..HEDGE_PERCENTILE_REFRESH as u64 {
+            hedging.record(Some(Duration::from_millis(ms)));
+        }
+        // failed requests are not latencies
+        hedging.record(None);
+        assert_eq!(hedging.delay(), Some(Duration::from_millis(50)));
+        hedging.record(Some(Duration::from_millis(100)));
+        assert_eq!(hedging.delay(), Some(Duration::from_millis(90)));
+
+        let hedging = from_toml("percentile = 90");
+        assert_eq!(hedging.delay(), None);
+    }
+
+    #[test]
+    fn test_budget() {
+        let hedging = from_toml("delay_ms = 50\nbudget_percent = 50");
+        for _ in 0..HEDGE_BUDGET_MAX as usize {
+            assert!(hedging.try_spend());
+        }
+        assert!(!hedging.try_spend());
+
+        // two requests earn a hedge
+        hedging.record(None);
+        assert!(!hedging.try_spend());
+        hedging.record(Some(Duration::from_millis(10)));
+        assert!(hedging.try_spend());
+        assert!(!hedging.try_spend());
+    }
+}
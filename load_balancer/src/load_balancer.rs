@@ -1,109 +1,265 @@
-use std::sync::{Arc, mpsc};
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use tokio::sync::Mutex;
 use tokio::task::spawn;
 use async_trait::async_trait;
-use futures::future::join_all;
 
-use crate::consts::HEALTH_CHECK_INTERVAL_MS;
+use crate::concurrency::ServerBusy;
+use crate::consts::{DRAIN_POLL_INTERVAL, ENDPOINT_DRAIN_TIMEOUT, SHED_ENDPOINTS_BUSY};
+use crate::consts::{HEDGE_BUDGET_EXHAUSTED, HEDGE_SENT, HEDGE_WON};
 use crate::endpoint::Endpoint;
+use crate::health::HealthSupervisor;
+use crate::hedging::Hedging;
+use crate::metrics::{ConcurrencyMetrics, EndpointGauge, HedgeCounter, PoolGauge};
+use crate::model::load_balancer_config::HedgingConfig;
 use crate::strategy::context::StrategyContext;
 use crate::strategy::RouteStrategy;
 
 #[async_trait]
 pub trait LoadBalancer: Sync + Send
 {
-    #[allow(dead_code)]
-    fn set_strategy(&mut self, strategy: Box<dyn RouteStrategy>);
-    async fn handle(&self, req: String) -> Result<String>;
+    async fn set_strategy(&self, strategy: Box<dyn RouteStrategy>);
+    async fn strategy_name(&self) -> String;
+    // `tenant` is the authenticated tenant of the request, forwarded to the endpoint
+    async fn handle(&self, req: String, client_addr: SocketAddr, tenant: Option<String>) -> Result<String>;
+    fn endpoints(&self) -> Vec<Arc<Box<dyn Endpoint>>>;
+
+    // swap the endpoint set atomically, endpoints left out are drained in background
+    fn replace_endpoints(&self, endpoints: Vec<Arc<Box<dyn Endpoint>>>);
+    // health checks of every endpoint run in background until stopped, following `replace_endpoints`
     fn health_maintain(&self);
 
     fn stop_health_maintain(&self);
+    // None disables hedging
+    fn set_hedging(&self, config: Option<HedgingConfig>);
 }
 
+type EndpointSet = Arc<Vec<Arc<Box<dyn Endpoint>>>>;
+
 pub struct LoadBalancerImpl
 {
-    endpoints: Arc<Vec<Arc<Box<dyn Endpoint>>>>,
+    endpoints: Arc<RwLock<EndpointSet>>,
     router_strategy: Mutex<Box<dyn RouteStrategy>>,
-    close_signal_receiver: Arc<Mutex<Receiver<bool>>>,
-    close_signal_sender: Sender<bool>,
+    health_supervisor: HealthSupervisor,
+    // no endpoint is healthy, requests go to all of them, see `routable_endpoints`
+    panic_mode: AtomicBool,
+    hedging: RwLock<Option<Arc<Hedging>>>,
 }
 
 impl LoadBalancerImpl
 {
     pub fn new(endpoints: Vec<Arc<Box<dyn Endpoint>>>, strategy: Box<dyn RouteStrategy>) -> Self {
-        let (tx, rx) = mpsc::channel();
         LoadBalancerImpl {
-            endpoints: Arc::new(endpoints),
+            endpoints: Arc::new(RwLock::new(Arc::new(endpoints))),
             router_strategy: Mutex::new(strategy),
-            close_signal_receiver: Arc::new(Mutex::new(rx)),
-            close_signal_sender: tx,
+            health_supervisor: HealthSupervisor::new(),
+            panic_mode: AtomicBool::default(),
+            hedging: RwLock::new(None),
         }
     }
 
-    async fn pick_endpoint(&self, ctx: &StrategyContext) -> Option<Arc<Box<dyn Endpoint>>> {
+    // endpoints at their `max_in_flight` are skipped, the request is shed when all of them are
+    async fn pick_endpoint(&self, ctx: &StrategyContext) -> Result<Arc<Box<dyn Endpoint>>> {
+        let endpoints = self.routable_endpoints();
+        if endpoints.is_empty() {
+            return Err(anyhow!("assign endpoint failed, no proper endpoint found"));
+        }
+        let endpoints: Vec<_> = endpoints.into_iter().filter(|endpoint| endpoint.has_capacity()).collect();
+        if endpoints.is_empty() {
+            ConcurrencyMetrics::shed(SHED_ENDPOINTS_BUSY);
+            return Err(ServerBusy(SHED_ENDPOINTS_BUSY).into());
+        }
         let mut strategy = self.router_strategy.lock().await;
-        strategy.pick(ctx, &self.filter_healthy_endpoints())
+        strategy.pick(ctx, &endpoints).ok_or_else(|| anyhow!("assign endpoint failed, no proper endpoint found"))
+    }
+
+    // The hedge goes to the next endpoint with capacity after the first one. The strategy is left out, so a hedge
+    // neither advances its cursor nor moves a StickySession client.
+    fn pick_hedge_endpoint(&self, first: &Arc<Box<dyn Endpoint>>) -> Option<Arc<Box<dyn Endpoint>>> {
+        let endpoints = self.routable_endpoints();
+        let start = endpoints.iter().position(|endpoint| Arc::ptr_eq(endpoint, first)).map_or(0, |index| index + 1);
+        endpoints
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(endpoints.len())
+            .find(|endpoint| !Arc::ptr_eq(endpoint, first) && endpoint.has_capacity())
+            .cloned()
+    }
+
+    fn hedging(&self) -> Option<Arc<Hedging>> {
+        self.hedging.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    // Sends the request to a second endpoint when the first has not answered within the hedge delay, and returns
+    // the first success. The other request is cancelled when its future is dropped on return.
+    async fn handle_hedged(&self, hedging: &Hedging, req: &str, tenant: Option<&str>, endpoint: Arc<Box<dyn Endpoint>>) -> Result<String> {
+        let start = Instant::now();
+        let first = endpoint.handle(req, tenant);
+        tokio::pin!(first);
+        let resp = match hedging.delay() {
+            Some(delay) => match tokio::time::timeout(delay, &mut first).await {
+                Ok(resp) => resp,
+                Err(_) => match self.hedge(hedging, &endpoint, delay) {
+                    Some(hedge_endpoint) => {
+                        let hedge = hedge_endpoint.handle(req, tenant);
+                        tokio::pin!(hedge);
+                        tokio::select! {
+                            resp = &mut first => match resp {
+                                Ok(resp) => Ok(resp),
+                                Err(_) => hedge.await.inspect(|_| HedgeCounter::inc(HEDGE_WON)),
+                            },
+                            resp = &mut hedge => match resp {
+                                Ok(resp) => {
+                                    HedgeCounter::inc(HEDGE_WON);
+                                    Ok(resp)
+                                }
+                                Err(_) => first.await,
+                            },
+                        }
+                    }
+                    None => first.await,
+                },
+            },
+            None => first.await,
+        };
+        hedging.record(resp.is_ok().then(|| start.elapsed()));
+        resp
+    }
+
+    // the endpoint to hedge to, None when there is none or the budget is used up
+    fn hedge(&self, hedging: &Hedging, first: &Arc<Box<dyn Endpoint>>, delay: Duration) -> Option<Arc<Box<dyn Endpoint>>> {
+        let hedge_endpoint = self.pick_hedge_endpoint(first)?;
+        if !hedging.try_spend() {
+            HedgeCounter::inc(HEDGE_BUDGET_EXHAUSTED);
+            return None;
+        }
+        HedgeCounter::inc(HEDGE_SENT);
+        tracing::info!("[LoadBalancer] no response from server [Name: {}] after {:?}, request hedged to server [Name: {}, Addr:{}]", first.name(), delay, hedge_endpoint.name(), hedge_endpoint.addr());
+        Some(hedge_endpoint)
+    }
+
+    fn snapshot(endpoints: &RwLock<EndpointSet>) -> EndpointSet {
+        Arc::clone(&endpoints.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
     }
 
     fn filter_healthy_endpoints(&self) -> Vec<Arc<Box<dyn Endpoint>>> {
-        self.endpoints
+        Self::snapshot(&self.endpoints)
             .iter()
-            .filter(|endpoint| endpoint.health_report())
+            .filter(|endpoint| endpoint.health_report() && !endpoint.is_drained())
             .map(Arc::clone)
             .collect()
     }
 
-    fn build_strategy_ctx(req: String) -> StrategyContext {
-        StrategyContext::new(req)
+    // When every endpoint is unhealthy, failing all requests is worse than trying them anyway: health checks
+    // may be failing for reasons that do not affect requests, e.g. an overloaded health service.
+    fn routable_endpoints(&self) -> Vec<Arc<Box<dyn Endpoint>>> {
+        let healthy = self.filter_healthy_endpoints();
+        let panic = healthy.is_empty();
+        let endpoints = if panic {
+            Self::snapshot(&self.endpoints)
+                .iter()
+                .filter(|endpoint| !endpoint.is_drained())
+                .map(Arc::clone)
+                .collect()
+        } else {
+            healthy
+        };
+        let panic = panic && !endpoints.is_empty();
+        if self.panic_mode.swap(panic, Ordering::SeqCst) != panic {
+            if panic {
+                tracing::warn!("[LoadBalancer] no healthy endpoint, panic mode: routing to all {} endpoints", endpoints.len());
+            } else {
+                tracing::info!("[LoadBalancer] healthy endpoints available, panic mode left");
+            }
+            PoolGauge::panic_mode(panic);
+        }
+        endpoints
+    }
+
+    fn build_strategy_ctx(req: String, client_addr: SocketAddr) -> StrategyContext {
+        StrategyContext::new(req).with_client_addr(client_addr)
+    }
+
+    // its metric series are removed once drained, unless an endpoint of the same name took its place meanwhile
+    fn drain(endpoints: Arc<RwLock<EndpointSet>>, endpoint: Arc<Box<dyn Endpoint>>) {
+        spawn(async move {
+            let start = Instant::now();
+            while endpoint.in_flight() > 0 && start.elapsed() < ENDPOINT_DRAIN_TIMEOUT {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+            let in_flight = endpoint.in_flight();
+            if in_flight > 0 {
+                tracing::warn!("[LoadBalancer] drain timeout, endpoint [Name: {}, Addr: {}] removed with {} requests in flight", endpoint.name(), endpoint.addr(), in_flight);
+            } else {
+                tracing::info!("[LoadBalancer] endpoint [Name: {}, Addr: {}] drained and removed", endpoint.name(), endpoint.addr());
+            }
+            let name = endpoint.name();
+            if !Self::snapshot(&endpoints).iter().any(|current| current.name() == name) {
+                EndpointGauge::remove(&name);
+            }
+        });
     }
 }
 
 #[async_trait]
 impl LoadBalancer for LoadBalancerImpl
 {
-    fn set_strategy(&mut self, strategy: Box<dyn RouteStrategy>) {
-        self.router_strategy = Mutex::new(strategy);
+    async fn set_strategy(&self, strategy: Box<dyn RouteStrategy>) {
+        tracing::info!("[LoadBalancer] strategy switched to {}", strategy.name());
+        *self.router_strategy.lock().await = strategy;
+    }
+
+    async fn strategy_name(&self) -> String {
+        self.router_strategy.lock().await.name()
     }
-    async fn handle(&self, req: String) -> Result<String> {
-        let endpoint = self.pick_endpoint(&Self::build_strategy_ctx(req.clone())).await
-            .ok_or_else(|| anyhow!("assign endpoint failed, no proper endpoint found"))?;
+    async fn handle(&self, req: String, client_addr: SocketAddr, tenant: Option<String>) -> Result<String> {
+        let endpoint = self.pick_endpoint(&Self::build_strategy_ctx(req.clone(), client_addr)).await?;
         tracing::info!("[LoadBalancer] request forwarded to server [Name: {}, Addr:{}], request={}", endpoint.name(), endpoint.addr(), req);
-        endpoint.handle(&req).await
+        match self.hedging() {
+            Some(hedging) => self.handle_hedged(&hedging, &req, tenant.as_deref(), endpoint).await,
+            None => endpoint.handle(&req, tenant.as_deref()).await,
+        }
     }
 
-    fn health_maintain(&self) {
-        let close_signal = Arc::clone(&self.close_signal_receiver);
-        let endpoints = Arc::clone(&self.endpoints);
+    fn endpoints(&self) -> Vec<Arc<Box<dyn Endpoint>>> {
+        Self::snapshot(&self.endpoints).to_vec()
+    }
 
-        spawn(async move {
-            loop {
-                match close_signal.lock().await.try_recv() {
-                    Ok(_) => {
-                        return;
-                    }
-                    Err(_) => {
-                        let handlers = endpoints.iter().map(|endpoint| async {
-                            endpoint.health_check().await
-                        });
-                        join_all(handlers).await;
-                        thread::sleep(HEALTH_CHECK_INTERVAL_MS)
-                    }
-                }
+    fn replace_endpoints(&self, endpoints: Vec<Arc<Box<dyn Endpoint>>>) {
+        let old = {
+            let mut current = self.endpoints.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+            std::mem::replace(&mut *current, Arc::new(endpoints))
+        };
+        let current = Self::snapshot(&self.endpoints);
+        self.health_supervisor.sync(&current);
+        for endpoint in old.iter() {
+            if !current.iter().any(|kept| Arc::ptr_eq(kept, endpoint)) {
+                Self::drain(Arc::clone(&self.endpoints), Arc::clone(endpoint));
             }
-        });
+        }
+        tracing::info!("[LoadBalancer] endpoints replaced, {} endpoints in service", current.len());
+    }
+
+    fn health_maintain(&self) {
+        self.health_supervisor.start(&Self::snapshot(&self.endpoints));
     }
 
     fn stop_health_maintain(&self) {
-        self.close_signal_sender.send(true).unwrap_or_else(
-            |err| {
-                tracing::warn!(?err, "load balancer exit failed");
-                ()
-            }
-        )
+        self.health_supervisor.stop();
+    }
+
+    // an unchanged config keeps the recorded latencies and budget
+    fn set_hedging(&self, config: Option<HedgingConfig>) {
+        let mut hedging = self.hedging.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if hedging.as_ref().map(|hedging| hedging.config()) != config.as_ref() {
+            tracing::info!("[LoadBalancer] hedging {}", if config.is_some() { "configured" } else { "disabled" });
+            *hedging = config.map(|config| Arc::new(Hedging::new(config)));
+        }
     }
 }
 
@@ -112,24 +268,33 @@ mod load_balancer_impl_tests {
     use std::net::{IpAddr, Ipv4Addr, SocketAddr};
     use std::time::Duration;
 
+    use crate::consts::GAUGE_HEALTH;
     use crate::endpoint::MockEndpoint;
+    use crate::model::endpoints_config::HealthCheckConfig;
     use crate::strategy::MockRouteStrategy;
 
     use super::*;
 
+    fn mock_endpoint(port: u16, healthy: bool) -> MockEndpoint {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(move || format!("s{}", port));
+        endpoint.expect_addr().returning(move || SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port));
+        endpoint.expect_health_report().returning(move || healthy);
+        endpoint.expect_is_drained().returning(|| false);
+        endpoint.expect_health_check_config().returning(HealthCheckConfig::default);
+        endpoint
+    }
+
     #[tokio::test]
     async fn test_health_maintain() {
         // healthy instances
-        let mut endpoint1 = MockEndpoint::new();
-        endpoint1.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080));
-        endpoint1.expect_health_report().returning(|| true);
-        let mut endpoint2 = MockEndpoint::new();
-        endpoint2.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081));
-        endpoint2.expect_health_report().returning(|| true);
+        let mut endpoint1 = mock_endpoint(8080, true);
+        endpoint1.expect_health_check().times(1..).returning(|| Box::pin(async {}));
+        let mut endpoint2 = mock_endpoint(8081, true);
+        endpoint2.expect_health_check().times(1..).returning(|| Box::pin(async {}));
         // unhealthy instance
-        let mut endpoint3 = MockEndpoint::new();
-        endpoint3.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8082));
-        endpoint3.expect_health_report().returning(|| false);
+        let mut endpoint3 = mock_endpoint(8082, false);
+        endpoint3.expect_health_check().times(1..).returning(|| Box::pin(async {}));
 
         let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![
             Arc::new(Box::new(endpoint1)),
@@ -141,10 +306,142 @@ mod load_balancer_impl_tests {
         let expectation3 = Arc::clone(&endpoints[2]);
         let lb = LoadBalancerImpl::new(endpoints, Box::new(MockRouteStrategy::new()));
         lb.health_maintain();
-        thread::sleep(Duration::from_millis(1000));
+        tokio::time::sleep(Duration::from_millis(100)).await;
         let endpoints_addr: Vec<SocketAddr> = lb.filter_healthy_endpoints().iter().map(|endpoint| endpoint.addr()).collect();
         assert!(endpoints_addr.contains(&expectation1.addr()));
         assert!(endpoints_addr.contains(&expectation2.addr()));
         assert!(!endpoints_addr.contains(&expectation3.addr()));
+        lb.stop_health_maintain();
+    }
+
+    #[tokio::test]
+    async fn test_panic_mode() {
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = [(8080, false), (8081, false), (8082, true)]
+            .into_iter()
+            .map(|(port, drained)| {
+                let mut endpoint = MockEndpoint::new();
+                endpoint.expect_addr().returning(move || SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port));
+                endpoint.expect_health_report().returning(|| false);
+                endpoint.expect_is_drained().returning(move || drained);
+                let endpoint: Box<dyn Endpoint> = Box::new(endpoint);
+                Arc::new(endpoint)
+            })
+            .collect();
+        let lb = LoadBalancerImpl::new(endpoints, Box::new(MockRouteStrategy::new()));
+        assert!(lb.filter_healthy_endpoints().is_empty());
+        // all unhealthy: every endpoint that is not drained is routable
+        let endpoints_addr: Vec<u16> = lb.routable_endpoints().iter().map(|endpoint| endpoint.addr().port()).collect();
+        assert_eq!(endpoints_addr, vec![8080, 8081]);
+        assert!(lb.panic_mode.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_busy() {
+        let mut endpoint = mock_endpoint(8080, true);
+        endpoint.expect_has_capacity().returning(|| false);
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(endpoint))];
+        let lb = LoadBalancerImpl::new(endpoints, Box::new(MockRouteStrategy::new()));
+        let err = lb.handle("{}".to_string(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9000), None).await.unwrap_err();
+        assert!(err.downcast_ref::<ServerBusy>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_hedging() {
+        let client_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9000);
+        let mut slow = mock_endpoint(8080, true);
+        slow.expect_has_capacity().returning(|| true);
+        slow.expect_handle().returning(|req, _| {
+            let req = req.to_string();
+            Box::pin(async move {
+                if req == "slow" {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                Ok("s8080".to_string())
+            })
+        });
+        let mut fast = mock_endpoint(8081, true);
+        fast.expect_has_capacity().returning(|| true);
+        // the hedge is sent on behalf of the same tenant
+        fast.expect_handle().withf(|_, tenant| *tenant == Some("acme")).times(1).returning(|_, _| Box::pin(async { Ok("s8081".to_string()) }));
+        // picked once per request, never for the hedge
+        let mut strategy = MockRouteStrategy::new();
+        strategy.expect_pick().times(2).returning(|_, endpoints| endpoints.first().cloned());
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(slow)), Arc::new(Box::new(fast))];
+        let lb = LoadBalancerImpl::new(endpoints, Box::new(strategy));
+        lb.set_hedging(Some(toml::from_str("delay_ms = 20").unwrap()));
+
+        // answered within the delay, not hedged
+        assert_eq!(lb.handle("fast".to_string(), client_addr, None).await.unwrap(), "s8080");
+        // hedged to the second endpoint, which answers first
+        let start = Instant::now();
+        assert_eq!(lb.handle("slow".to_string(), client_addr, Some("acme".to_string())).await.unwrap(), "s8081");
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_pick_hedge_endpoint() {
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = [(8080, true), (8081, false), (8082, true)]
+            .into_iter()
+            .map(|(port, capacity)| {
+                let mut endpoint = mock_endpoint(port, true);
+                endpoint.expect_has_capacity().returning(move || capacity);
+                Arc::new(Box::new(endpoint) as Box<dyn Endpoint>)
+            })
+            .collect();
+        let lb = LoadBalancerImpl::new(endpoints.clone(), Box::new(MockRouteStrategy::new()));
+        let hedge = |first: usize| lb.pick_hedge_endpoint(&endpoints[first]).map(|endpoint| endpoint.name());
+        // the next one with capacity, wrapping around
+        assert_eq!(hedge(0), Some("s8082".to_string()));
+        assert_eq!(hedge(1), Some("s8082".to_string()));
+        assert_eq!(hedge(2), Some("s8080".to_string()));
+
+        let lb = LoadBalancerImpl::new(endpoints[..2].to_vec(), Box::new(MockRouteStrategy::new()));
+        assert!(lb.pick_hedge_endpoint(&endpoints[0]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_replace_endpoints() {
+        let mut endpoint1 = MockEndpoint::new();
+        endpoint1.expect_name().returning(|| "s1".to_string());
+        endpoint1.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080));
+        endpoint1.expect_health_report().returning(|| true);
+        endpoint1.expect_is_drained().returning(|| false);
+        let mut endpoint2 = MockEndpoint::new();
+        endpoint2.expect_name().returning(|| "s2".to_string());
+        endpoint2.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081));
+        endpoint2.expect_health_report().returning(|| true);
+        endpoint2.expect_is_drained().returning(|| false);
+        endpoint2.expect_in_flight().returning(|| 0);
+        let mut endpoint3 = MockEndpoint::new();
+        endpoint3.expect_name().returning(|| "s3".to_string());
+        endpoint3.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8082));
+        endpoint3.expect_health_report().returning(|| true);
+        endpoint3.expect_is_drained().returning(|| false);
+
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![
+            Arc::new(Box::new(endpoint1)),
+            Arc::new(Box::new(endpoint2)),
+        ];
+        let kept = Arc::clone(&endpoints[0]);
+        let removed = Arc::clone(&endpoints[1]);
+        let lb = LoadBalancerImpl::new(endpoints, Box::new(MockRouteStrategy::new()));
+        EndpointGauge::health("s1", true);
+        EndpointGauge::health("s2", true);
+        lb.replace_endpoints(vec![Arc::clone(&kept), Arc::new(Box::new(endpoint3))]);
+
+        let endpoints_addr: Vec<SocketAddr> = lb.filter_healthy_endpoints().iter().map(|endpoint| endpoint.addr()).collect();
+        assert_eq!(endpoints_addr.len(), 2);
+        assert!(endpoints_addr.contains(&kept.addr()));
+        assert!(!endpoints_addr.contains(&removed.addr()));
+        assert!(lb.endpoints().iter().any(|endpoint| Arc::ptr_eq(endpoint, &kept)));
+
+        // the drained endpoint's series are gone, the kept one's stay
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let servers: Vec<String> = prometheus::gather().iter()
+            .filter(|family| family.get_name() == GAUGE_HEALTH)
+            .flat_map(|family| family.get_metric().iter().flat_map(|metric| metric.get_label().iter().map(|label| label.get_value().to_string())).collect::<Vec<_>>())
+            .collect();
+        assert!(servers.contains(&"s1".to_string()));
+        assert!(!servers.contains(&"s2".to_string()));
     }
 }
\ No newline at end of file
@@ -1,64 +1,127 @@
-use std::path::Path;
+use std::process;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
 use prometheus::{Encoder, TextEncoder};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 use tracing_appender::non_blocking::WorkerGuard;
 use warp::Filter;
 
-use crate::consts::{CONFIG_PATH_ENDPOINTS, CONFIG_PATH_LOAD_BALANCER, CONFIG_PATH_SERVER, ROUND_ROBIN, WEIGHTED_ROUND_ROBIN, HASH_BY_REQUEST};
+use crate::admin::AdminApi;
+use crate::cli::Cli;
+use crate::discovery::Discovery;
+use crate::consts::{WEIGHTED_ROUND_ROBIN, HASH_BY_REQUEST, STICKY_SESSION};
 use crate::endpoint::{Endpoint, WordCountServer};
 use crate::load_balancer::{LoadBalancer, LoadBalancerImpl};
-use crate::model::endpoints_config::EndpointPoolConfig;
+use crate::model::endpoints_config::{EndpointConfig, EndpointPoolConfig};
 use crate::model::load_balancer_config::LBConfig;
 use crate::model::server_config::ServerConfig;
+use crate::registry::Registry;
+use crate::reloader::ConfigReloader;
 use crate::server::LBServer;
 use crate::strategy::round_robin::RoundRobin;
 use crate::strategy::RouteStrategy;
 use crate::strategy::weighted_round_robin::WeightedRoundRobin;
 use crate::strategy::hash_lb::HashByRequest;
+use crate::strategy::sticky_session::StickySession;
 
+mod admin;
+mod auth;
+mod cli;
+mod concurrency;
 mod endpoint;
 mod load_balancer;
 mod strategy;
 mod server;
+mod tls;
+mod connection;
 mod consts;
+mod discovery;
+mod health;
+mod hedging;
 mod metrics;
+mod rate_limit;
+mod registry;
+mod reloader;
 
 mod model {
+    pub mod auth_config;
+    pub mod config_check;
     pub mod endpoints_config;
     pub mod server_config;
 
     pub mod load_balancer_config;
+    pub mod unified_config;
 }
 
 #[tokio::main]
-async fn main() {
-    // init ctrlc handler
-    let running = Arc::new(AtomicBool::new(true));
-    init_graceful_exit(running.clone());
+async fn main() -> Result<()> {
+    let cli = Arc::new(Cli::parse());
+
+    // validate the config files and exit, e.g. before deploying them
+    if cli.check_config {
+        match AppBuilder::check_config(&cli) {
+            Ok(effective) => println!("{}", effective),
+            Err(err) => {
+                eprintln!("{:#}", err);
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
-    // init logger
+    // init logger, flushed when the guard drops at the end of main
     let _guard = init_logger();
     tracing::info!("logger initiated");
 
-    // metrics data server
-    let metrics_task = tokio::spawn(async {
-        AppBuilder::start_metrics_server().await;
-        tracing::info!("metrics server exit");
+    // SIGTERM and SIGINT start a graceful shutdown
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
+
+    let server_config = cli.server_config().unwrap_or_else(|e| {
+        panic!("load server config failed with error: {:?}", e)
+    });
+    tracing::info!("effective server config {}:\n{}", cli.config_source(&cli.paths.server_config), server_config);
+    let load_balancer = AppBuilder::build_load_balancer(&cli).await.unwrap_or_else(|e| {
+        panic!("load balancer init failed with error: {:?}", e)
     });
 
+    // metrics data and admin API server, kept up until the load balancer has drained
+    let metrics_shutdown = CancellationToken::new();
+    let metrics_task = tokio::spawn(AppBuilder::start_metrics_server(Arc::clone(&load_balancer), server_config.clone(), metrics_shutdown.clone()));
+
+    // config hot reload
+    let reloader_task = tokio::spawn(ConfigReloader::new(Arc::clone(&load_balancer), Arc::clone(&cli)).run());
+
     // load balance server
     let lb_task = tokio::spawn(async {
-        let mut server = AppBuilder::build_lb_server().await.unwrap_or_else(|e| {
+        let server = LBServer::build(load_balancer, server_config).await.unwrap_or_else(|e| {
             panic!("server init failed with error: {:?}", e)
         });
-        server.start(running).await;
+        server.start(shutdown).await;
         tracing::info!("load balance server exit");
     });
 
-    let _ = tokio::join!(metrics_task, lb_task);
+    let served = lb_task.await.inspect_err(|err| tracing::error!(?err, "load balance server panicked"));
+    reloader_task.abort();
+    metrics_shutdown.cancel();
+    let _ = metrics_task.await;
+    // a panic of the server exits with a failure status, once the other tasks are stopped
+    served.context("load balance server panicked")?;
+    tracing::info!("load balancer exit");
+    Ok(())
+}
+
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let mut terminate = signal(SignalKind::terminate()).expect("register SIGTERM handler failed");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    tracing::info!("shutdown signal received");
+    shutdown.cancel();
 }
 
 fn init_logger() -> WorkerGuard {
@@ -71,35 +134,64 @@ fn init_logger() -> WorkerGuard {
     _guard
 }
 
-fn init_graceful_exit(running: Arc<AtomicBool>) {
-    ctrlc::set_handler(move || {
-        running.store(false, Ordering::SeqCst);
-    }).expect("Error setting Ctrl-C handler");
-}
-
 struct AppBuilder {}
 
 impl AppBuilder {
-    async fn build_lb_server() -> Result<LBServer> {
-        let lb_config = LBConfig::load(Path::new(CONFIG_PATH_LOAD_BALANCER))?;
-        let pool_config = EndpointPoolConfig::load(Path::new(CONFIG_PATH_ENDPOINTS), lb_config.strategy().as_str())?;
+    async fn build_load_balancer(cli: &Cli) -> Result<Arc<Box<dyn LoadBalancer>>> {
+        let lb_config = cli.lb_config()?;
+        let pool_config = cli.pool_config(lb_config.strategy().as_str())?;
+        tracing::info!("effective load balancer config {}:\n{}", cli.config_source(&cli.paths.load_balancer_config), lb_config);
 
         let strategy = Self::strategy(&lb_config);
-        let endpoints = Self::endpoints(pool_config).await;
+        let endpoints = Self::endpoints(pool_config, lb_config.strategy().as_str()).await?;
 
-        let lb = Self::load_balancer(endpoints, strategy);
-        LBServer::build(Arc::new(lb)).await
+        let load_balancer = Self::load_balancer(endpoints, strategy);
+        load_balancer.set_hedging(lb_config.hedging());
+        Ok(Arc::new(load_balancer))
     }
 
-    pub async fn start_metrics_server() {
-        let server_config = ServerConfig::load(Path::new(CONFIG_PATH_SERVER)).expect("load server config failed");
+    // loads every config file, so the problems of all files are reported together,
+    // returns the effective server and load balancer config when all are valid
+    fn check_config(cli: &Cli) -> Result<String> {
+        let server_config = cli.server_config();
+        let lb_config = cli.lb_config();
+        let strategy = lb_config.as_ref().map(LBConfig::strategy).unwrap_or_default();
+        let pool_config = cli.pool_config(&strategy);
+        match (server_config, lb_config, pool_config) {
+            (Ok(server_config), Ok(lb_config), Ok(_)) => Ok(format!(
+                "# server: {}\n{}\n\n# load balancer: {}\n{}",
+                cli.config_source(&cli.paths.server_config), server_config,
+                cli.config_source(&cli.paths.load_balancer_config), lb_config
+            )),
+            (server_config, lb_config, pool_config) => {
+                let errors: Vec<String> = [server_config.err(), lb_config.err(), pool_config.err()]
+                    .into_iter()
+                    .flatten()
+                    .map(|err| format!("{:#}", err))
+                    .collect();
+                Err(anyhow!(errors.join("\n")))
+            }
+        }
+    }
+
+    pub async fn start_metrics_server(load_balancer: Arc<Box<dyn LoadBalancer>>, server_config: ServerConfig, shutdown: CancellationToken) {
         let metrics = warp::path!("metrics").map(|| {
             let encoder = TextEncoder::new();
             let mut buffer = vec![];
-            let _ = encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
+            encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
             warp::reply::with_header(buffer, "Context-Type", encoder.format_type())
         });
-        warp::serve(metrics).run(server_config.get_metrics_addr()).await;
+        let addr = server_config.get_metrics_addr();
+        let shutdown = async move { shutdown.cancelled().await };
+        match server_config.admin_token() {
+            Some(token) => {
+                tracing::info!("admin API enabled");
+                let admin = AdminApi::routes(load_balancer, token);
+                warp::serve(metrics.or(admin)).bind_with_graceful_shutdown(addr, shutdown).1.await;
+            }
+            None => warp::serve(metrics).bind_with_graceful_shutdown(addr, shutdown).1.await,
+        }
+        tracing::info!("metrics server exit");
     }
 
     fn strategy(config: &LBConfig) -> Box<dyn RouteStrategy> {
@@ -110,17 +202,31 @@ impl AppBuilder {
         Box::new(LoadBalancerImpl::new(endpoints, strategy))
     }
 
-    async fn endpoints(config: EndpointPoolConfig) -> Vec<Arc<Box<dyn Endpoint>>> {
+    async fn endpoints(config: EndpointPoolConfig, strategy: &str) -> Result<Vec<Arc<Box<dyn Endpoint>>>> {
+        let discovery = Discovery::new(&config.discovery())?;
+        let mut registered = vec![];
+        if let Some(registry) = config.registry() {
+            match Registry::new(&registry)?.endpoints().await {
+                Ok(endpoints) => registered = config.registered(&endpoints, strategy),
+                Err(err) => tracing::error!(?err, "fetch registered endpoints failed"),
+            }
+        }
+        let mut configs = config.endpoint_configs();
+        configs.extend(registered);
         let mut endpoints = vec![];
-        for config in config.endpoint_configs() {
-            let mut endpoint = WordCountServer::new(config.clone());
-            endpoint.build().await.unwrap_or_else(|err| {
-                tracing::error!(?config, ?err, "build endpoint failed.")
-            });
-            let endpoint: Box<dyn Endpoint> = Box::new(endpoint);
-            endpoints.push(Arc::new(endpoint))
+        for config in discovery.resolve_all(configs).await {
+            endpoints.push(Self::endpoint(config).await)
         }
-        endpoints
+        Ok(endpoints)
+    }
+
+    async fn endpoint(config: EndpointConfig) -> Arc<Box<dyn Endpoint>> {
+        let mut endpoint = WordCountServer::new(config.clone());
+        endpoint.build().await.unwrap_or_else(|err| {
+            tracing::error!(?config, ?err, "build endpoint failed.")
+        });
+        let endpoint: Box<dyn Endpoint> = Box::new(endpoint);
+        Arc::new(endpoint)
     }
 
     fn create_strategy(config: &LBConfig) -> Box<dyn RouteStrategy> {
@@ -130,7 +236,8 @@ impl AppBuilder {
         match strategy.as_str() {
             WEIGHTED_ROUND_ROBIN => Box::new(WeightedRoundRobin::new()),
             HASH_BY_REQUEST => Box::new(HashByRequest::new()),
-            ROUND_ROBIN | _ => Box::new(RoundRobin::new(None)),
+            STICKY_SESSION => Box::new(StickySession::new(config.sticky_session_ttl())),
+            _ => Box::new(RoundRobin::new(None)),
         }
     }
 }
\ No newline at end of file
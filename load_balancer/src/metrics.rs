@@ -1,14 +1,121 @@
 use lazy_static::lazy_static;
-use prometheus::{HistogramTimer, register_histogram_vec, register_int_counter_vec};
-use prometheus::{HistogramVec, IntCounterVec};
+use std::time::Duration;
 
-use crate::consts::{COUNTER_LATENCY, COUNTER_QUERY};
+use prometheus::{HistogramTimer, register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec};
+use prometheus::{HistogramVec, IntCounterVec, IntGauge, IntGaugeVec};
+
+use crate::connection::ConnectionState;
+use crate::consts::{COUNTER_HEALTH_CHECK_MISSED_INTERVALS, COUNTER_LATENCY, COUNTER_QUERY, GAUGE_CONNECTION_STATE, GAUGE_HEALTH};
+use crate::consts::{COUNTER_RATE_LIMITED, COUNTER_SHED, GAUGE_CONCURRENCY_LIMIT, GAUGE_PANIC_MODE, GAUGE_QUEUE_DEPTH};
+use crate::consts::{COUNTER_AUTH_REJECTED, COUNTER_HEDGED, HISTOGRAM_HEALTH_CHECK_DURATION};
 
 lazy_static! {
     static ref QUERY_COUNTER_VEC: IntCounterVec =
         register_int_counter_vec!(COUNTER_QUERY, "query count", &["server_name", "handler", "success"]).unwrap();
     static ref LATENCY_COUNTER_VEC: HistogramVec =
         register_histogram_vec!(COUNTER_LATENCY, "server latency", &["server_name", "handler", "success"]).unwrap();
+    static ref CONNECTION_STATE_GAUGE_VEC: IntGaugeVec =
+        register_int_gauge_vec!(GAUGE_CONNECTION_STATE, "endpoint connection state, 0: disconnected, 1: connecting, 2: connected", &["server_name"]).unwrap();
+    static ref HEALTH_GAUGE_VEC: IntGaugeVec =
+        register_int_gauge_vec!(GAUGE_HEALTH, "endpoint health, 1: healthy, 0: unhealthy", &["server_name"]).unwrap();
+    static ref PANIC_MODE_GAUGE: IntGauge =
+        register_int_gauge!(GAUGE_PANIC_MODE, "1 while no endpoint is healthy and requests are routed to all of them").unwrap();
+    static ref HEALTH_CHECK_DURATION_VEC: HistogramVec =
+        register_histogram_vec!(HISTOGRAM_HEALTH_CHECK_DURATION, "health check duration in seconds", &["server_name"]).unwrap();
+    static ref HEALTH_CHECK_MISSED_INTERVALS_VEC: IntCounterVec =
+        register_int_counter_vec!(COUNTER_HEALTH_CHECK_MISSED_INTERVALS, "health check intervals passed without a check", &["server_name"]).unwrap();
+    static ref RATE_LIMITED_VEC: IntCounterVec =
+        register_int_counter_vec!(COUNTER_RATE_LIMITED, "requests rejected by the rate limit", &["client"]).unwrap();
+    static ref SHED_VEC: IntCounterVec =
+        register_int_counter_vec!(COUNTER_SHED, "requests rejected with server busy", &["reason"]).unwrap();
+    static ref QUEUE_DEPTH_GAUGE: IntGauge =
+        register_int_gauge!(GAUGE_QUEUE_DEPTH, "requests waiting for the concurrency limit").unwrap();
+    static ref CONCURRENCY_LIMIT_GAUGE: IntGauge =
+        register_int_gauge!(GAUGE_CONCURRENCY_LIMIT, "current limit of requests processed at once").unwrap();
+    static ref HEDGED_VEC: IntCounterVec =
+        register_int_counter_vec!(COUNTER_HEDGED, "hedged requests, sent, won over the first request, or not sent for lack of budget", &["outcome"]).unwrap();
+    static ref AUTH_REJECTED_VEC: IntCounterVec =
+        register_int_counter_vec!(COUNTER_AUTH_REJECTED, "requests rejected for a missing or unknown api key, or a file not allowed", &["reason"]).unwrap();
+}
+
+pub struct EndpointGauge;
+
+impl EndpointGauge {
+    pub fn connection_state(server_name: &str, state: ConnectionState) {
+        CONNECTION_STATE_GAUGE_VEC.with_label_values(&[server_name]).set(state as i64);
+    }
+
+    pub fn health(server_name: &str, healthy: bool) {
+        HEALTH_GAUGE_VEC.with_label_values(&[server_name]).set(healthy as i64);
+    }
+
+    // the series of an endpoint that left the pool, which would otherwise be exported at their last value forever
+    pub fn remove(server_name: &str) {
+        let _ = CONNECTION_STATE_GAUGE_VEC.remove_label_values(&[server_name]);
+        let _ = HEALTH_GAUGE_VEC.remove_label_values(&[server_name]);
+        let _ = HEALTH_CHECK_DURATION_VEC.remove_label_values(&[server_name]);
+        let _ = HEALTH_CHECK_MISSED_INTERVALS_VEC.remove_label_values(&[server_name]);
+    }
+}
+
+pub struct PoolGauge;
+
+impl PoolGauge {
+    pub fn panic_mode(active: bool) {
+        PANIC_MODE_GAUGE.set(active as i64);
+    }
+}
+
+pub struct HealthCheckMetrics;
+
+impl HealthCheckMetrics {
+    pub fn duration(server_name: &str, duration: Duration) {
+        HEALTH_CHECK_DURATION_VEC.with_label_values(&[server_name]).observe(duration.as_secs_f64());
+    }
+
+    pub fn missed_intervals(server_name: &str, missed: u64) {
+        HEALTH_CHECK_MISSED_INTERVALS_VEC.with_label_values(&[server_name]).inc_by(missed);
+    }
+}
+
+pub struct RateLimitCounter;
+
+impl RateLimitCounter {
+    pub fn rejected(client: &str) {
+        RATE_LIMITED_VEC.with_label_values(&[client]).inc();
+    }
+}
+
+pub struct ConcurrencyMetrics;
+
+impl ConcurrencyMetrics {
+    pub fn shed(reason: &str) {
+        SHED_VEC.with_label_values(&[reason]).inc();
+    }
+
+    pub fn queue_depth(depth: usize) {
+        QUEUE_DEPTH_GAUGE.set(depth as i64);
+    }
+
+    pub fn limit(limit: i64) {
+        CONCURRENCY_LIMIT_GAUGE.set(limit);
+    }
+}
+
+pub struct HedgeCounter;
+
+impl HedgeCounter {
+    pub fn inc(outcome: &str) {
+        HEDGED_VEC.with_label_values(&[outcome]).inc();
+    }
+}
+
+pub struct AuthCounter;
+
+impl AuthCounter {
+    pub fn rejected(reason: &str) {
+        AUTH_REJECTED_VEC.with_label_values(&[reason]).inc();
+    }
 }
 
 pub struct QueryCounter {
@@ -25,8 +132,8 @@ impl QueryCounter {
             query_success: false,
             server_name: String::from(server_name),
             handler: String::from(handler),
-            success_timer: Some(LATENCY_COUNTER_VEC.with_label_values(&[&server_name, &handler, "true"]).start_timer()),
-            failed_timer: Some(LATENCY_COUNTER_VEC.with_label_values(&[&server_name, &handler, "false"]).start_timer()),
+            success_timer: Some(LATENCY_COUNTER_VEC.with_label_values(&[server_name, handler, "true"]).start_timer()),
+            failed_timer: Some(LATENCY_COUNTER_VEC.with_label_values(&[server_name, handler, "false"]).start_timer()),
         }
     }
 
@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::model::config_check::ConfigProblems;
+
+/// Tenants, their API keys and the files they may query, read from `auth.toml`. counter_service reads the same
+/// file to enforce the same policy on direct gRPC calls.
+#[derive(Debug, Deserialize)]
+pub struct AuthConfig {
+    // keys of services calling counter_service on behalf of tenants, such as the load balancer
+    service_keys: Option<Vec<Spanned<String>>>,
+    tenants: Option<Vec<Spanned<TenantConfig>>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TenantConfig {
+    name: String,
+    api_keys: Vec<String>,
+    // file name patterns, `*` matches any characters and `?` a single one
+    files: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read auth config file:{:?}", path))?;
+        let mut problems = ConfigProblems::new(path, &content);
+        let config: AuthConfig = toml::from_str(&content)
+            .with_context(|| format!("failed to parse auth config {}", problems.source()))?;
+
+        config.check(&mut problems);
+        problems.into_result()?;
+
+        Ok(config)
+    }
+
+    fn check(&self, problems: &mut ConfigProblems) {
+        let mut names = HashSet::new();
+        let mut keys = HashMap::new();
+        for key in self.service_keys.iter().flatten() {
+            if keys.insert(key.get_ref().clone(), "service_keys".to_string()).is_some() {
+                problems.add(Some(key.span()), "duplicate key in service_keys");
+            }
+        }
+        for tenant in self.tenants.iter().flatten() {
+            let span = Some(tenant.span());
+            let config = tenant.get_ref();
+            if !names.insert(config.name.clone()) {
+                problems.add(span.clone(), format!("duplicate tenant name {:?}", config.name));
+            }
+            if config.api_keys.is_empty() {
+                problems.add(span.clone(), format!("tenant {}: api_keys should not be empty", config.name));
+            }
+            for key in &config.api_keys {
+                if key.is_empty() {
+                    problems.add(span.clone(), format!("tenant {}: api key should not be empty", config.name));
+                } else if let Some(owner) = keys.insert(key.clone(), format!("tenant {}", config.name)) {
+                    // the key itself is not repeated in the message, it would end up in logs
+                    problems.add(span.clone(), format!("tenant {}: api key already used by {}", config.name, owner));
+                }
+            }
+        }
+    }
+
+    pub fn tenants(&self) -> Vec<TenantConfig> {
+        self.tenants.iter().flatten().map(|tenant| tenant.get_ref().clone()).collect()
+    }
+}
+
+impl TenantConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn api_keys(&self) -> &[String] {
+        &self.api_keys
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let config = AuthConfig::load(Path::new("src/config_test/auth_test.toml")).unwrap();
+        let service_keys: Vec<&String> = config.service_keys.iter().flatten().map(Spanned::get_ref).collect();
+        assert_eq!(service_keys, ["lb-key"]);
+        let tenants = config.tenants();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants[0].name(), "acme");
+        assert_eq!(tenants[0].api_keys(), ["acme-key-1".to_string(), "acme-key-2".to_string()]);
+        assert_eq!(tenants[1].files(), ["*".to_string()]);
+    }
+
+    #[test]
+    fn test_check() {
+        let content = "service_keys = [\"k1\"]\n\n[[tenants]]\nname = \"a\"\napi_keys = [\"k1\"]\nfiles = []\n\n\
+                       [[tenants]]\nname = \"a\"\napi_keys = []\nfiles = []";
+        let config: AuthConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("auth.toml"), content);
+        config.check(&mut problems);
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("auth.toml:3: tenant a: api key already used by service_keys"));
+        assert!(err.contains("auth.toml:8: duplicate tenant name \"a\""));
+        assert!(err.contains("auth.toml:8: tenant a: api_keys should not be empty"));
+    }
+}
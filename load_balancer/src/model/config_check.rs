@@ -0,0 +1,69 @@
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+/// Collects every problem found in one config file, so they are all reported at once
+/// instead of failing on the first one. Problems are located by the span of the offending value.
+pub struct ConfigProblems<'a> {
+    source: String,
+    // None when the content was merged from several files, spans then point nowhere meaningful
+    content: Option<&'a str>,
+    problems: Vec<String>,
+}
+
+impl<'a> ConfigProblems<'a> {
+    pub fn new(path: &Path, content: &'a str) -> Self {
+        ConfigProblems { source: path.display().to_string(), content: Some(content), problems: vec![] }
+    }
+
+    pub fn without_lines(source: String) -> Self {
+        ConfigProblems { source, content: None, problems: vec![] }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn add(&mut self, span: Option<Range<usize>>, problem: impl Display) {
+        let problem = match span.and_then(|span| self.line(span.start)) {
+            Some(line) => format!("{}:{}: {}", self.source, line, problem),
+            None => format!("{}: {}", self.source, problem),
+        };
+        self.problems.push(problem);
+    }
+
+    pub fn into_result(self) -> Result<()> {
+        if self.problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("invalid config {}:\n{}", self.source, self.problems.join("\n")))
+    }
+
+    pub fn line(&self, offset: usize) -> Option<usize> {
+        let content = self.content?;
+        Some(content[..offset.min(content.len())].matches('\n').count() + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_problems() {
+        let content = "a = 1\nb = 2\n";
+        let mut problems = ConfigProblems::new(Path::new("x.toml"), content);
+        assert!(ConfigProblems::new(Path::new("x.toml"), content).into_result().is_ok());
+        problems.add(Some(10..11), "b is invalid");
+        problems.add(None, "c is missing");
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("x.toml:2: b is invalid"));
+        assert!(err.contains("x.toml: c is missing"));
+
+        let mut problems = ConfigProblems::without_lines("x.toml [server]".to_string());
+        problems.add(Some(10..11), "b is invalid");
+        assert!(problems.into_result().unwrap_err().to_string().contains("x.toml [server]: b is invalid"));
+    }
+}
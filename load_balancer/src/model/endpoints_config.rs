@@ -1,78 +1,382 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use serde::Deserialize;
+use toml::Spanned;
 
-use crate::consts::WEIGHTED_ROUND_ROBIN;
+use crate::consts::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_DISCOVERY_REFRESH_INTERVAL, DEFAULT_HEALTH_CHECK_FALL};
+use crate::consts::{DEFAULT_HEALTH_CHECK_INTERVAL, DEFAULT_HEALTH_CHECK_RISE, DEFAULT_HEALTH_CHECK_TIMEOUT};
+use crate::consts::{DEFAULT_REQUEST_TIMEOUT, DEFAULT_TCP_KEEPALIVE, MAX_HTTP2_WINDOW_SIZE};
+use crate::consts::{MAX_WEIGHT, WEIGHTED_ROUND_ROBIN};
+use crate::endpoint::word_counter::WordCountRequest;
+use crate::model::config_check::ConfigProblems;
 
 #[derive(Default, Debug, Deserialize)]
 pub struct EndpointPoolConfig {
-    endpoints: Vec<EndpointConfig>,
+    // spans locate problems found by `check`
+    endpoints: Vec<Spanned<EndpointConfig>>,
+    discovery: Option<Spanned<DiscoveryConfig>>,
+    registry: Option<RegistryConfig>,
+    // defaults for every endpoint, see `EndpointConfig::connection`
+    connection: Option<Spanned<ConnectionConfig>>,
+    // defaults for every endpoint, see `EndpointConfig::health_check`
+    health_check: Option<Spanned<HealthCheckConfig>>,
 }
 
+// the pool-level settings every endpoint falls back to
+#[derive(Default, Debug, Clone)]
+pub struct EndpointDefaults {
+    pub connection: ConnectionConfig,
+    pub health_check: HealthCheckConfig,
+}
+
+// An endpoint is addressed by exactly one of `ip` (IPv4 or IPv6), `host` (expanded into one endpoint
+// per A/AAAA record) or `srv` (expanded into one endpoint per SRV target, with the record's port).
 #[derive(Debug, Deserialize, Clone)]
 pub struct EndpointConfig {
     name: String,
-    ip: Ipv4Addr,
-    port: u16,
+    ip: Option<IpAddr>,
+    host: Option<String>,
+    srv: Option<String>,
+    port: Option<u16>,
     weight: Option<u8>,
+    connection: Option<ConnectionConfig>,
+    health_check: Option<HealthCheckConfig>,
+}
+
+// Channel settings of an endpoint. Every field is optional: unset fields of an endpoint's `[endpoints.connection]`
+// fall back to the pool's `[connection]`, and then to the built-in defaults.
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct ConnectionConfig {
+    connect_timeout_ms: Option<u64>,
+    request_timeout_ms: Option<u64>,
+    health_check_timeout_ms: Option<u64>,
+    tcp_keepalive_secs: Option<u64>,
+    // requests forwarded at once, an endpoint at the limit is skipped by the strategy
+    max_in_flight: Option<usize>,
+    // HTTP/2
+    concurrency_limit: Option<usize>,
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    keepalive_interval_secs: Option<u64>,
+    keepalive_timeout_secs: Option<u64>,
+    keepalive_while_idle: Option<bool>,
+    // TLS, see `EndpointTlsConfig`
+    tls_ca_cert: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_domain_name: Option<String>,
+    // key of the load balancer in counter_service's `service_keys`, sent with every call when it enforces auth
+    api_key: Option<String>,
+}
+
+// TLS of the channel, verified with `ca_cert`, mutual TLS when `cert` and `key` are set. The server certificate
+// is checked for `domain_name`, or for the endpoint's IP address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointTlsConfig {
+    ca_cert: String,
+    cert: Option<String>,
+    key: Option<String>,
+    domain_name: Option<String>,
+}
+
+// Health check schedule and thresholds. Unset fields of an endpoint's `[endpoints.health_check]` fall back to
+// the pool's `[health_check]`, and then to the built-in defaults.
+//
+// Deep health check: once the health service reports SERVING, a Count of `canary_word` in `canary_file`
+// must succeed (and return `expected_count` when set) for the endpoint to be healthy. Disabled unless
+// `canary_file` is set.
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct HealthCheckConfig {
+    interval_ms: Option<u64>,
+    // each check is scheduled `interval_ms` ± a random delay up to `jitter_ms` after the previous one
+    jitter_ms: Option<u64>,
+    // consecutive successes to turn healthy, consecutive failures to turn unhealthy
+    rise: Option<u32>,
+    fall: Option<u32>,
+    canary_file: Option<String>,
+    canary_word: Option<String>,
+    expected_count: Option<i64>,
+    // defaults to the connection's `health_check_timeout_ms`
+    timeout_ms: Option<u64>,
+}
+
+// counter_service instances registering themselves in redis, see `Registry`
+#[derive(Debug, Deserialize, Clone)]
+pub struct RegistryConfig {
+    redis_url: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub struct DiscoveryConfig {
+    refresh_interval_secs: Option<u64>,
+    nameserver: Option<SocketAddr>,
 }
 
 impl EndpointPoolConfig {
     pub fn load(path: &Path, strategy: &str) -> Result<Self> {
         let config_content = fs::read_to_string(path)
             .with_context(|| format!("failed to read endpoints config file:{:?}", path))?;
-        let mut new: EndpointPoolConfig = toml::from_str(&config_content)
-            .with_context(|| format!("failed to parse endpoints config file:{:?}", path))?;
+        Self::parse(&config_content, ConfigProblems::new(path, &config_content), strategy)
+    }
+
+    pub fn parse(content: &str, mut problems: ConfigProblems, strategy: &str) -> Result<Self> {
+        let new: EndpointPoolConfig = toml::from_str(content)
+            .with_context(|| format!("failed to parse endpoints config {}", problems.source()))?;
 
-        new.filter(strategy);
-        new.check()?;
+        new.check(strategy, &mut problems);
+        problems.into_result()?;
 
         Ok(new)
     }
 
+    // with the pool's defaults applied
     pub fn endpoint_configs(self) -> Vec<EndpointConfig> {
+        let defaults = self.endpoint_defaults();
         self.endpoints
+            .into_iter()
+            .map(|config| config.into_inner().with_defaults(&defaults))
+            .collect()
+    }
+
+    pub fn endpoint_defaults(&self) -> EndpointDefaults {
+        EndpointDefaults {
+            connection: self.connection.as_ref().map(|connection| connection.get_ref().clone()).unwrap_or_default(),
+            health_check: self.health_check.as_ref().map(|health_check| health_check.get_ref().clone()).unwrap_or_default(),
+        }
+    }
+
+    pub fn discovery(&self) -> DiscoveryConfig {
+        self.discovery.as_ref().map(|discovery| discovery.get_ref().clone()).unwrap_or_default()
     }
 
-    fn check(&self) -> Result<()> {
-        // checkers
-        Ok(())
+    // endpoints may change while the config files do not: addressed by hostname or SRV record, or registered
+    pub fn refreshed(&self) -> bool {
+        self.registry.is_some() || self.endpoints.iter().any(|config| config.get_ref().ip().is_none())
     }
 
-    fn filter(&mut self, strategy: &str) {
-        self.filter_by_weight(strategy);
+    pub fn registry(&self) -> Option<RegistryConfig> {
+        self.registry.clone()
     }
 
-    fn filter_by_weight(&mut self, strategy: &str) {
-        if strategy != WEIGHTED_ROUND_ROBIN { return; }
-        self.endpoints = self.endpoints.iter()
-            .filter(
-                |&config|
-                config.weight.is_some() && (config.weight.unwrap() <= 100)
-            )
-            .cloned()
-            .collect();
+    // registered endpoints valid on their own and not clashing with the configured ones or each other, with the pool's
+    // defaults applied. The others are skipped, they would break matching endpoints by name on reload and in the admin API.
+    pub fn registered(&self, registered: &[EndpointConfig], strategy: &str) -> Vec<EndpointConfig> {
+        let mut names: HashSet<String> = self.endpoints.iter().map(|config| config.get_ref().name.clone()).collect();
+        let mut addrs: HashSet<String> = self.endpoints.iter().filter_map(|config| config.get_ref().address_key()).collect();
+        let defaults = self.endpoint_defaults();
+        let mut configs = vec![];
+        for config in registered {
+            let mut problems = config.problems(strategy);
+            if !names.insert(config.name.clone()) {
+                problems.push(format!("endpoint {}: duplicate name", config.name));
+            }
+            if let Some(addr) = config.address_key().filter(|addr| !addrs.insert(addr.clone())) {
+                problems.push(format!("endpoint {}: duplicate address {}", config.name, addr));
+            }
+            if problems.is_empty() {
+                configs.push(config.clone().with_defaults(&defaults));
+            } else {
+                tracing::warn!(?problems, "[Registry] skip invalid registered endpoint");
+            }
+        }
+        configs
+    }
+
+    pub fn check(&self, strategy: &str, problems: &mut ConfigProblems) {
+        if self.endpoints.is_empty() && self.registry.is_none() {
+            problems.add(None, "no endpoints configured, add [[endpoints]] or a [registry]");
+        }
+        if let Some(discovery) = &self.discovery {
+            if discovery.get_ref().refresh_interval_secs == Some(0) {
+                problems.add(Some(discovery.span()), "[discovery]: refresh_interval_secs should be positive");
+            }
+        }
+
+        let defaults = self.endpoint_defaults();
+        let default_problems = defaults.connection.problems();
+        let connection_span = self.connection.as_ref().map(Spanned::span);
+        for problem in &default_problems {
+            problems.add(connection_span.clone(), format!("[connection]: {}", problem));
+        }
+        let default_health_check_problems = defaults.health_check.problems(&defaults.connection);
+        let health_check_span = self.health_check.as_ref().map(Spanned::span);
+        for problem in &default_health_check_problems {
+            problems.add(health_check_span.clone(), format!("[health_check]: {}", problem));
+        }
+
+        // first line of each name and address, to point duplicates at the original
+        let mut names = HashMap::new();
+        let mut addrs = HashMap::new();
+        for spanned in &self.endpoints {
+            let config = spanned.get_ref();
+            let span = Some(spanned.span());
+            let line = problems.line(spanned.span().start);
+            let name = &config.name;
+            for problem in config.problems(strategy) {
+                problems.add(span.clone(), problem);
+            }
+            if !name.is_empty() {
+                if let Some(first) = names.insert(name.clone(), line) {
+                    match first {
+                        Some(first) => problems.add(span.clone(), format!("endpoint {}: duplicate name, first defined at line {}", name, first)),
+                        None => problems.add(span.clone(), format!("endpoint {}: duplicate name", name)),
+                    }
+                }
+            }
+            if let Some(addr) = config.address_key() {
+                if let Some(first) = addrs.insert(addr.clone(), name.clone()) {
+                    problems.add(span.clone(), format!("endpoint {}: duplicate address {}, already used by endpoint {}", name, addr, first));
+                }
+            }
+
+            // only problems coming from the endpoint's own overrides, those of the defaults are reported once
+            if config.connection.is_some() {
+                for problem in config.connection().or(&defaults.connection).problems() {
+                    if !default_problems.contains(&problem) {
+                        problems.add(span.clone(), format!("endpoint {}: {}", name, problem));
+                    }
+                }
+            }
+            if config.health_check.is_some() || config.connection.is_some() {
+                let connection = config.connection().or(&defaults.connection);
+                for problem in config.health_check().or(&defaults.health_check).problems(&connection) {
+                    if !default_health_check_problems.contains(&problem) {
+                        problems.add(span.clone(), format!("endpoint {}: {}", name, problem));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// An endpoint registered by a counter_service instance in Redis. It carries its address only: connection, TLS and
+// health check settings always come from `endpoints.toml`, whoever can write the registry must not pick the files the
+// load balancer reads or where it sends its api key.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisteredEndpointConfig {
+    name: String,
+    ip: Option<IpAddr>,
+    host: Option<String>,
+    port: Option<u16>,
+    weight: Option<u8>,
+}
+
+impl From<RegisteredEndpointConfig> for EndpointConfig {
+    fn from(registered: RegisteredEndpointConfig) -> Self {
+        EndpointConfig {
+            name: registered.name,
+            ip: registered.ip,
+            host: registered.host,
+            srv: None,
+            port: registered.port,
+            weight: registered.weight,
+            connection: None,
+            health_check: None,
+        }
     }
 }
 
 impl EndpointConfig {
+    // only meaningful for endpoints addressed by ip, see `Discovery` for the others
     pub fn get_socket_addr(&self) -> SocketAddr {
-        SocketAddr::new((self.ip).into(), self.port)
+        SocketAddr::new(self.ip().unwrap_or(Ipv4Addr::UNSPECIFIED.into()), self.port.unwrap_or_default())
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        // a `host` written as an ip literal needs no lookup
+        self.ip.or_else(|| self.host.as_ref().and_then(|host| host.parse().ok()))
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
     }
 
-    #[allow(dead_code)]
-    pub fn ip(&self) -> Ipv4Addr {
-        self.ip
+    pub fn srv(&self) -> Option<&str> {
+        self.srv.as_deref()
     }
 
-    #[allow(dead_code)]
-    pub fn port(&self) -> u16 {
+    pub fn port(&self) -> Option<u16> {
         self.port
     }
 
+    // a concrete endpoint discovered from this config, named after its address
+    pub fn resolved(&self, addr: SocketAddr) -> EndpointConfig {
+        EndpointConfig {
+            name: format!("{}@{}", self.name, addr),
+            ip: Some(addr.ip()),
+            host: None,
+            srv: None,
+            port: Some(addr.port()),
+            weight: self.weight,
+            connection: self.connection.clone(),
+            health_check: self.health_check.clone(),
+        }
+    }
+
+    // problems of the endpoint on its own, whether configured or registered
+    fn problems(&self, strategy: &str) -> Vec<String> {
+        let name = &self.name;
+        let mut problems = vec![];
+        if name.is_empty() {
+            problems.push("endpoint name should not be empty".to_string());
+        }
+        for problem in self.address_problems() {
+            problems.push(format!("endpoint {}: {}", name, problem));
+        }
+        match self.weight {
+            Some(weight) if weight == 0 || weight > MAX_WEIGHT => {
+                problems.push(format!("endpoint {}: weight {} should be in the range 1..={}", name, weight, MAX_WEIGHT));
+            }
+            None if strategy == WEIGHTED_ROUND_ROBIN => {
+                problems.push(format!("endpoint {}: weight is required by {}", name, WEIGHTED_ROUND_ROBIN));
+            }
+            _ => {}
+        }
+        problems
+    }
+
+    fn address_problems(&self) -> Vec<&'static str> {
+        let mut problems = vec![];
+        let addressed = [self.ip.is_some(), self.host.is_some(), self.srv.is_some()];
+        if addressed.iter().filter(|set| **set).count() != 1 {
+            problems.push("exactly one of ip, host or srv is required");
+        }
+        if self.srv.is_none() && self.port.is_none() {
+            problems.push("port is required");
+        }
+        problems
+    }
+
+    // identifies the upstream an endpoint points to, whether or not it needs resolving
+    fn address_key(&self) -> Option<String> {
+        match (self.ip(), &self.host, &self.srv) {
+            (Some(ip), _, _) => Some(SocketAddr::new(ip, self.port?).to_string()),
+            (None, Some(host), _) => Some(format!("{}:{}", host, self.port?)),
+            (None, None, Some(srv)) => Some(srv.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn with_defaults(mut self, defaults: &EndpointDefaults) -> EndpointConfig {
+        self.connection = Some(self.connection().or(&defaults.connection));
+        self.health_check = Some(self.health_check().or(&defaults.health_check));
+        self
+    }
+
+    pub fn connection(&self) -> ConnectionConfig {
+        self.connection.clone().unwrap_or_default()
+    }
+
+    pub fn health_check(&self) -> HealthCheckConfig {
+        self.health_check.clone().unwrap_or_default()
+    }
+
     pub fn name(&self) -> String {
         self.name.clone()
     }
@@ -82,12 +386,267 @@ impl EndpointConfig {
     }
 }
 
+impl ConnectionConfig {
+    // fields set here win over `defaults`
+    fn or(self, defaults: &ConnectionConfig) -> ConnectionConfig {
+        ConnectionConfig {
+            connect_timeout_ms: self.connect_timeout_ms.or(defaults.connect_timeout_ms),
+            request_timeout_ms: self.request_timeout_ms.or(defaults.request_timeout_ms),
+            health_check_timeout_ms: self.health_check_timeout_ms.or(defaults.health_check_timeout_ms),
+            tcp_keepalive_secs: self.tcp_keepalive_secs.or(defaults.tcp_keepalive_secs),
+            max_in_flight: self.max_in_flight.or(defaults.max_in_flight),
+            concurrency_limit: self.concurrency_limit.or(defaults.concurrency_limit),
+            initial_stream_window_size: self.initial_stream_window_size.or(defaults.initial_stream_window_size),
+            initial_connection_window_size: self.initial_connection_window_size.or(defaults.initial_connection_window_size),
+            keepalive_interval_secs: self.keepalive_interval_secs.or(defaults.keepalive_interval_secs),
+            keepalive_timeout_secs: self.keepalive_timeout_secs.or(defaults.keepalive_timeout_secs),
+            keepalive_while_idle: self.keepalive_while_idle.or(defaults.keepalive_while_idle),
+            tls_ca_cert: self.tls_ca_cert.or_else(|| defaults.tls_ca_cert.clone()),
+            tls_cert: self.tls_cert.or_else(|| defaults.tls_cert.clone()),
+            tls_key: self.tls_key.or_else(|| defaults.tls_key.clone()),
+            tls_domain_name: self.tls_domain_name.or_else(|| defaults.tls_domain_name.clone()),
+            api_key: self.api_key.or_else(|| defaults.api_key.clone()),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let positive = [
+            ("connect_timeout_ms", self.connect_timeout_ms),
+            ("request_timeout_ms", self.request_timeout_ms),
+            ("health_check_timeout_ms", self.health_check_timeout_ms),
+            ("tcp_keepalive_secs", self.tcp_keepalive_secs),
+            ("keepalive_interval_secs", self.keepalive_interval_secs),
+            ("keepalive_timeout_secs", self.keepalive_timeout_secs),
+            ("concurrency_limit", self.concurrency_limit.map(|limit| limit as u64)),
+            ("max_in_flight", self.max_in_flight.map(|limit| limit as u64)),
+        ];
+        for (field, _) in positive.iter().filter(|(_, value)| *value == Some(0)) {
+            problems.push(format!("{} should be positive", field));
+        }
+        for (field, size) in [
+            ("initial_stream_window_size", self.initial_stream_window_size),
+            ("initial_connection_window_size", self.initial_connection_window_size),
+        ] {
+            if size.is_some_and(|size| size > MAX_HTTP2_WINDOW_SIZE) {
+                problems.push(format!("{} should be at most {}", field, MAX_HTTP2_WINDOW_SIZE));
+            }
+        }
+        if self.keepalive_interval_secs.is_none() && (self.keepalive_timeout_secs.is_some() || self.keepalive_while_idle.is_some()) {
+            problems.push("keepalive_timeout_secs and keepalive_while_idle require keepalive_interval_secs".to_string());
+        }
+        let tls = [&self.tls_ca_cert, &self.tls_cert, &self.tls_key, &self.tls_domain_name];
+        if self.tls_ca_cert.is_none() && tls.iter().any(|field| field.is_some()) {
+            problems.push("tls_cert, tls_key and tls_domain_name require tls_ca_cert".to_string());
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            problems.push("tls_cert and tls_key should be set together".to_string());
+        }
+        for (field, path) in [("tls_ca_cert", &self.tls_ca_cert), ("tls_cert", &self.tls_cert), ("tls_key", &self.tls_key)] {
+            if let Some(path) = path.as_ref().filter(|path| !Path::new(path).is_file()) {
+                problems.push(format!("{} file {:?} not found", field, path));
+            }
+        }
+        if self.api_key.as_ref().is_some_and(|key| key.is_empty()) {
+            problems.push("api_key should not be empty".to_string());
+        }
+        problems
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout_ms.map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_millis)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        self.request_timeout_ms.map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_millis)
+    }
+
+    pub fn health_check_timeout(&self) -> Duration {
+        self.health_check_timeout_ms.map_or(DEFAULT_HEALTH_CHECK_TIMEOUT, Duration::from_millis)
+    }
+
+    pub fn tcp_keepalive(&self) -> Duration {
+        self.tcp_keepalive_secs.map_or(DEFAULT_TCP_KEEPALIVE, Duration::from_secs)
+    }
+
+    pub fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    pub fn concurrency_limit(&self) -> Option<usize> {
+        self.concurrency_limit
+    }
+
+    pub fn initial_stream_window_size(&self) -> Option<u32> {
+        self.initial_stream_window_size
+    }
+
+    pub fn initial_connection_window_size(&self) -> Option<u32> {
+        self.initial_connection_window_size
+    }
+
+    // HTTP/2 PING interval, keepalive pings are disabled when unset
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval_secs.map(Duration::from_secs)
+    }
+
+    pub fn keepalive_timeout(&self) -> Option<Duration> {
+        self.keepalive_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn keepalive_while_idle(&self) -> bool {
+        self.keepalive_while_idle.unwrap_or_default()
+    }
+
+    pub fn api_key(&self) -> Option<&str> {
+        self.api_key.as_deref()
+    }
+
+    // None for plaintext, TLS is enabled by `tls_ca_cert`
+    pub fn tls(&self) -> Option<EndpointTlsConfig> {
+        self.tls_ca_cert.as_ref().map(|ca_cert| EndpointTlsConfig {
+            ca_cert: ca_cert.clone(),
+            cert: self.tls_cert.clone(),
+            key: self.tls_key.clone(),
+            domain_name: self.tls_domain_name.clone(),
+        })
+    }
+}
+
+impl EndpointTlsConfig {
+    pub fn ca_cert(&self) -> &str {
+        &self.ca_cert
+    }
+
+    pub fn cert(&self) -> Option<&str> {
+        self.cert.as_deref()
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    pub fn domain_name(&self) -> Option<&str> {
+        self.domain_name.as_deref()
+    }
+}
+
+impl HealthCheckConfig {
+    // fields set here win over `defaults`
+    fn or(self, defaults: &HealthCheckConfig) -> HealthCheckConfig {
+        HealthCheckConfig {
+            interval_ms: self.interval_ms.or(defaults.interval_ms),
+            jitter_ms: self.jitter_ms.or(defaults.jitter_ms),
+            rise: self.rise.or(defaults.rise),
+            fall: self.fall.or(defaults.fall),
+            canary_file: self.canary_file.or_else(|| defaults.canary_file.clone()),
+            canary_word: self.canary_word.or_else(|| defaults.canary_word.clone()),
+            expected_count: self.expected_count.or(defaults.expected_count),
+            timeout_ms: self.timeout_ms.or(defaults.timeout_ms),
+        }
+    }
+
+    // timeouts are checked against the interval, the health service one comes from `connection`
+    fn problems(&self, connection: &ConnectionConfig) -> Vec<String> {
+        let mut problems = vec![];
+        let positive = [
+            ("interval_ms", self.interval_ms),
+            ("rise", self.rise.map(u64::from)),
+            ("fall", self.fall.map(u64::from)),
+            ("timeout_ms", self.timeout_ms),
+        ];
+        for (field, _) in positive.iter().filter(|(_, value)| *value == Some(0)) {
+            problems.push(format!("{} should be positive", field));
+        }
+        let interval = self.interval();
+        if !interval.is_zero() && self.jitter() >= interval {
+            problems.push(format!("jitter_ms should be less than interval_ms {:?}", interval));
+        }
+        if connection.health_check_timeout() >= interval {
+            problems.push(format!("health_check_timeout_ms should be less than the health check interval {:?}", interval));
+        }
+        if self.timeout().is_some_and(|timeout| timeout >= interval) {
+            problems.push(format!("timeout_ms should be less than the health check interval {:?}", interval));
+        }
+        if self.canary_file.as_ref().is_some_and(|file| file.is_empty()) {
+            problems.push("canary_file should not be empty".to_string());
+        }
+        if self.canary_word.as_ref().is_some_and(|word| word.is_empty()) {
+            problems.push("canary_word should not be empty".to_string());
+        }
+        if self.canary_file.is_none() && (self.canary_word.is_some() || self.expected_count.is_some()) {
+            problems.push("canary_word and expected_count require canary_file".to_string());
+        }
+        if self.canary_file.is_some() && self.canary_word.is_none() {
+            problems.push("canary_file requires canary_word".to_string());
+        }
+        if self.expected_count.is_some_and(|count| count < 0) {
+            problems.push("expected_count should not be negative".to_string());
+        }
+        problems
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval_ms.map_or(DEFAULT_HEALTH_CHECK_INTERVAL, Duration::from_millis)
+    }
+
+    // defaults to a tenth of the interval
+    pub fn jitter(&self) -> Duration {
+        self.jitter_ms.map_or(self.interval() / 10, Duration::from_millis)
+    }
+
+    pub fn rise(&self) -> u32 {
+        self.rise.unwrap_or(DEFAULT_HEALTH_CHECK_RISE)
+    }
+
+    pub fn fall(&self) -> u32 {
+        self.fall.unwrap_or(DEFAULT_HEALTH_CHECK_FALL)
+    }
+
+    // the Count request of the deep check, None when disabled
+    pub fn canary_request(&self) -> Option<WordCountRequest> {
+        Some(WordCountRequest {
+            word: self.canary_word.clone()?,
+            file_name: self.canary_file.clone()?,
+            // the default corpus
+            corpus: String::new(),
+            lossy: false,
+        })
+    }
+
+    pub fn expected_count(&self) -> Option<i64> {
+        self.expected_count
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+}
+
+impl RegistryConfig {
+    pub fn redis_url(&self) -> &str {
+        &self.redis_url
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn refresh_interval(&self) -> Duration {
+        // 0 is rejected by `check`
+        self.refresh_interval_secs.filter(|secs| *secs > 0).map_or(DEFAULT_DISCOVERY_REFRESH_INTERVAL, Duration::from_secs)
+    }
+
+    pub fn nameserver(&self) -> Option<SocketAddr> {
+        self.nameserver
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::path::Path;
 
     use crate::consts::DEFAULT_STRATEGY;
     use crate::model::endpoints_config::EndpointPoolConfig;
+    use crate::tls::test_certs::TestCerts;
 
     use super::*;
 
@@ -95,27 +654,39 @@ mod test {
     fn test_load() {
         let pool_config = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_test.toml"), DEFAULT_STRATEGY);
         assert!(pool_config.is_ok());
-        let dataset = vec![
+        let dataset = [
             EndpointConfig {
                 name: "s1".to_string(),
-                ip: "192.168.1.1".parse().unwrap(),
-                port: 8080,
+                ip: Some("192.168.1.1".parse().unwrap()),
+                host: None,
+                srv: None,
+                port: Some(8080),
                 weight: Some(80),
+                connection: None,
+                health_check: None,
             },
             EndpointConfig {
                 name: "s2".to_string(),
-                ip: "192.168.1.2".parse().unwrap(),
-                port: 8081,
+                ip: Some("192.168.1.2".parse().unwrap()),
+                host: None,
+                srv: None,
+                port: Some(8081),
                 weight: Some(10),
+                connection: None,
+                health_check: None,
             },
             EndpointConfig {
                 name: "s3".to_string(),
-                ip: "192.168.1.3".parse().unwrap(),
-                port: 8082,
+                ip: Some("192.168.1.3".parse().unwrap()),
+                host: None,
+                srv: None,
+                port: Some(8082),
                 weight: Some(10),
+                connection: None,
+                health_check: None,
             },
         ];
-        let server_configs = pool_config.unwrap().endpoints;
+        let server_configs = pool_config.unwrap().endpoint_configs();
         assert_eq!(dataset.len(), server_configs.len());
         for (i, config) in server_configs.iter().enumerate() {
             assert_eq!(config.ip, dataset[i].ip);
@@ -125,8 +696,205 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_load_discovery() {
+        let pool_config = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_discovery_test.toml"), DEFAULT_STRATEGY).unwrap();
+        let discovery = pool_config.discovery();
+        assert_eq!(discovery.refresh_interval(), Duration::from_secs(10));
+        assert_eq!(discovery.nameserver(), Some("127.0.0.1:5353".parse().unwrap()));
+        assert_eq!(pool_config.registry().unwrap().redis_url(), "redis://127.0.0.1:6379");
+
+        let configs = pool_config.endpoint_configs();
+        assert_eq!(configs[0].ip(), Some("fd00::10".parse().unwrap()));
+        assert_eq!(configs[0].get_socket_addr(), "[fd00::10]:50051".parse().unwrap());
+        assert_eq!(configs[1].ip(), None);
+        assert_eq!(configs[1].host(), Some("counter.local"));
+        assert_eq!(configs[1].port(), Some(50051));
+        assert_eq!(configs[2].srv(), Some("_grpc._tcp.counter.local"));
+        let resolved = configs[1].resolved("10.0.0.1:50051".parse().unwrap());
+        assert_eq!(resolved.name(), "dns@10.0.0.1:50051");
+        assert_eq!(resolved.get_socket_addr(), "10.0.0.1:50051".parse().unwrap());
+        assert_eq!(resolved.weight(), Some(20));
+    }
+
+    #[test]
+    fn test_load_connection() {
+        let configs = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_connection_test.toml"), DEFAULT_STRATEGY)
+            .unwrap()
+            .endpoint_configs();
+        // defaults from [connection]
+        let connection = configs[0].connection();
+        assert_eq!(connection.connect_timeout(), Duration::from_millis(200));
+        assert_eq!(connection.request_timeout(), Duration::from_secs(5));
+        assert_eq!(connection.health_check_timeout(), DEFAULT_HEALTH_CHECK_TIMEOUT);
+        assert_eq!(connection.keepalive_interval(), Some(Duration::from_secs(20)));
+        assert!(connection.keepalive_while_idle());
+        assert_eq!(connection.concurrency_limit(), None);
+        // per-endpoint overrides
+        let connection = configs[1].connection();
+        assert_eq!(connection.connect_timeout(), Duration::from_millis(200));
+        assert_eq!(connection.request_timeout(), Duration::from_secs(30));
+        assert_eq!(connection.concurrency_limit(), Some(64));
+        assert_eq!(connection.max_in_flight(), Some(32));
+        assert_eq!(connection.initial_stream_window_size(), Some(1048576));
+        // resolved endpoints keep the connection of their config
+        assert_eq!(configs[1].resolved("10.0.0.1:50051".parse().unwrap()).connection(), connection);
+    }
+
+    #[test]
+    fn test_load_health_check() {
+        let configs = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_health_check_test.toml"), DEFAULT_STRATEGY)
+            .unwrap()
+            .endpoint_configs();
+        // defaults from [health_check]
+        let health_check = configs[0].health_check();
+        let canary = health_check.canary_request().unwrap();
+        assert_eq!((canary.word.as_str(), canary.file_name.as_str()), ("canary", "canary.txt"));
+        assert_eq!(health_check.expected_count(), Some(1));
+        assert_eq!(health_check.timeout(), None);
+        assert_eq!(health_check.interval(), Duration::from_secs(1));
+        assert_eq!(health_check.jitter(), Duration::from_millis(100));
+        assert_eq!((health_check.rise(), health_check.fall()), (3, DEFAULT_HEALTH_CHECK_FALL));
+        // overridden by [endpoints.health_check]
+        let health_check = configs[1].health_check();
+        assert!(health_check.canary_request().is_some());
+        assert_eq!(health_check.expected_count(), Some(2));
+        assert_eq!(health_check.timeout(), Some(Duration::from_millis(300)));
+        assert_eq!(health_check.jitter(), Duration::ZERO);
+        assert_eq!((health_check.rise(), health_check.fall()), (3, 1));
+
+        let configs = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_test.toml"), DEFAULT_STRATEGY)
+            .unwrap()
+            .endpoint_configs();
+        assert!(configs[0].health_check().canary_request().is_none());
+    }
+
+    const S1: &str = "[[endpoints]]\nname = \"s1\"\nip = \"192.168.1.1\"\nport = 8080\n";
+
+    fn check(content: &str, strategy: &str) -> Result<()> {
+        let config: EndpointPoolConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("endpoints.toml"), content);
+        config.check(strategy, &mut problems);
+        problems.into_result()
+    }
+
+    #[test]
+    fn test_check_connection() {
+        assert!(check(&format!("{S1}[connection]\nrequest_timeout_ms = 1000"), DEFAULT_STRATEGY).is_ok());
+        assert!(check(&format!("{S1}[connection]\nconnect_timeout_ms = 0"), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[connection]\nhealth_check_timeout_ms = 1000"), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[connection]\ninitial_connection_window_size = 4294967295"), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[connection]\nkeepalive_while_idle = true"), DEFAULT_STRATEGY).is_err());
+        let err = check(&format!("{S1}[endpoints.connection]\nconcurrency_limit = 0"), DEFAULT_STRATEGY).unwrap_err();
+        assert!(err.to_string().contains("endpoints.toml:1: endpoint s1: concurrency_limit should be positive"));
+    }
+
+    #[test]
+    fn test_check_tls() {
+        let certs = TestCerts::generate("endpoints-config");
+        let tls = format!("tls_ca_cert = {:?}\ntls_cert = {:?}\ntls_key = {:?}", certs.path("ca.pem"), certs.path("client.pem"), certs.path("client.key"));
+        assert!(check(&format!("{S1}[connection]\n{tls}"), DEFAULT_STRATEGY).is_ok());
+
+        let err = check(&format!("{S1}[connection]\ntls_cert = \"client.pem\""), DEFAULT_STRATEGY).unwrap_err().to_string();
+        assert!(err.contains("endpoints.toml:5: [connection]: tls_cert, tls_key and tls_domain_name require tls_ca_cert"));
+        assert!(err.contains("endpoints.toml:5: [connection]: tls_cert and tls_key should be set together"));
+        assert!(err.contains("endpoints.toml:5: [connection]: tls_cert file \"client.pem\" not found"));
+
+        // fields of the endpoint's [endpoints.connection] win over the pool's
+        let config: EndpointPoolConfig = toml::from_str(&format!("{S1}[endpoints.connection]\ntls_domain_name = \"server1\"\n[connection]\n{tls}")).unwrap();
+        let tls = config.endpoint_configs()[0].connection().tls().unwrap();
+        assert_eq!(tls.ca_cert(), certs.path("ca.pem"));
+        assert_eq!((tls.key(), tls.domain_name()), (Some(certs.path("client.key").as_str()), Some("server1")));
+    }
+
+    #[test]
+    fn test_api_key() {
+        let config: EndpointPoolConfig = toml::from_str(&format!("{S1}[connection]\napi_key = \"lb-key\"")).unwrap();
+        assert_eq!(config.endpoint_configs()[0].connection().api_key(), Some("lb-key"));
+        let err = check(&format!("{S1}[connection]\napi_key = \"\""), DEFAULT_STRATEGY).unwrap_err().to_string();
+        assert!(err.contains("endpoints.toml:5: [connection]: api_key should not be empty"));
+    }
+
+    #[test]
+    fn test_check_health_check() {
+        assert!(check(&format!("{S1}[health_check]\ncanary_file = \"canary.txt\"\ncanary_word = \"canary\""), DEFAULT_STRATEGY).is_ok());
+        assert!(check(&format!("{S1}[health_check]\ncanary_file = \"canary.txt\""), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[health_check]\ncanary_word = \"canary\""), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[health_check]\ncanary_file = \"\"\ncanary_word = \"canary\""), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[health_check]\ntimeout_ms = 500"), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[health_check]\ninterval_ms = 2000\ntimeout_ms = 500"), DEFAULT_STRATEGY).is_ok());
+        assert!(check(&format!("{S1}[health_check]\nrise = 0"), DEFAULT_STRATEGY).is_err());
+        assert!(check(&format!("{S1}[health_check]\njitter_ms = 500"), DEFAULT_STRATEGY).is_err());
+        // the health service timeout of [connection] is checked against the interval of [health_check]
+        assert!(check(&format!("{S1}[health_check]\ninterval_ms = 2000\n[connection]\nhealth_check_timeout_ms = 1000"), DEFAULT_STRATEGY).is_ok());
+        let err = check(&format!("{S1}[endpoints.health_check]\nexpected_count = -1"), DEFAULT_STRATEGY).unwrap_err().to_string();
+        assert!(err.contains("endpoints.toml:1: endpoint s1: canary_word and expected_count require canary_file"));
+        assert!(err.contains("endpoints.toml:1: endpoint s1: expected_count should not be negative"));
+    }
+
+    #[test]
+    fn test_check() {
+        let path = Path::new("src/config_test/endpoints_check_test.toml");
+        let err = EndpointPoolConfig::load(path, WEIGHTED_ROUND_ROBIN).unwrap_err().to_string();
+        let expected = [
+            "endpoints_check_test.toml:7: endpoint s1: duplicate name, first defined at line 1",
+            "endpoints_check_test.toml:7: endpoint s1: duplicate address 192.168.1.1:8080, already used by endpoint s1",
+            "endpoints_check_test.toml:13: endpoint s3: weight 0 should be in the range 1..=100",
+            "endpoints_check_test.toml:19: endpoint s4: weight is required by WeightedRoundRobin",
+            "endpoints_check_test.toml:24: endpoint s5: exactly one of ip, host or srv is required",
+            "endpoints_check_test.toml:31: endpoint s6: port is required",
+        ];
+        for problem in expected {
+            assert!(err.contains(problem), "missing problem: {}\n{}", problem, err);
+        }
+        assert_eq!(err.lines().count(), expected.len() + 1);
+
+        assert!(check("endpoints = []", DEFAULT_STRATEGY).is_err());
+        assert!(check("endpoints = []\n[registry]\nredis_url = \"redis://127.0.0.1:6379\"", DEFAULT_STRATEGY).is_ok());
+    }
+
+    #[test]
+    fn test_check_discovery() {
+        assert!(check(&format!("{S1}[discovery]\nrefresh_interval_secs = 10"), DEFAULT_STRATEGY).is_ok());
+        let err = check(&format!("{S1}[discovery]\nrefresh_interval_secs = 0"), DEFAULT_STRATEGY).unwrap_err().to_string();
+        assert!(err.contains("endpoints.toml:5: [discovery]: refresh_interval_secs should be positive"), "{}", err);
+        let config: EndpointPoolConfig = toml::from_str(&format!("{S1}[discovery]\nrefresh_interval_secs = 0")).unwrap();
+        assert_eq!(config.discovery().refresh_interval(), DEFAULT_DISCOVERY_REFRESH_INTERVAL);
+    }
+
+    #[test]
+    fn test_registered() {
+        let config: EndpointPoolConfig = toml::from_str(&format!("{S1}[connection]\nrequest_timeout_ms = 1000")).unwrap();
+        let registered: Vec<EndpointConfig> = [
+            r#"{"name":"s2","ip":"192.168.1.2","port":8080,"weight":20}"#,
+            r#"{"name":"s3","ip":"192.168.1.3","port":8080,"weight":0}"#,
+            r#"{"name":"s4","ip":"192.168.1.4","port":8080}"#,
+            r#"{"name":"s1","ip":"192.168.1.5","port":8080,"weight":20}"#,
+            r#"{"name":"s6","ip":"192.168.1.1","port":8080,"weight":20}"#,
+            r#"{"name":"s2","ip":"192.168.1.7","port":8080,"weight":20}"#,
+            r#"{"name":"s8","port":8080,"weight":20}"#,
+        ].into_iter().map(|entry| serde_json::from_str(entry).unwrap()).collect();
+        let configs = config.registered(&registered, WEIGHTED_ROUND_ROBIN);
+        assert_eq!(configs.iter().map(EndpointConfig::name).collect::<Vec<_>>(), ["s2"]);
+        assert_eq!(configs[0].connection().request_timeout(), Duration::from_millis(1000));
+        // a missing weight is only a problem for weighted round robin
+        assert_eq!(config.registered(&registered, DEFAULT_STRATEGY).len(), 2);
+    }
+
+    #[test]
+    fn test_refreshed() {
+        let config: EndpointPoolConfig = toml::from_str(S1).unwrap();
+        assert!(!config.refreshed());
+        let config: EndpointPoolConfig = toml::from_str("[[endpoints]]\nname = \"s1\"\nhost = \"10.0.0.1\"\nport = 8080").unwrap();
+        assert!(!config.refreshed());
+        let config: EndpointPoolConfig = toml::from_str("[[endpoints]]\nname = \"s1\"\nhost = \"localhost\"\nport = 8080").unwrap();
+        assert!(config.refreshed());
+        let config: EndpointPoolConfig = toml::from_str(&format!("{S1}[registry]\nredis_url = \"redis://127.0.0.1:6379\"")).unwrap();
+        assert!(config.refreshed());
+    }
+
     #[test]
     fn test_load_failed() {
-        assert!(EndpointPoolConfig::load(Path::new("../config_test/endpoints_test_invalid.toml"), DEFAULT_STRATEGY).is_err());
+        assert!(EndpointPoolConfig::load(Path::new("src/config_test/endpoints_test_invalid.toml"), DEFAULT_STRATEGY).is_err());
     }
 }
\ No newline at end of file
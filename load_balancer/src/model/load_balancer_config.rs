@@ -1,28 +1,181 @@
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use clap::Args;
+use clap::builder::PossibleValuesParser;
 use serde::Deserialize;
+use toml::Spanned;
 
-use crate::consts::DEFAULT_STRATEGY;
+use crate::consts::{DEFAULT_HEDGE_BUDGET_PERCENT, DEFAULT_STICKY_SESSION_TTL, DEFAULT_STRATEGY, STRATEGIES};
+use crate::model::config_check::ConfigProblems;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct LBConfig {
     strategy: Option<String>,
+    sticky_session_ttl_secs: Option<u64>,
+    hedging: Option<HedgingConfig>,
+    #[serde(skip)]
+    overrides: LBOverrides,
+}
+
+// Hedged requests, disabled unless `delay_ms` or `percentile` is set. With `percentile`, the hedge is sent once
+// the request is slower than that percentile of recent requests, and after `delay_ms` until enough are recorded.
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct HedgingConfig {
+    delay_ms: Option<u64>,
+    percentile: Option<f64>,
+    // hedges allowed in percent of requests, defaults to 10
+    budget_percent: Option<u32>,
+}
+
+/// Command line and environment overrides of `load_balancer.toml`, every field that is set wins over the file.
+#[derive(Default, Debug, Args, Clone, PartialEq)]
+pub struct LBOverrides {
+    /// Load balancing strategy
+    #[arg(long, env = "LB_STRATEGY", value_parser = PossibleValuesParser::new(STRATEGIES))]
+    strategy: Option<String>,
+    /// Client affinity lifetime of the StickySession strategy
+    #[arg(long, env = "LB_STICKY_SESSION_TTL_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    sticky_session_ttl_secs: Option<u64>,
+    /// Delay after which a request is hedged to a second endpoint, enables hedging
+    #[arg(long, env = "LB_HEDGE_DELAY_MS", value_parser = clap::value_parser!(u64).range(1..))]
+    hedge_delay_ms: Option<u64>,
+    /// Latency percentile of recent requests after which a request is hedged, enables hedging
+    #[arg(long, env = "LB_HEDGE_PERCENTILE", value_parser = parse_percentile)]
+    hedge_percentile: Option<f64>,
+    /// Hedges allowed in percent of requests
+    #[arg(long, env = "LB_HEDGE_BUDGET_PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+    hedge_budget_percent: Option<u32>,
+}
+
+fn parse_percentile(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(value) if value > 0.0 && value < 100.0 => Ok(value),
+        Ok(_) => Err("should be between 0 and 100, exclusive".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// LBConfig is also read from JSON by the admin API, which has no spans, so `check` parses the file again
+#[derive(Deserialize)]
+struct LBConfigSpans {
+    strategy: Option<Spanned<String>>,
+    sticky_session_ttl_secs: Option<Spanned<u64>>,
+    hedging: Option<Spanned<HedgingConfig>>,
 }
 
 impl LBConfig {
-    pub fn load(path: &Path) -> Result<Self> {
-        let config_content = fs::read_to_string(path)?;
-        toml::from_str(&config_content).context("load server.toml failed")
+    pub fn load(path: &Path, overrides: &LBOverrides) -> Result<Self> {
+        let config_content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read load balancer config file:{:?}", path))?;
+        Self::parse(&config_content, ConfigProblems::new(path, &config_content), overrides)
+    }
+
+    pub fn parse(content: &str, mut problems: ConfigProblems, overrides: &LBOverrides) -> Result<Self> {
+        let mut config: LBConfig = toml::from_str(content)
+            .with_context(|| format!("failed to parse load balancer config {}", problems.source()))?;
+        // overrides are validated by the command line parser, only the file needs a check
+        config.overrides = overrides.clone();
+
+        Self::check(content, &mut problems, overrides);
+        problems.into_result()?;
+
+        Ok(config)
+    }
+
+    fn check(content: &str, problems: &mut ConfigProblems, overrides: &LBOverrides) {
+        let Ok(spans) = toml::from_str::<LBConfigSpans>(content) else {
+            return;
+        };
+        if let Some(strategy) = spans.strategy {
+            if !STRATEGIES.contains(&strategy.get_ref().as_str()) {
+                problems.add(Some(strategy.span()), format!("unknown strategy {:?}, should be one of {}", strategy.get_ref(), STRATEGIES.join(", ")));
+            }
+        }
+        if let Some(ttl) = spans.sticky_session_ttl_secs {
+            if *ttl.get_ref() == 0 {
+                problems.add(Some(ttl.span()), "sticky_session_ttl_secs should be positive");
+            }
+        }
+        if let Some(hedging) = spans.hedging {
+            let span = Some(hedging.span());
+            let config = hedging.get_ref();
+            if config.delay_ms == Some(0) {
+                problems.add(span.clone(), "[hedging]: delay_ms should be positive");
+            }
+            if config.percentile.is_some_and(|percentile| percentile <= 0.0 || percentile >= 100.0) {
+                problems.add(span.clone(), "[hedging]: percentile should be between 0 and 100, exclusive");
+            }
+            if config.budget_percent.is_some_and(|budget| budget == 0 || budget > 100) {
+                problems.add(span.clone(), "[hedging]: budget_percent should be in the range 1..=100");
+            }
+            let enabled = [config.delay_ms.is_some(), config.percentile.is_some(), overrides.hedge_delay_ms.is_some(), overrides.hedge_percentile.is_some()];
+            if !enabled.contains(&true) {
+                problems.add(span, "[hedging]: delay_ms or percentile is required");
+            }
+        }
     }
 
     pub fn strategy(&self) -> String {
-        self.strategy.clone().unwrap_or_else(|| {
+        self.overrides.strategy.clone().or_else(|| self.strategy.clone()).unwrap_or_else(|| {
             tracing::error!("strategy is None, using default value");
             DEFAULT_STRATEGY.to_string()
         })
     }
+
+    pub fn sticky_session_ttl(&self) -> Duration {
+        self.overrides.sticky_session_ttl_secs
+            .or(self.sticky_session_ttl_secs)
+            .map_or(DEFAULT_STICKY_SESSION_TTL, Duration::from_secs)
+    }
+
+    // None when hedging is disabled
+    pub fn hedging(&self) -> Option<HedgingConfig> {
+        let file = self.hedging.clone().unwrap_or_default();
+        let config = HedgingConfig {
+            delay_ms: self.overrides.hedge_delay_ms.or(file.delay_ms),
+            percentile: self.overrides.hedge_percentile.or(file.percentile),
+            budget_percent: self.overrides.hedge_budget_percent.or(file.budget_percent),
+        };
+        (config.delay_ms.is_some() || config.percentile.is_some()).then_some(config)
+    }
+}
+
+impl HedgingConfig {
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay_ms.map(Duration::from_millis)
+    }
+
+    pub fn percentile(&self) -> Option<f64> {
+        self.percentile
+    }
+
+    // fraction of a hedge each request earns
+    pub fn budget_ratio(&self) -> f64 {
+        self.budget_percent.unwrap_or(DEFAULT_HEDGE_BUDGET_PERCENT) as f64 / 100.0
+    }
+}
+
+// the effective values in the format of `load_balancer.toml`
+impl fmt::Display for LBConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "strategy = \"{}\"", self.strategy())?;
+        write!(f, "sticky_session_ttl_secs = {}", self.sticky_session_ttl().as_secs())?;
+        let Some(hedging) = self.hedging() else {
+            return Ok(());
+        };
+        write!(f, "\n\n[hedging]")?;
+        if let Some(delay) = hedging.delay() {
+            write!(f, "\ndelay_ms = {}", delay.as_millis())?;
+        }
+        if let Some(percentile) = hedging.percentile() {
+            write!(f, "\npercentile = {}", percentile)?;
+        }
+        write!(f, "\nbudget_percent = {}", hedging.budget_percent.unwrap_or(DEFAULT_HEDGE_BUDGET_PERCENT))
+    }
 }
 
 #[cfg(test)]
@@ -33,9 +186,61 @@ mod test {
 
     #[test]
     fn test_load() {
-        let lb_config = LBConfig::load(Path::new("src/config_test/load_balancer_test.toml"));
+        let lb_config = LBConfig::load(Path::new("src/config_test/load_balancer_test.toml"), &LBOverrides::default());
         assert!(lb_config.is_ok());
         let lb_config = lb_config.unwrap();
         assert_eq!(lb_config.strategy(), "WeightedRoundRobin");
+        assert_eq!(lb_config.sticky_session_ttl(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = LBOverrides { strategy: Some("HashByRequest".to_string()), sticky_session_ttl_secs: None, ..Default::default() };
+        let lb_config = LBConfig::load(Path::new("src/config_test/load_balancer_test.toml"), &overrides).unwrap();
+        assert_eq!(lb_config.strategy(), "HashByRequest");
+        assert_eq!(lb_config.sticky_session_ttl(), Duration::from_secs(60));
+        assert_eq!(lb_config.to_string(), "strategy = \"HashByRequest\"\nsticky_session_ttl_secs = 60");
+        assert_eq!(lb_config.hedging(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hedging() {
+        let content = "strategy = \"RoundRobin\"\n\n[hedging]\npercentile = 95\ndelay_ms = 50";
+        let problems = ConfigProblems::new(Path::new("load_balancer.toml"), content);
+        let lb_config = LBConfig::parse(content, problems, &LBOverrides::default()).unwrap();
+        let hedging = lb_config.hedging().unwrap();
+        assert_eq!(hedging.delay(), Some(Duration::from_millis(50)));
+        assert_eq!(hedging.percentile(), Some(95.0));
+        assert_eq!(hedging.budget_ratio(), 0.1);
+
+        let overrides = LBOverrides { hedge_budget_percent: Some(5), ..Default::default() };
+        let problems = ConfigProblems::new(Path::new("load_balancer.toml"), content);
+        let lb_config = LBConfig::parse(content, problems, &overrides).unwrap();
+        assert_eq!(lb_config.hedging().unwrap().budget_ratio(), 0.05);
+        assert!(lb_config.to_string().ends_with("[hedging]\ndelay_ms = 50\npercentile = 95\nbudget_percent = 5"));
+
+        // enabled by an override alone
+        let overrides = LBOverrides { hedge_delay_ms: Some(20), ..Default::default() };
+        let problems = ConfigProblems::new(Path::new("load_balancer.toml"), "");
+        let lb_config = LBConfig::parse("", problems, &overrides).unwrap();
+        assert_eq!(lb_config.hedging().unwrap().delay(), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn test_check() {
+        let content = "strategy = \"LeastConn\"\nsticky_session_ttl_secs = 0\n\n[hedging]\npercentile = 100\nbudget_percent = 0";
+        let mut problems = ConfigProblems::new(Path::new("load_balancer.toml"), content);
+        LBConfig::check(content, &mut problems, &LBOverrides::default());
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("load_balancer.toml:1: unknown strategy \"LeastConn\""));
+        assert!(err.contains("load_balancer.toml:2: sticky_session_ttl_secs should be positive"));
+        assert!(err.contains("load_balancer.toml:4: [hedging]: percentile should be between 0 and 100, exclusive"));
+        assert!(err.contains("load_balancer.toml:4: [hedging]: budget_percent should be in the range 1..=100"));
+
+        let content = "[hedging]\nbudget_percent = 5";
+        let mut problems = ConfigProblems::new(Path::new("load_balancer.toml"), content);
+        LBConfig::check(content, &mut problems, &LBOverrides::default());
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("load_balancer.toml:1: [hedging]: delay_ms or percentile is required"));
+    }
+}
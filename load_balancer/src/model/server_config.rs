@@ -1,44 +1,230 @@
+use std::fmt;
 use std::fs;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use clap::Args;
 use serde::Deserialize;
+use toml::Spanned;
 
-use crate::consts::{DEFAULT_IP_ADDR, DEFAULT_METRICS_PORT, DEFAULT_PORT};
+use crate::consts::{DEFAULT_IP_ADDR, DEFAULT_METRICS_PORT, DEFAULT_PORT, DEFAULT_QUEUE_TIMEOUT, DEFAULT_SHUTDOWN_TIMEOUT};
+use crate::consts::{STATUS_FAILED, STATUS_FORBIDDEN, STATUS_RATE_LIMITED, STATUS_SERVER_BUSY, STATUS_UNAUTHORIZED};
 use crate::endpoint::word_counter::WordCountResponse;
+use crate::model::config_check::ConfigProblems;
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     ip: Option<Ipv4Addr>,
+    port: Option<Spanned<u16>>,
+    metrics_port: Option<Spanned<u16>>,
+    enable_fault_tolerance: Option<bool>,
+    admin_token: Option<String>,
+    // `auth.toml` of the tenants allowed to query, requests need no api key unless it is set
+    auth_policy: Option<Spanned<String>>,
+    shutdown_timeout_secs: Option<Spanned<u64>>,
+    rate_limit: Option<Spanned<RateLimitConfig>>,
+    concurrency: Option<Spanned<ConcurrencyConfig>>,
+    tls: Option<Spanned<ListenerTlsConfig>>,
+    #[serde(skip)]
+    overrides: ServerOverrides,
+}
+
+// Token buckets per client IP and per tenant, rate limiting is disabled unless `requests_per_sec` is set.
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    requests_per_sec: Option<u32>,
+    // requests a client may send at once, defaults to `requests_per_sec`
+    burst: Option<u32>,
+}
+
+// Global limit of requests processed at once, disabled unless `max_concurrent_requests` is set.
+// The limit is adaptive between `min_concurrent_requests` and `max_concurrent_requests` when
+// `latency_target_ms` is set, see `ConcurrencyLimiter`.
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct ConcurrencyConfig {
+    max_concurrent_requests: Option<usize>,
+    // requests waiting for the limit, defaults to `max_concurrent_requests`
+    max_queue: Option<usize>,
+    queue_timeout_ms: Option<u64>,
+    latency_target_ms: Option<u64>,
+    min_concurrent_requests: Option<usize>,
+}
+
+// TLS termination on the listener, disabled unless `cert` is set. Clients must present a certificate signed by
+// `client_ca` when it is set (mutual TLS).
+#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+pub struct ListenerTlsConfig {
+    cert: Option<String>,
+    key: Option<String>,
+    client_ca: Option<String>,
+}
+
+/// Command line and environment overrides of `server.toml`, every field that is set wins over the file.
+#[derive(Default, Debug, Args, Clone)]
+pub struct ServerOverrides {
+    /// IP address to bind the server to
+    #[arg(long, env = "LB_IP")]
+    ip: Option<Ipv4Addr>,
+    /// Port to listen for requests on
+    #[arg(long, env = "LB_PORT")]
     port: Option<u16>,
+    /// Port to serve metrics and the admin API on
+    #[arg(long, env = "LB_METRICS_PORT")]
     metrics_port: Option<u16>,
+    /// Enable health checks and failover
+    #[arg(long, env = "LB_ENABLE_FAULT_TOLERANCE")]
     enable_fault_tolerance: Option<bool>,
+    /// Token enabling the admin API
+    #[arg(long, env = "LB_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Tenants and api keys file, enables authentication
+    #[arg(long, env = "LB_AUTH_POLICY")]
+    auth_policy: Option<String>,
+    /// How long in-flight requests may take to finish on shutdown
+    #[arg(long, env = "LB_SHUTDOWN_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    shutdown_timeout_secs: Option<u64>,
+    /// Requests per second allowed per client IP and per tenant, enables rate limiting
+    #[arg(long, env = "LB_RATE_LIMIT_REQUESTS_PER_SEC", value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit_requests_per_sec: Option<u32>,
+    /// Requests a client IP, or a tenant, may send at once
+    #[arg(long, env = "LB_RATE_LIMIT_BURST", value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit_burst: Option<u32>,
+    /// Requests processed at once, enables concurrency limiting
+    #[arg(long, env = "LB_MAX_CONCURRENT_REQUESTS", value_parser = parse_positive)]
+    max_concurrent_requests: Option<usize>,
+    /// Requests waiting for the concurrency limit
+    #[arg(long, env = "LB_MAX_QUEUE")]
+    max_queue: Option<usize>,
+    /// How long a request may wait for the concurrency limit
+    #[arg(long, env = "LB_QUEUE_TIMEOUT_MS", value_parser = clap::value_parser!(u64).range(1..))]
+    queue_timeout_ms: Option<u64>,
+    /// Latency target of the adaptive concurrency limit
+    #[arg(long, env = "LB_LATENCY_TARGET_MS", value_parser = clap::value_parser!(u64).range(1..))]
+    latency_target_ms: Option<u64>,
+    /// Lower bound of the adaptive concurrency limit
+    #[arg(long, env = "LB_MIN_CONCURRENT_REQUESTS", value_parser = parse_positive)]
+    min_concurrent_requests: Option<usize>,
+    /// Certificate chain (PEM) of the listener, enables TLS
+    #[arg(long, env = "LB_TLS_CERT")]
+    tls_cert: Option<String>,
+    /// Private key (PEM) of the listener certificate
+    #[arg(long, env = "LB_TLS_KEY")]
+    tls_key: Option<String>,
+    /// CA certificate (PEM) client certificates must be signed by, enables mutual TLS
+    #[arg(long, env = "LB_TLS_CLIENT_CA")]
+    tls_client_ca: Option<String>,
+}
+
+fn parse_positive(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("should be positive".to_string()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(err.to_string()),
+    }
 }
 
 impl ServerConfig {
-    pub fn load(path: &Path) -> Result<Self> {
+    pub fn load(path: &Path, overrides: &ServerOverrides) -> Result<Self> {
         let config_content = fs::read_to_string(path)
             .with_context(|| format!("failed to read server config file:{:?}", path))?;
-        toml::from_str(&config_content)
-            .with_context(|| format!("failed to parse server config file:{:?}", path))
+        Self::parse(&config_content, ConfigProblems::new(path, &config_content), overrides)
+    }
+
+    pub fn parse(content: &str, mut problems: ConfigProblems, overrides: &ServerOverrides) -> Result<Self> {
+        let mut config: ServerConfig = toml::from_str(content)
+            .with_context(|| format!("failed to parse server config {}", problems.source()))?;
+        config.overrides = overrides.clone();
+
+        config.check(&mut problems);
+        problems.into_result()?;
+
+        Ok(config)
     }
+
+    fn check(&self, problems: &mut ConfigProblems) {
+        if self.port() == self.metrics_port() {
+            // there is no line to point at when either port comes from an override
+            let overridden = self.overrides.port.is_some() || self.overrides.metrics_port.is_some();
+            let span = self.metrics_port.as_ref().or(self.port.as_ref()).map(Spanned::span).filter(|_| !overridden);
+            problems.add(span, format!("port and metrics_port should differ, both are {}", self.port()));
+        }
+        if let Some(path) = self.auth_policy().filter(|path| !Path::new(path).is_file()) {
+            let span = self.auth_policy.as_ref().map(Spanned::span).filter(|_| self.overrides.auth_policy.is_none());
+            problems.add(span, format!("auth_policy file {:?} not found", path));
+        }
+        if let Some(timeout) = self.shutdown_timeout_secs.as_ref().filter(|timeout| *timeout.get_ref() == 0) {
+            problems.add(Some(timeout.span()), "shutdown_timeout_secs should be positive");
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            let span = Some(rate_limit.span());
+            let config = rate_limit.get_ref();
+            for (field, value) in [("requests_per_sec", config.requests_per_sec), ("burst", config.burst)] {
+                if value == Some(0) {
+                    problems.add(span.clone(), format!("[rate_limit]: {} should be positive", field));
+                }
+            }
+            if config.requests_per_sec.is_none() && self.overrides.rate_limit_requests_per_sec.is_none() {
+                problems.add(span, "[rate_limit]: requests_per_sec is required");
+            }
+        }
+        if let Some(concurrency) = &self.concurrency {
+            let span = Some(concurrency.span());
+            let config = concurrency.get_ref();
+            let positive = [
+                ("max_concurrent_requests", config.max_concurrent_requests.map(|value| value as u64)),
+                ("min_concurrent_requests", config.min_concurrent_requests.map(|value| value as u64)),
+                ("queue_timeout_ms", config.queue_timeout_ms),
+                ("latency_target_ms", config.latency_target_ms),
+            ];
+            for (field, _) in positive.iter().filter(|(_, value)| *value == Some(0)) {
+                problems.add(span.clone(), format!("[concurrency]: {} should be positive", field));
+            }
+            if config.max_concurrent_requests.is_none() && self.overrides.max_concurrent_requests.is_none() {
+                problems.add(span.clone(), "[concurrency]: max_concurrent_requests is required");
+            }
+        }
+        if let Some(concurrency) = self.concurrency() {
+            if concurrency.min_concurrent_requests() > concurrency.max_concurrent_requests() {
+                let span = self.concurrency.as_ref().map(Spanned::span);
+                problems.add(span, "[concurrency]: min_concurrent_requests should be at most max_concurrent_requests");
+            }
+        }
+        let tls = self.tls.as_ref().map(|tls| tls.get_ref().clone()).unwrap_or_default();
+        let tls_span = self.tls.as_ref().map(Spanned::span);
+        match self.tls() {
+            Some(config) => {
+                if config.key.is_none() {
+                    problems.add(tls_span.clone(), "[tls]: key is required");
+                }
+                for (field, path) in [("cert", &config.cert), ("key", &config.key), ("client_ca", &config.client_ca)] {
+                    if let Some(path) = path.as_ref().filter(|path| !Path::new(path).is_file()) {
+                        problems.add(tls_span.clone(), format!("[tls]: {} file {:?} not found", field, path));
+                    }
+                }
+            }
+            None if tls.key.is_some() || tls.client_ca.is_some() => problems.add(tls_span, "[tls]: cert is required"),
+            None => {}
+        }
+    }
+
     pub fn ip(&self) -> &Ipv4Addr {
-        self.ip.as_ref().unwrap_or_else(|| {
+        self.overrides.ip.as_ref().or(self.ip.as_ref()).unwrap_or_else(|| {
             tracing::error!("IP address is None, using default value");
             &DEFAULT_IP_ADDR
         })
     }
 
     pub fn port(&self) -> u16 {
-        self.port.unwrap_or_else(|| {
+        self.overrides.port.or(self.port.as_ref().map(|port| *port.get_ref())).unwrap_or_else(|| {
             tracing::error!("port is None, using default value");
             DEFAULT_PORT
         })
     }
 
     pub fn metrics_port(&self) -> u16 {
-        self.metrics_port.unwrap_or_else(|| {
+        self.overrides.metrics_port.or(self.metrics_port.as_ref().map(|port| *port.get_ref())).unwrap_or_else(|| {
             tracing::error!("metrics port is None, using default value");
             DEFAULT_METRICS_PORT
         })
@@ -53,7 +239,148 @@ impl ServerConfig {
     }
 
     pub fn fault_tolerance(&self) -> bool {
-        self.enable_fault_tolerance.unwrap_or_default()
+        self.overrides.enable_fault_tolerance.or(self.enable_fault_tolerance).unwrap_or_default()
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.overrides.shutdown_timeout_secs
+            .or(self.shutdown_timeout_secs.as_ref().map(|timeout| *timeout.get_ref()))
+            .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs)
+    }
+
+    // None when rate limiting is disabled
+    pub fn rate_limit(&self) -> Option<RateLimitConfig> {
+        let file = self.rate_limit.as_ref().map(|rate_limit| rate_limit.get_ref().clone()).unwrap_or_default();
+        let config = RateLimitConfig {
+            requests_per_sec: self.overrides.rate_limit_requests_per_sec.or(file.requests_per_sec),
+            burst: self.overrides.rate_limit_burst.or(file.burst),
+        };
+        config.requests_per_sec.map(|_| config)
+    }
+
+    // None when concurrency limiting is disabled
+    pub fn concurrency(&self) -> Option<ConcurrencyConfig> {
+        let file = self.concurrency.as_ref().map(|concurrency| concurrency.get_ref().clone()).unwrap_or_default();
+        let config = ConcurrencyConfig {
+            max_concurrent_requests: self.overrides.max_concurrent_requests.or(file.max_concurrent_requests),
+            max_queue: self.overrides.max_queue.or(file.max_queue),
+            queue_timeout_ms: self.overrides.queue_timeout_ms.or(file.queue_timeout_ms),
+            latency_target_ms: self.overrides.latency_target_ms.or(file.latency_target_ms),
+            min_concurrent_requests: self.overrides.min_concurrent_requests.or(file.min_concurrent_requests),
+        };
+        config.max_concurrent_requests.map(|_| config)
+    }
+
+    // None when the listener is plaintext
+    pub fn tls(&self) -> Option<ListenerTlsConfig> {
+        let file = self.tls.as_ref().map(|tls| tls.get_ref().clone()).unwrap_or_default();
+        let config = ListenerTlsConfig {
+            cert: self.overrides.tls_cert.clone().or(file.cert),
+            key: self.overrides.tls_key.clone().or(file.key),
+            client_ca: self.overrides.tls_client_ca.clone().or(file.client_ca),
+        };
+        config.cert.is_some().then_some(config)
+    }
+
+    // None when requests are not authenticated
+    pub fn auth_policy(&self) -> Option<String> {
+        self.overrides.auth_policy.clone().or_else(|| self.auth_policy.as_ref().map(|path| path.get_ref().clone()))
+    }
+
+    // admin API is disabled when no token is configured
+    pub fn admin_token(&self) -> Option<String> {
+        self.overrides.admin_token.clone().or_else(|| self.admin_token.clone()).filter(|token| !token.is_empty())
+    }
+}
+
+// the effective values in the format of `server.toml`, with the admin token redacted
+impl fmt::Display for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ip = \"{}\"", self.ip())?;
+        writeln!(f, "port = {}", self.port())?;
+        writeln!(f, "metrics_port = {}", self.metrics_port())?;
+        writeln!(f, "enable_fault_tolerance = {}", self.fault_tolerance())?;
+        writeln!(f, "shutdown_timeout_secs = {}", self.shutdown_timeout().as_secs())?;
+        match self.admin_token() {
+            Some(_) => writeln!(f, "admin_token = \"<redacted>\"")?,
+            None => writeln!(f, "# admin_token is not set")?,
+        }
+        match self.auth_policy() {
+            Some(path) => writeln!(f, "auth_policy = {:?}", path)?,
+            None => writeln!(f, "# auth_policy is not set")?,
+        }
+        match self.rate_limit() {
+            Some(rate_limit) => writeln!(f, "\n[rate_limit]\nrequests_per_sec = {}\nburst = {}", rate_limit.requests_per_sec(), rate_limit.burst())?,
+            None => writeln!(f, "# rate_limit is not set")?,
+        }
+        match self.concurrency() {
+            Some(concurrency) => {
+                write!(f, "\n[concurrency]\nmax_concurrent_requests = {}\nmax_queue = {}\nqueue_timeout_ms = {}",
+                       concurrency.max_concurrent_requests(), concurrency.max_queue(), concurrency.queue_timeout().as_millis())?;
+                if let Some(target) = concurrency.latency_target() {
+                    write!(f, "\nlatency_target_ms = {}\nmin_concurrent_requests = {}", target.as_millis(), concurrency.min_concurrent_requests())?;
+                }
+                writeln!(f)?;
+            }
+            None => writeln!(f, "# concurrency is not set")?,
+        }
+        match self.tls() {
+            Some(tls) => {
+                write!(f, "\n[tls]\ncert = {:?}\nkey = {:?}", tls.cert(), tls.key())?;
+                match tls.client_ca() {
+                    Some(client_ca) => write!(f, "\nclient_ca = {:?}", client_ca),
+                    None => Ok(()),
+                }
+            }
+            None => write!(f, "# tls is not set"),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn requests_per_sec(&self) -> f64 {
+        self.requests_per_sec.unwrap_or_default() as f64
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst.or(self.requests_per_sec).unwrap_or_default()
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.max_concurrent_requests.unwrap_or_default()
+    }
+
+    pub fn max_queue(&self) -> usize {
+        self.max_queue.unwrap_or(self.max_concurrent_requests())
+    }
+
+    pub fn queue_timeout(&self) -> Duration {
+        self.queue_timeout_ms.map_or(DEFAULT_QUEUE_TIMEOUT, Duration::from_millis)
+    }
+
+    // None for a fixed limit
+    pub fn latency_target(&self) -> Option<Duration> {
+        self.latency_target_ms.map(Duration::from_millis)
+    }
+
+    pub fn min_concurrent_requests(&self) -> usize {
+        self.min_concurrent_requests.unwrap_or(1)
+    }
+}
+
+impl ListenerTlsConfig {
+    pub fn cert(&self) -> &str {
+        self.cert.as_deref().unwrap_or_default()
+    }
+
+    pub fn key(&self) -> &str {
+        self.key.as_deref().unwrap_or_default()
+    }
+
+    pub fn client_ca(&self) -> Option<&str> {
+        self.client_ca.as_deref()
     }
 }
 
@@ -61,33 +388,175 @@ impl WordCountResponse {
     pub fn failed_resp() -> Self {
         WordCountResponse {
             count: 0,
-            status_code: -1,
+            status_code: STATUS_FAILED,
             status_message: "some error occurred...".to_string(),
             log_id: "0".to_string(),
         }
     }
+
+    pub fn server_busy_resp() -> Self {
+        WordCountResponse {
+            count: 0,
+            status_code: STATUS_SERVER_BUSY,
+            status_message: "server busy, retry later".to_string(),
+            log_id: "0".to_string(),
+        }
+    }
+
+    pub fn unauthorized_resp() -> Self {
+        WordCountResponse {
+            count: 0,
+            status_code: STATUS_UNAUTHORIZED,
+            status_message: "missing or unknown api key".to_string(),
+            log_id: "0".to_string(),
+        }
+    }
+
+    pub fn forbidden_resp() -> Self {
+        WordCountResponse {
+            count: 0,
+            status_code: STATUS_FORBIDDEN,
+            status_message: "file not allowed for this api key".to_string(),
+            log_id: "0".to_string(),
+        }
+    }
+
+    pub fn rate_limited_resp() -> Self {
+        WordCountResponse {
+            count: 0,
+            status_code: STATUS_RATE_LIMITED,
+            status_message: "rate limited, retry later".to_string(),
+            log_id: "0".to_string(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::path::Path;
 
+    use crate::tls::test_certs::TestCerts;
+
     use super::*;
 
     #[test]
     fn test_load() {
-        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"));
+        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &ServerOverrides::default());
         assert!(server_config.is_ok());
         let server_config = server_config.unwrap();
         let expected = ServerConfig {
             ip: Some("192.168.1.1".parse().unwrap()),
-            port: Some(8080),
-            metrics_port: Some(8081),
+            port: Some(Spanned::new(0..0, 8080)),
+            metrics_port: Some(Spanned::new(0..0, 8081)),
             enable_fault_tolerance: Some(true),
+            admin_token: Some("test-token".to_string()),
+            auth_policy: None,
+            shutdown_timeout_secs: Some(Spanned::new(0..0, 10)),
+            rate_limit: Some(Spanned::new(0..0, RateLimitConfig { requests_per_sec: Some(50), burst: None })),
+            concurrency: None,
+            tls: None,
+            overrides: ServerOverrides::default(),
         };
         assert_eq!(server_config.ip, expected.ip);
-        assert_eq!(server_config.port, expected.port);
-        assert_eq!(server_config.metrics_port, expected.metrics_port);
+        assert_eq!(server_config.port(), expected.port());
+        assert_eq!(server_config.metrics_port(), expected.metrics_port());
         assert_eq!(server_config.enable_fault_tolerance, expected.enable_fault_tolerance);
+        assert_eq!(server_config.admin_token(), expected.admin_token);
+        assert_eq!(server_config.shutdown_timeout(), expected.shutdown_timeout());
+        assert_eq!(server_config.rate_limit(), expected.rate_limit());
+        assert_eq!(server_config.rate_limit().unwrap().burst(), 50);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = ServerOverrides { port: Some(9000), admin_token: Some("cli-token".to_string()), ..Default::default() };
+        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &overrides).unwrap();
+        assert_eq!(server_config.port(), 9000);
+        assert_eq!(server_config.metrics_port(), 8081);
+        assert_eq!(server_config.admin_token(), Some("cli-token".to_string()));
+        assert!(server_config.to_string().contains("admin_token = \"<redacted>\""));
+
+        let overrides = ServerOverrides { rate_limit_burst: Some(100), ..Default::default() };
+        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &overrides).unwrap();
+        let rate_limit = server_config.rate_limit().unwrap();
+        assert_eq!((rate_limit.requests_per_sec(), rate_limit.burst()), (50.0, 100));
+        assert!(server_config.concurrency().is_none());
+
+        let overrides = ServerOverrides { max_concurrent_requests: Some(64), ..Default::default() };
+        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &overrides).unwrap();
+        let concurrency = server_config.concurrency().unwrap();
+        assert_eq!((concurrency.max_concurrent_requests(), concurrency.max_queue()), (64, 64));
+        assert_eq!(concurrency.latency_target(), None);
+
+        let overrides = ServerOverrides { port: Some(8081), ..Default::default() };
+        let err = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &overrides).unwrap_err().to_string();
+        assert!(err.contains("server_test.toml: port and metrics_port should differ"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_check() {
+        let content = "port = 8080\nmetrics_port = 8080";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("server.toml:2: port and metrics_port should differ, both are 8080"));
+
+        let content = "port = 8080\nmetrics_port = 8081\nshutdown_timeout_secs = 0";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        assert!(problems.into_result().unwrap_err().to_string().contains("server.toml:3: shutdown_timeout_secs should be positive"));
+
+        let content = "port = 8080\nmetrics_port = 8081\n[rate_limit]\nburst = 0";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("[rate_limit]: burst should be positive"));
+        assert!(err.contains("[rate_limit]: requests_per_sec is required"));
+
+        let content = "port = 8080\nmetrics_port = 8081\n[concurrency]\nmax_concurrent_requests = 4\nmin_concurrent_requests = 8";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("server.toml:3: [concurrency]: min_concurrent_requests should be at most max_concurrent_requests"));
+
+        let content = "port = 8080\nmetrics_port = 8081\n[tls]\nclient_ca = \"ca.pem\"";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        assert!(problems.into_result().unwrap_err().to_string().contains("server.toml:3: [tls]: cert is required"));
+
+        let content = "port = 8080\nmetrics_port = 8081\n[tls]\ncert = \"missing.pem\"";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        let err = problems.into_result().unwrap_err().to_string();
+        assert!(err.contains("server.toml:3: [tls]: key is required"));
+        assert!(err.contains("server.toml:3: [tls]: cert file \"missing.pem\" not found"));
+
+        let content = "port = 8080\nmetrics_port = 8081\nauth_policy = \"missing.toml\"";
+        let config: ServerConfig = toml::from_str(content).unwrap();
+        let mut problems = ConfigProblems::new(Path::new("server.toml"), content);
+        config.check(&mut problems);
+        assert!(problems.into_result().unwrap_err().to_string().contains("server.toml:3: auth_policy file \"missing.toml\" not found"));
+    }
+
+    #[test]
+    fn test_tls() {
+        let certs = TestCerts::generate("server-config");
+        let overrides = ServerOverrides {
+            tls_cert: Some(certs.path("server.pem")),
+            tls_key: Some(certs.path("server.key")),
+            ..Default::default()
+        };
+        let server_config = ServerConfig::load(Path::new("src/config_test/server_test.toml"), &overrides).unwrap();
+        let tls = server_config.tls().unwrap();
+        assert_eq!(tls.cert(), certs.path("server.pem"));
+        assert_eq!(tls.client_ca(), None);
+        assert!(server_config.to_string().contains("[tls]\ncert = "));
+        assert!(ServerConfig::load(Path::new("src/config_test/server_test.toml"), &ServerOverrides::default()).unwrap().tls().is_none());
+    }
+}
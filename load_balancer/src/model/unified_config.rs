@@ -0,0 +1,403 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use toml::{Spanned, Table, Value};
+
+use crate::model::config_check::ConfigProblems;
+use crate::model::endpoints_config::EndpointPoolConfig;
+use crate::model::load_balancer_config::{LBConfig, LBOverrides};
+use crate::model::server_config::{ServerConfig, ServerOverrides};
+
+const INCLUDE: &str = "include";
+const PROFILES: &str = "profiles";
+const SERVER: &str = "server";
+const LOAD_BALANCER: &str = "load_balancer";
+// the top-level keys of `endpoints.toml`
+const ENDPOINTS_KEYS: &[&str] = &["endpoints", "discovery", "registry", "connection", "health_check"];
+
+// the keys of every profile, with their spans
+#[derive(Deserialize)]
+struct ProfileKeys {
+    profiles: BTreeMap<String, BTreeMap<Spanned<String>, Value>>,
+}
+
+/// A single config file replacing `server.toml`, `load_balancer.toml` and `endpoints.toml`: the `[server]` and
+/// `[load_balancer]` tables hold the content of the first two files, every other key is that of `endpoints.toml`.
+///
+/// * `include = ["a.toml", ...]` merges other files first, paths are relative to the including file
+/// * `[profiles.<name>]` holds tables in the same layout, merged over the rest when the profile is selected
+/// * `${NAME}` and `${NAME:-default}` are replaced by environment variables before a file is parsed, outside comments
+///
+/// Tables are merged key by key, any other value (arrays included) is replaced as a whole.
+pub struct UnifiedConfig {
+    source: String,
+    server: Table,
+    load_balancer: Table,
+    endpoints: Table,
+    // the file and everything it includes, watched by the hot reload
+    files: Vec<PathBuf>,
+    // the files setting each top-level key, those of a profile under `profiles.<name>.<key>`
+    origins: HashMap<String, Vec<PathBuf>>,
+}
+
+impl UnifiedConfig {
+    pub fn load(path: &Path, profile: Option<&str>) -> Result<Self> {
+        let mut files = vec![];
+        let mut origins = HashMap::new();
+        let mut table = Self::load_file(path, &mut vec![], &mut files, &mut origins)?;
+
+        let profiles = match table.remove(PROFILES) {
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => return Err(anyhow!("{:?}: {} should be a table", path, PROFILES)),
+            None => Table::new(),
+        };
+        if let Some(profile) = profile {
+            match profiles.get(profile) {
+                Some(Value::Table(overlay)) => {
+                    for key in overlay.keys() {
+                        let files = origins.get(&format!("{}.{}.{}", PROFILES, profile, key)).cloned().unwrap_or_default();
+                        add_origins(&mut origins, key, files);
+                    }
+                    merge(&mut table, overlay.clone())
+                }
+                _ => {
+                    let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                    return Err(anyhow!("{:?}: unknown profile {:?}, available: [{}]", path, profile, available.join(", ")));
+                }
+            }
+        }
+
+        let mut section = |name: &str| match table.remove(name) {
+            Some(Value::Table(section)) => Ok(section),
+            Some(_) => Err(anyhow!("{:?}: {} should be a table", path, name)),
+            None => Ok(Table::new()),
+        };
+        let server = section(SERVER)?;
+        let load_balancer = section(LOAD_BALANCER)?;
+        let source = match profile {
+            Some(profile) => format!("{} (profile {})", path.display(), profile),
+            None => path.display().to_string(),
+        };
+        Ok(UnifiedConfig { source, server, load_balancer, endpoints: table, files, origins })
+    }
+
+    // `stack` holds the files being included, to detect cycles
+    fn load_file(path: &Path, stack: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>, origins: &mut HashMap<String, Vec<PathBuf>>) -> Result<Table> {
+        let canonical = fs::canonicalize(path).with_context(|| format!("failed to read config file:{:?}", path))?;
+        if stack.contains(&canonical) {
+            return Err(anyhow!("{:?}: include cycle", path));
+        }
+        let content = fs::read_to_string(path).with_context(|| format!("failed to read config file:{:?}", path))?;
+        let content = interpolate(&content).with_context(|| format!("failed to interpolate config file:{:?}", path))?;
+        let mut table: Table = toml::from_str(&content).with_context(|| format!("failed to parse config file:{:?}", path))?;
+        Self::check_keys(path, &content)?;
+        files.push(path.to_path_buf());
+        Self::record_origins(&table, path, origins);
+
+        let includes = match table.remove(INCLUDE) {
+            Some(Value::Array(includes)) => includes,
+            Some(_) => return Err(anyhow!("{:?}: {} should be an array of paths", path, INCLUDE)),
+            None => return Ok(table),
+        };
+        stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut merged = Table::new();
+        for include in includes {
+            let Value::String(include) = include else {
+                return Err(anyhow!("{:?}: {} should be an array of paths", path, INCLUDE));
+            };
+            merge(&mut merged, Self::load_file(&dir.join(include), stack, files, origins)?);
+        }
+        stack.pop();
+        // the including file wins over what it includes
+        merge(&mut merged, table);
+        Ok(merged)
+    }
+
+    // any other key would otherwise be taken as one of `endpoints.toml` and ignored there, so a misspelled section is
+    // reported at its line, at the top level and in every profile
+    fn check_keys(path: &Path, content: &str) -> Result<()> {
+        let mut problems = ConfigProblems::new(path, content);
+        let mut keys: Vec<Spanned<String>> = toml::from_str::<BTreeMap<Spanned<String>, Value>>(content)?.into_keys().collect();
+        // a profile that is not a table is reported when it is selected
+        if let Ok(profile_keys) = toml::from_str::<ProfileKeys>(content) {
+            keys.extend(profile_keys.profiles.into_values().flat_map(BTreeMap::into_keys));
+        }
+        let known: Vec<&str> = [INCLUDE, PROFILES, SERVER, LOAD_BALANCER].into_iter().chain(ENDPOINTS_KEYS.iter().copied()).collect();
+        for key in keys {
+            if !known.contains(&key.get_ref().as_str()) {
+                problems.add(Some(key.span()), format!("unknown key {:?}, should be one of {}", key.get_ref(), known.join(", ")));
+            }
+        }
+        problems.into_result()
+    }
+
+    fn record_origins(table: &Table, path: &Path, origins: &mut HashMap<String, Vec<PathBuf>>) {
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                (INCLUDE, _) => {}
+                (PROFILES, Value::Table(profiles)) => {
+                    for (profile, overlay) in profiles {
+                        let Value::Table(overlay) = overlay else { continue };
+                        for key in overlay.keys() {
+                            add_origins(origins, &format!("{}.{}.{}", PROFILES, profile, key), vec![path.to_path_buf()]);
+                        }
+                    }
+                }
+                _ => add_origins(origins, key, vec![path.to_path_buf()]),
+            }
+        }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn server_config(&self, overrides: &ServerOverrides) -> Result<ServerConfig> {
+        let content = toml::to_string(&self.server)?;
+        ServerConfig::parse(&content, self.problems(SERVER), overrides)
+    }
+
+    pub fn lb_config(&self, overrides: &LBOverrides) -> Result<LBConfig> {
+        let content = toml::to_string(&self.load_balancer)?;
+        LBConfig::parse(&content, self.problems(LOAD_BALANCER), overrides)
+    }
+
+    pub fn pool_config(&self, strategy: &str) -> Result<EndpointPoolConfig> {
+        let content = toml::to_string(&self.endpoints)?;
+        EndpointPoolConfig::parse(&content, self.problems("endpoints"), strategy)
+    }
+
+    // sections are re-serialized from the merged files, so problems are reported without a line, but with the files the
+    // section was merged from
+    fn problems(&self, section: &str) -> ConfigProblems<'static> {
+        let keys: Vec<&String> = match section {
+            SERVER | LOAD_BALANCER => self.origins.keys().filter(|key| *key == section).collect(),
+            _ => self.endpoints.keys().collect(),
+        };
+        let origins: Vec<&PathBuf> = keys.into_iter().filter_map(|key| self.origins.get(key)).flatten().collect();
+        // in the order the files are loaded
+        let files: Vec<String> = self.files.iter()
+            .filter(|file| origins.contains(file))
+            .map(|file| file.display().to_string())
+            .collect();
+        match files.is_empty() {
+            true => ConfigProblems::without_lines(format!("{} [{}]", self.source, section)),
+            false => ConfigProblems::without_lines(format!("{} [{}] from {}", self.source, section, files.join(", "))),
+        }
+    }
+}
+
+fn add_origins(origins: &mut HashMap<String, Vec<PathBuf>>, key: &str, files: Vec<PathBuf>) {
+    let origin = origins.entry(key.to_string()).or_default();
+    for file in files {
+        if !origin.contains(&file) {
+            origin.push(file);
+        }
+    }
+}
+
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// replaces `${NAME}` and `${NAME:-default}` outside comments, an unset variable without default is an error. A value
+// inside a basic string is escaped, one inside a literal string cannot hold what would end it, and one outside strings is
+// inserted as is, to be parsed as a number, a boolean or any other TOML value.
+fn interpolate(content: &str) -> Result<String> {
+    let mut result = String::with_capacity(content.len());
+    let mut scope = Scope::Value;
+    let mut offset = 0;
+    while offset < content.len() {
+        let rest = &content[offset..];
+        if scope != Scope::Comment && rest.starts_with("${") {
+            let line = content[..offset].matches('\n').count() + 1;
+            let end = rest.find('}').ok_or_else(|| anyhow!("line {}: unclosed ${{", line))?;
+            let expr = &rest[2..end];
+            let (name, default) = match expr.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expr, None),
+            };
+            match (env::var(name), default) {
+                (Ok(value), _) => {
+                    let value = scope.quote(&value).map_err(|problem| anyhow!("line {}: environment variable {} {}", line, name, problem))?;
+                    result.push_str(&value);
+                }
+                // written in the file, already quoted as it should be
+                (Err(_), Some(default)) => result.push_str(default),
+                (Err(_), None) => return Err(anyhow!("line {}: environment variable {} is not set", line, name)),
+            }
+            offset += end + 1;
+            continue;
+        }
+        let (next, length) = scope.next(rest);
+        result.push_str(&rest[..length]);
+        scope = next;
+        offset += length;
+    }
+    Ok(result)
+}
+
+// where in the TOML syntax a `${...}` is found
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Value,
+    Comment,
+    Basic,
+    MultilineBasic,
+    Literal,
+    MultilineLiteral,
+}
+
+impl Scope {
+    // the scope after the token `rest` starts with, and the token's length
+    fn next(self, rest: &str) -> (Scope, usize) {
+        match self {
+            Scope::Value if rest.starts_with('#') => (Scope::Comment, 1),
+            Scope::Value if rest.starts_with("\"\"\"") => (Scope::MultilineBasic, 3),
+            Scope::Value if rest.starts_with("'''") => (Scope::MultilineLiteral, 3),
+            Scope::Value if rest.starts_with('"') => (Scope::Basic, 1),
+            Scope::Value if rest.starts_with('\'') => (Scope::Literal, 1),
+            Scope::Comment if rest.starts_with('\n') => (Scope::Value, 1),
+            // an escaped character never ends the string
+            Scope::Basic | Scope::MultilineBasic if rest.starts_with('\\') => (self, 1 + char_length(&rest[1..])),
+            Scope::Basic if rest.starts_with('"') => (Scope::Value, 1),
+            Scope::MultilineBasic if rest.starts_with("\"\"\"") => (Scope::Value, 3),
+            Scope::Literal if rest.starts_with('\'') => (Scope::Value, 1),
+            Scope::MultilineLiteral if rest.starts_with("'''") => (Scope::Value, 3),
+            _ => (self, char_length(rest)),
+        }
+    }
+
+    fn quote(self, value: &str) -> std::result::Result<String, &'static str> {
+        match self {
+            Scope::Basic | Scope::MultilineBasic => Ok(value.chars().map(escape).collect()),
+            Scope::Literal if value.contains(['\'', '\n', '\r']) => {
+                Err("cannot be inserted in a literal string, it holds a quote or a line break")
+            }
+            Scope::MultilineLiteral if value.contains("'''") => {
+                Err("cannot be inserted in a multi-line literal string, it holds '''")
+            }
+            _ => Ok(value.to_string()),
+        }
+    }
+}
+
+fn char_length(text: &str) -> usize {
+    text.chars().next().map_or(0, char::len_utf8)
+}
+
+// `c` as written in a basic string
+fn escape(c: char) -> String {
+    match c {
+        '"' => "\\\"".to_string(),
+        '\\' => "\\\\".to_string(),
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        c if c.is_control() => format!("\\u{:04X}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let config = UnifiedConfig::load(Path::new("src/config_test/unified_test.toml"), None).unwrap();
+        assert_eq!(config.files().len(), 2);
+        let server_config = config.server_config(&ServerOverrides::default()).unwrap();
+        assert_eq!(server_config.port(), 8080);
+        let lb_config = config.lb_config(&LBOverrides::default()).unwrap();
+        assert_eq!(lb_config.strategy(), "WeightedRoundRobin");
+        // endpoints come from the included file, the connection defaults from the including one
+        let endpoints = config.pool_config(&lb_config.strategy()).unwrap().endpoint_configs();
+        assert_eq!(endpoints.len(), 3);
+        assert_eq!(endpoints[0].connection().request_timeout(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_profile() {
+        let config = UnifiedConfig::load(Path::new("src/config_test/unified_test.toml"), Some("test")).unwrap();
+        let server_config = config.server_config(&ServerOverrides::default()).unwrap();
+        assert_eq!(server_config.port(), 18080);
+        assert_eq!(server_config.metrics_port(), 8081);
+        let lb_config = config.lb_config(&LBOverrides::default()).unwrap();
+        assert_eq!(lb_config.strategy(), "RoundRobin");
+        let endpoints = config.pool_config(&lb_config.strategy()).unwrap().endpoint_configs();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].name(), "local");
+
+        assert!(UnifiedConfig::load(Path::new("src/config_test/unified_test.toml"), Some("prod")).is_err());
+    }
+
+    #[test]
+    fn test_problems_without_lines() {
+        let config = UnifiedConfig::load(Path::new("src/config_test/unified_test.toml"), Some("invalid")).unwrap();
+        let err = config.server_config(&ServerOverrides::default()).unwrap_err().to_string();
+        assert!(err.contains("unified_test.toml (profile invalid) [server] from src/config_test/unified_test.toml: port and metrics_port should differ"), "{}", err);
+        // the endpoints are included, their connection defaults are not
+        let config = UnifiedConfig::load(Path::new("src/config_test/unified_test.toml"), None).unwrap();
+        assert_eq!(config.problems("endpoints").source(), "src/config_test/unified_test.toml [endpoints] from src/config_test/unified_test.toml, src/config_test/endpoints_test.toml");
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let err = UnifiedConfig::load(Path::new("src/config_test/unified_unknown_test.toml"), None).err().unwrap();
+        let err = format!("{:#}", err);
+        assert!(err.contains("src/config_test/unified_unknown_test.toml:1: unknown key \"sever\""), "{}", err);
+        assert!(err.contains("src/config_test/unified_unknown_test.toml:4: unknown key \"endpoint\""), "{}", err);
+        assert!(err.contains("src/config_test/unified_unknown_test.toml:11: unknown key \"loadbalancer\""), "{}", err);
+        assert!(!err.contains("\"profiles\""), "{}", err);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let err = UnifiedConfig::load(Path::new("src/config_test/unified_cycle_test.toml"), None).err().unwrap();
+        assert!(format!("{:#}", err).contains("include cycle"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("UNIFIED_CONFIG_TEST_PORT", "9000");
+        assert_eq!(interpolate("port = ${UNIFIED_CONFIG_TEST_PORT}").unwrap(), "port = 9000");
+        assert_eq!(interpolate("host = \"${UNIFIED_CONFIG_TEST_UNSET:-redis}\"").unwrap(), "host = \"redis\"");
+        assert_eq!(interpolate("a = 1\nb = \"$HOME\"").unwrap(), "a = 1\nb = \"$HOME\"");
+        let err = interpolate("a = 1\nb = ${UNIFIED_CONFIG_TEST_UNSET}").unwrap_err().to_string();
+        assert_eq!(err, "line 2: environment variable UNIFIED_CONFIG_TEST_UNSET is not set");
+        assert!(interpolate("b = ${UNIFIED_CONFIG_TEST_PORT").is_err());
+    }
+    #[test]
+    fn test_interpolate_scopes() {
+        env::set_var("UNIFIED_CONFIG_TEST_SCOPE_PORT", "9001");
+        env::set_var("UNIFIED_CONFIG_TEST_QUOTED", "a \"b\" \\c\n");
+        // comments are left as they are, an unset variable there is no error
+        assert_eq!(interpolate("a = 1 # ${UNIFIED_CONFIG_TEST_UNSET}\nb = 2").unwrap(), "a = 1 # ${UNIFIED_CONFIG_TEST_UNSET}\nb = 2");
+        assert_eq!(interpolate("a = \"#${UNIFIED_CONFIG_TEST_SCOPE_PORT}\"").unwrap(), "a = \"#9001\"");
+        let content = interpolate("a = \"${UNIFIED_CONFIG_TEST_QUOTED}\"\nb = \"\"\"${UNIFIED_CONFIG_TEST_QUOTED}\"\"\"").unwrap();
+        let table: Table = toml::from_str(&content).unwrap();
+        assert_eq!(table["a"].as_str(), Some("a \"b\" \\c\n"));
+        assert_eq!(table["b"].as_str(), Some("a \"b\" \\c\n"));
+        // an escaped quote does not end the string
+        assert_eq!(interpolate("a = \"\\\" ${UNIFIED_CONFIG_TEST_SCOPE_PORT}\"").unwrap(), "a = \"\\\" 9001\"");
+        assert_eq!(interpolate("a = '${UNIFIED_CONFIG_TEST_SCOPE_PORT}'").unwrap(), "a = '9001'");
+        let err = interpolate("a = 1\nb = '${UNIFIED_CONFIG_TEST_QUOTED}'").unwrap_err().to_string();
+        assert!(err.starts_with("line 2: environment variable UNIFIED_CONFIG_TEST_QUOTED cannot be inserted in a literal string"), "{}", err);
+        assert!(interpolate("a = '''${UNIFIED_CONFIG_TEST_QUOTED}'''").is_ok());
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::consts::RATE_LIMIT_CLEANUP_INTERVAL;
+use crate::model::server_config::RateLimitConfig;
+
+/// Token bucket per client: each client may send `burst` requests at once, and `requests_per_sec` on average.
+/// Clients are keyed by a string: every request takes a token of its IP address, then one of its tenant when authenticated.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    cleaned_at: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        RateLimiter {
+            rate: config.requests_per_sec(),
+            burst: config.burst() as f64,
+            state: Mutex::new(Buckets { buckets: HashMap::new(), cleaned_at: Instant::now() }),
+        }
+    }
+
+    // takes a token from the client's bucket, false when it is empty
+    pub fn allow(&self, client: &str) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if now.duration_since(state.cleaned_at) >= RATE_LIMIT_CLEANUP_INTERVAL {
+            self.cleanup(&mut state, now);
+        }
+        let bucket = state.buckets
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: self.burst, updated_at: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    // a bucket refilled to `burst` is the same as a new one, dropping it bounds the memory to active clients
+    fn cleanup(&self, state: &mut Buckets, now: Instant) {
+        let refill = Duration::from_secs_f64(self.burst / self.rate);
+        state.buckets.retain(|_, bucket| now.duration_since(bucket.updated_at) < refill);
+        state.cleaned_at = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_allow() {
+        let limiter = RateLimiter::new(&toml::from_str("requests_per_sec = 20\nburst = 2").unwrap());
+        assert!(limiter.allow("10.0.0.1"));
+        assert!(limiter.allow("10.0.0.1"));
+        assert!(!limiter.allow("10.0.0.1"));
+        // every client has its own bucket
+        assert!(limiter.allow("10.0.0.2"));
+
+        // refilled at requests_per_sec
+        thread::sleep(Duration::from_millis(60));
+        assert!(limiter.allow("10.0.0.1"));
+        assert!(!limiter.allow("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let limiter = RateLimiter::new(&toml::from_str("requests_per_sec = 100\nburst = 1").unwrap());
+        assert!(limiter.allow("10.0.0.1"));
+        thread::sleep(Duration::from_millis(20));
+        assert!(limiter.allow("10.0.0.2"));
+        let mut state = limiter.state.lock().unwrap();
+        limiter.cleanup(&mut state, Instant::now());
+        assert!(!state.buckets.contains_key("10.0.0.1"));
+        assert!(state.buckets.contains_key("10.0.0.2"));
+    }
+}
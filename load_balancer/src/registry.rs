@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use redis::AsyncCommands;
+
+use crate::consts::REGISTRY_KEY_PREFIX;
+use crate::model::endpoints_config::{EndpointConfig, RegisteredEndpointConfig, RegistryConfig};
+
+/// Endpoints registered by counter_service instances in Redis. Each instance keeps its own key alive with
+/// a TTL as heartbeat and deletes it on shutdown, so expired or removed keys drop out of the endpoint set.
+pub struct Registry {
+    client: redis::Client,
+}
+
+impl Registry {
+    pub fn new(config: &RegistryConfig) -> Result<Self> {
+        let client = redis::Client::open(config.redis_url()).context("open registry redis client failed")?;
+        Ok(Registry { client })
+    }
+
+    pub async fn endpoints(&self) -> Result<Vec<EndpointConfig>> {
+        let mut conn = self.client.get_multiplexed_async_connection().await
+            .context("connect registry redis failed")?;
+        let mut keys: Vec<String> = vec![];
+        {
+            let mut iter = conn.scan_match::<_, String>(format!("{}*", REGISTRY_KEY_PREFIX)).await
+                .context("scan registry keys failed")?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        keys.sort();
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query_async(&mut conn).await
+            .context("get registry entries failed")?;
+        Ok(Self::parse(values))
+    }
+
+    // keys may expire between SCAN and MGET, and malformed entries are skipped, as are entries with settings other
+    // than the address and weight
+    fn parse(values: Vec<Option<String>>) -> Vec<EndpointConfig> {
+        values
+            .into_iter()
+            .flatten()
+            .filter_map(|value| {
+                serde_json::from_str::<RegisteredEndpointConfig>(&value)
+                    .map_err(|err| tracing::warn!(?err, value, "[Registry] invalid registry entry"))
+                    .ok()
+            })
+            .map(EndpointConfig::from)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let values = vec![
+            Some(r#"{"name":"server4","host":"server4","port":50051,"weight":20}"#.to_string()),
+            None,
+            Some(r#"{"name":"server5","ip":"192.168.1.14","port":50051}"#.to_string()),
+            Some("not json".to_string()),
+            // connection settings come from endpoints.toml only
+            Some(r#"{"name":"server6","host":"server6","port":50051,"connection":{"tls_key":"/etc/shadow","api_key":"x"}}"#.to_string()),
+            Some(r#"{"name":"server7","srv":"_grpc._tcp.evil","port":50051}"#.to_string()),
+        ];
+        let configs = Registry::parse(values);
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].name(), "server4");
+        assert_eq!(configs[0].host(), Some("server4"));
+        assert_eq!(configs[0].weight(), Some(20));
+        assert_eq!(configs[1].get_socket_addr(), "192.168.1.14:50051".parse().unwrap());
+        assert!(configs.iter().all(|config| config.connection() == Default::default()));
+    }
+}
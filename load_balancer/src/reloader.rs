@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{interval_at, Instant, Interval};
+
+use crate::AppBuilder;
+use crate::cli::Cli;
+use crate::consts::CONFIG_WATCH_INTERVAL;
+use crate::discovery::Discovery;
+use crate::registry::Registry;
+use crate::endpoint::Endpoint;
+use crate::load_balancer::LoadBalancer;
+use crate::model::endpoints_config::EndpointConfig;
+use crate::model::load_balancer_config::LBConfig;
+
+/// Re-applies `endpoints.toml` and `load_balancer.toml` while the load balancer is running.
+/// A reload is triggered by a changed modification time of either file, by SIGHUP, or periodically
+/// to re-resolve endpoints addressed by hostname or SRV record and to follow the registry, when any is configured.
+pub struct ConfigReloader {
+    load_balancer: Arc<Box<dyn LoadBalancer>>,
+    // config file paths, and overrides applied again on every reload
+    cli: Arc<Cli>,
+    lb_config: Option<LBConfig>,
+    modified: HashMap<PathBuf, SystemTime>,
+    // none when no endpoint is discovered or registered
+    refresh_interval: Option<Duration>,
+    // the weight each endpoint was last given by the config, one set through the admin API is kept until it changes
+    config_weights: HashMap<String, Option<u8>>,
+    // last successful fetch from the registry
+    registered: Vec<EndpointConfig>,
+}
+
+impl ConfigReloader {
+    pub fn new(load_balancer: Arc<Box<dyn LoadBalancer>>, cli: Arc<Cli>) -> Self {
+        let config_weights = load_balancer.endpoints()
+            .iter()
+            .map(|endpoint| (endpoint.name(), endpoint.weight()))
+            .collect();
+        let mut reloader = ConfigReloader {
+            load_balancer,
+            lb_config: cli.lb_config().ok(),
+            modified: HashMap::new(),
+            refresh_interval: cli.pool_config("")
+                .ok()
+                .filter(|config| config.refreshed())
+                .map(|config| config.discovery().refresh_interval()),
+            config_weights,
+            registered: vec![],
+            cli,
+        };
+        reloader.files_changed();
+        reloader
+    }
+
+    pub async fn run(mut self) {
+        let mut hangup = signal(SignalKind::hangup()).map_err(|err| {
+            tracing::warn!(?err, "[ConfigReloader] register SIGHUP handler failed");
+        }).ok();
+        let mut interval = tokio::time::interval(CONFIG_WATCH_INTERVAL);
+        let mut refresh = Self::refresh_timer(self.refresh_interval);
+        tracing::info!("[ConfigReloader] watching config files");
+        loop {
+            let reason = tokio::select! {
+                _ = interval.tick() => "config file changed",
+                _ = Self::hangup(&mut hangup) => "SIGHUP",
+                _ = Self::refresh(&mut refresh) => "discovery refresh",
+            };
+            if !self.files_changed() && reason == "config file changed" {
+                continue;
+            }
+            tracing::info!(reason, "[ConfigReloader] reloading config");
+            let refresh_interval = self.refresh_interval;
+            if let Err(err) = self.reload().await {
+                tracing::error!(?err, "[ConfigReloader] reload failed, keep running with previous config");
+            }
+            if self.refresh_interval != refresh_interval {
+                refresh = Self::refresh_timer(self.refresh_interval);
+            }
+        }
+    }
+
+    fn refresh_timer(period: Option<Duration>) -> Option<Interval> {
+        period.map(|period| interval_at(Instant::now() + period, period))
+    }
+
+    async fn refresh(timer: &mut Option<Interval>) {
+        match timer {
+            Some(timer) => { timer.tick().await; }
+            None => futures::future::pending().await,
+        }
+    }
+
+    async fn hangup(signal: &mut Option<Signal>) {
+        match signal {
+            Some(signal) => { signal.recv().await; }
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn files_changed(&mut self) -> bool {
+        let mut changed = false;
+        for path in self.cli.reloadable_files() {
+            let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+                continue;
+            };
+            if self.modified.insert(path, modified) != Some(modified) {
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    async fn reload(&mut self) -> Result<()> {
+        let lb_config = self.cli.lb_config()?;
+        let strategy = lb_config.strategy();
+        let pool_config = self.cli.pool_config(strategy.as_str())?;
+
+        let discovery_config = pool_config.discovery();
+        let discovery = Discovery::new(&discovery_config)?;
+        self.refresh_interval = pool_config.refreshed().then(|| discovery_config.refresh_interval());
+
+        match pool_config.registry() {
+            Some(registry) => {
+                match Registry::new(&registry)?.endpoints().await {
+                    Ok(registered) => self.registered = registered,
+                    Err(err) => tracing::error!(?err, "[ConfigReloader] fetch registered endpoints failed, keep previously registered"),
+                }
+            }
+            None => self.registered.clear(),
+        }
+        let registered = pool_config.registered(&self.registered, strategy.as_str());
+        let mut configs = pool_config.endpoint_configs();
+        configs.extend(registered);
+
+        let current = self.load_balancer.endpoints();
+        let mut endpoints = vec![];
+        let mut config_weights = HashMap::new();
+        for config in configs {
+            let resolved = match discovery.resolve(&config).await {
+                Ok(resolved) => resolved,
+                Err(err) => {
+                    tracing::error!(?err, "[ConfigReloader] resolve endpoint failed, keep previously resolved addresses");
+                    for endpoint in Self::previously_resolved(&current, &config) {
+                        if let Some(weight) = self.config_weights.get(&endpoint.name()) {
+                            config_weights.insert(endpoint.name(), *weight);
+                        }
+                        endpoints.push(endpoint);
+                    }
+                    continue;
+                }
+            };
+            for config in resolved {
+                let previous_weight = self.config_weights.get(&config.name()).copied();
+                config_weights.insert(config.name(), config.weight());
+                match Self::reuse(&current, &config) {
+                    Some(endpoint) => {
+                        if previous_weight != Some(config.weight()) {
+                            endpoint.set_weight(config.weight());
+                        }
+                        endpoints.push(endpoint);
+                    }
+                    None => {
+                        let endpoint = AppBuilder::endpoint(config).await;
+                        Self::keep_drained(&current, &endpoint);
+                        endpoints.push(endpoint);
+                    }
+                }
+            }
+        }
+        self.load_balancer.replace_endpoints(endpoints);
+        self.config_weights = config_weights;
+
+        if self.lb_config.as_ref() != Some(&lb_config) {
+            self.load_balancer.set_strategy(AppBuilder::strategy(&lb_config)).await;
+            self.load_balancer.set_hedging(lb_config.hedging());
+            self.lb_config = Some(lb_config);
+        }
+        Ok(())
+    }
+
+    // keep the running endpoint (and its connection) when name, address, connection and health check settings are unchanged
+    fn reuse(current: &[Arc<Box<dyn Endpoint>>], config: &EndpointConfig) -> Option<Arc<Box<dyn Endpoint>>> {
+        current
+            .iter()
+            .find(|endpoint| {
+                endpoint.name() == config.name()
+                    && endpoint.addr() == config.get_socket_addr()
+                    && endpoint.connection_config() == config.connection()
+                    && endpoint.health_check_config() == config.health_check()
+            })
+            .map(Arc::clone)
+    }
+
+    // an endpoint rebuilt for changed settings stays drained when the admin API drained the one it replaces
+    fn keep_drained(current: &[Arc<Box<dyn Endpoint>>], endpoint: &Arc<Box<dyn Endpoint>>) {
+        let name = endpoint.name();
+        if current.iter().any(|previous| previous.name() == name && previous.is_drained()) {
+            endpoint.set_drained(true);
+        }
+    }
+
+    fn previously_resolved(current: &[Arc<Box<dyn Endpoint>>], config: &EndpointConfig) -> Vec<Arc<Box<dyn Endpoint>>> {
+        let prefix = format!("{}@", config.name());
+        current
+            .iter()
+            .filter(|endpoint| endpoint.name() == config.name() || endpoint.name().starts_with(&prefix))
+            .map(Arc::clone)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::path::Path;
+
+    use crate::endpoint::MockEndpoint;
+    use crate::model::endpoints_config::{ConnectionConfig, EndpointDefaults, EndpointPoolConfig, HealthCheckConfig};
+
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(|| "s1".to_string());
+        endpoint.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080));
+        endpoint.expect_connection_config().returning(ConnectionConfig::default);
+        endpoint.expect_health_check_config().returning(HealthCheckConfig::default);
+        let current: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(endpoint))];
+
+        let configs = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_test.toml"), "RoundRobin")
+            .unwrap()
+            .endpoint_configs();
+        let reused = ConfigReloader::reuse(&current, &configs[0]);
+        assert!(reused.is_some_and(|endpoint| Arc::ptr_eq(&endpoint, &current[0])));
+        // any other name, address, connection or health check settings are built as a new endpoint
+        assert!(ConfigReloader::reuse(&current, &configs[1]).is_none());
+        let connection = EndpointDefaults { connection: toml::from_str("request_timeout_ms = 1000").unwrap(), ..Default::default() };
+        assert!(ConfigReloader::reuse(&current, &configs[0].clone().with_defaults(&connection)).is_none());
+        let health_check = EndpointDefaults {
+            health_check: toml::from_str("canary_file = \"canary.txt\"\ncanary_word = \"canary\"").unwrap(),
+            ..Default::default()
+        };
+        assert!(ConfigReloader::reuse(&current, &configs[0].clone().with_defaults(&health_check)).is_none());
+    }
+
+    #[test]
+    fn test_keep_drained() {
+        let mut drained = MockEndpoint::new();
+        drained.expect_name().returning(|| "s1".to_string());
+        drained.expect_is_drained().returning(|| true);
+        let mut active = MockEndpoint::new();
+        active.expect_name().returning(|| "s2".to_string());
+        active.expect_is_drained().returning(|| false);
+        let current: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(drained)), Arc::new(Box::new(active))];
+
+        let mut rebuilt = MockEndpoint::new();
+        rebuilt.expect_name().returning(|| "s1".to_string());
+        rebuilt.expect_set_drained().withf(|drained| *drained).times(1).return_const(());
+        ConfigReloader::keep_drained(&current, &Arc::new(Box::new(rebuilt)));
+
+        // neither an active nor a new endpoint gets drained
+        for name in ["s2", "s3"] {
+            let mut rebuilt = MockEndpoint::new();
+            rebuilt.expect_name().returning(move || name.to_string());
+            rebuilt.expect_set_drained().never();
+            ConfigReloader::keep_drained(&current, &Arc::new(Box::new(rebuilt)));
+        }
+    }
+
+    #[test]
+    fn test_previously_resolved() {
+        let current: Vec<Arc<Box<dyn Endpoint>>> = ["dns@10.0.0.1:50051", "dns@10.0.0.2:50051", "dns2@10.0.0.3:50051"]
+            .into_iter()
+            .map(|name| {
+                let mut endpoint = MockEndpoint::new();
+                endpoint.expect_name().returning(move || name.to_string());
+                let endpoint: Box<dyn Endpoint> = Box::new(endpoint);
+                Arc::new(endpoint)
+            })
+            .collect();
+        let configs = EndpointPoolConfig::load(Path::new("src/config_test/endpoints_discovery_test.toml"), "RoundRobin")
+            .unwrap()
+            .endpoint_configs();
+        let kept = ConfigReloader::previously_resolved(&current, &configs[1]);
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().all(|endpoint| endpoint.name().starts_with("dns@")));
+    }
+}
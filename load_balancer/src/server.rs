@@ -1,34 +1,54 @@
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{Context, Result};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::task::spawn;
+use anyhow::{anyhow, Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-use crate::consts::CONFIG_PATH_SERVER;
+use crate::auth::{AuthError, Authenticator};
+use crate::concurrency::{ConcurrencyLimiter, ServerBusy};
+use crate::consts::{MAX_REQUEST_SIZE, TLS_HANDSHAKE_TIMEOUT};
 use crate::endpoint::word_counter::WordCountResponse;
 use crate::load_balancer::LoadBalancer;
+use crate::metrics::RateLimitCounter;
+use crate::model::auth_config::AuthConfig;
 use crate::model::server_config::ServerConfig;
+use crate::rate_limit::RateLimiter;
+use crate::tls;
 
 pub struct LBServer
 {
     listener: TcpListener,
     load_balancer: Arc<Box<dyn LoadBalancer>>,
     config: ServerConfig,
+    authenticator: Option<Arc<Authenticator>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    concurrency_limiter: Option<Arc<ConcurrencyLimiter>>,
+    tls_acceptor: Option<TlsAcceptor>,
 }
 
+// checks a request passes before it is forwarded: the rate limit per client IP, authentication, the rate limit per
+// tenant, then the concurrency limit
+type Admission = (Option<Arc<Authenticator>>, Option<Arc<RateLimiter>>, Option<Arc<ConcurrencyLimiter>>);
+
 impl LBServer
 {
-    pub async fn build(load_balancer: Arc<Box<dyn LoadBalancer>>) -> Result<Self> {
-        let config = Self::load_config().with_context(|| "server started failed")?;
+    pub async fn build(load_balancer: Arc<Box<dyn LoadBalancer>>, config: ServerConfig) -> Result<Self> {
         let listener = Self::init_listener(&config).await.unwrap();
+        let tls_acceptor = config.tls().map(|tls| tls::acceptor(&tls)).transpose().context("init TLS failed")?;
+        let auth_config = config.auth_policy().map(|path| AuthConfig::load(Path::new(&path))).transpose()?;
 
         Ok(LBServer {
+            tls_acceptor,
             listener,
             load_balancer,
+            authenticator: auth_config.map(|config| Arc::new(Authenticator::new(&config))),
+            rate_limiter: config.rate_limit().map(|config| Arc::new(RateLimiter::new(&config))),
+            concurrency_limiter: config.concurrency().map(|config| Arc::new(ConcurrencyLimiter::new(config))),
             config,
         })
     }
@@ -43,51 +63,74 @@ impl LBServer
         config.get_socket_addr()
     }
 
-    fn load_config() -> Result<ServerConfig> {
-        ServerConfig::load(Path::new(CONFIG_PATH_SERVER))
-            .with_context(|| "load server config failed")
-    }
-
-    pub async fn start(&mut self, running: Arc<AtomicBool>) {
+    // serves until `shutdown` is cancelled, then stops accepting and lets in-flight requests finish
+    // within `shutdown_timeout`
+    pub async fn start(self, shutdown: CancellationToken) {
         if self.config.fault_tolerance() {
             tracing::info!("[LoadBalancer] health maintain process started");
             self.load_balancer.health_maintain();
         }
         tracing::info!("[LoadBalancer] server started, serving at {:?}", self.config.get_socket_addr());
+        let connections = TaskTracker::new();
         loop {
-            match self.listener.accept().await {
-                Ok((stream, addr)) => {
-                    tracing::info!("[Load Balancer] accept new tcp connection from addr={:?}", addr);
-                    let load_balancer = Arc::clone(&self.load_balancer);
-                    spawn(Self::handle_connection(stream, load_balancer));
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        tracing::info!("[Load Balancer] accept new tcp connection from addr={:?}", addr);
+                        let load_balancer = Arc::clone(&self.load_balancer);
+                        let admission = (self.authenticator.clone(), self.rate_limiter.clone(), self.concurrency_limiter.clone());
+                        match self.tls_acceptor.clone() {
+                            Some(acceptor) => connections.spawn(Self::handle_tls_connection(acceptor, stream, addr, load_balancer, admission)),
+                            None => connections.spawn(Self::handle_connection(stream, addr, load_balancer, admission)),
+                        };
+                    }
+                    Err(err) => { tracing::error!(?err, "connection failed"); }
                 }
-                Err(err) => { tracing::error!(?err, "connection failed"); }
-            }
-            // graceful exit while process is killed
-            if !Arc::clone(&running).load(Ordering::SeqCst) {
-                tracing::info!("[LoadBalancer] gracefully exit");
-                self.close();
-                return;
             }
         }
-    }
 
-    pub fn close(&self) {
+        // refuse new connections right away instead of leaving them in the backlog
+        drop(self.listener);
+        connections.close();
+        tracing::info!("[LoadBalancer] stopped accepting, draining {} connections", connections.len());
+        let timeout = self.config.shutdown_timeout();
+        match tokio::time::timeout(timeout, connections.wait()).await {
+            Ok(_) => tracing::info!("[LoadBalancer] all connections drained"),
+            Err(_) => tracing::warn!("[LoadBalancer] drain timeout {:?} exceeded, {} connections cut", timeout, connections.len()),
+        }
         if self.config.fault_tolerance() {
             self.load_balancer.stop_health_maintain();
         }
+        tracing::info!("[LoadBalancer] gracefully exit");
+    }
+
+    // the handshake runs in the connection task, a slow client does not hold up accepting others
+    async fn handle_tls_connection<S>(acceptor: TlsAcceptor, stream: S, addr: SocketAddr, lb: Arc<Box<dyn LoadBalancer>>, admission: Admission)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => Self::handle_connection(stream, addr, lb, admission).await,
+            Ok(Err(err)) => tracing::warn!(?err, "[Load Balancer] TLS handshake with addr={:?} failed", addr),
+            Err(_) => tracing::warn!("[Load Balancer] TLS handshake with addr={:?} timed out", addr),
+        }
     }
-    async fn read_request(stream: &mut TcpStream) -> Result<String> {
+
+    async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
         let mut len_buf = [0u8; 4];
         stream.read_exact(&mut len_buf).await.context("failed to read message length")?;
         let len = u32::from_be_bytes(len_buf) as usize;
+        if len > MAX_REQUEST_SIZE {
+            return Err(anyhow!("message length {} exceeds {} bytes", len, MAX_REQUEST_SIZE));
+        }
 
         let mut buffer = vec![0; len];
         stream.read_exact(&mut buffer).await.context("failed to read message body")?;
         Ok(String::from_utf8(buffer)?)
     }
 
-    async fn send_response(stream: &mut TcpStream, resp: &str) {
+    async fn send_response<S: AsyncWrite + Unpin>(stream: &mut S, resp: &str) {
         if let Err(e) = stream.write_u32(resp.len() as u32).await {
             tracing::error!("fail to write response length, err={:?}", e);
             return;
@@ -102,10 +145,56 @@ impl LBServer
         });
     }
 
-    async fn handle_connection(mut stream: TcpStream, lb: Arc<Box<dyn LoadBalancer>>) {
+    async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        addr: SocketAddr,
+        lb: Arc<Box<dyn LoadBalancer>>,
+        (authenticator, rate_limiter, concurrency_limiter): Admission,
+    ) {
         let req = Self::read_request(&mut stream).await;
+        // limited before authorizing, so API keys can't be guessed at full speed
+        let ip = addr.ip().to_string();
+        if req.is_ok() && Self::rate_limited(&mut stream, addr, &rate_limiter, &ip, &ip).await {
+            return;
+        }
+        // answered without reaching an endpoint
+        let (req, tenant) = match (req, authenticator) {
+            (Ok(req), Some(authenticator)) => match authenticator.authorize(&req) {
+                Ok((req, tenant)) => (Ok(req), Some(tenant)),
+                Err(err) => {
+                    tracing::warn!("[Load Balancer] request from addr={:?} rejected, {}", addr, err);
+                    let resp = match err {
+                        AuthError::Unauthenticated => WordCountResponse::unauthorized_resp(),
+                        AuthError::Forbidden(_) => WordCountResponse::forbidden_resp(),
+                    };
+                    Self::send_response(&mut stream, &serde_json::to_string(&resp).unwrap_or_default()).await;
+                    return;
+                }
+            },
+            (req, _) => (req, None),
+        };
+        if let Some(tenant) = &tenant {
+            // a bucket apart from the IP ones, whatever the tenant is named
+            let bucket = format!("tenant:{}", tenant.name());
+            if Self::rate_limited(&mut stream, addr, &rate_limiter, &bucket, tenant.name()).await {
+                return;
+            }
+        }
+        // held until the response is sent
+        let _permit = match &concurrency_limiter {
+            Some(limiter) if req.is_ok() => match limiter.acquire().await {
+                Ok(permit) => Some(permit),
+                Err(busy) => {
+                    tracing::warn!("[Load Balancer] request from addr={:?} shed, {}", addr, busy);
+                    let response = serde_json::to_string(&WordCountResponse::server_busy_resp()).unwrap_or_default();
+                    Self::send_response(&mut stream, &response).await;
+                    return;
+                }
+            },
+            _ => None,
+        };
         let resp = match req {
-            Ok(req) => lb.handle(req).await,
+            Ok(req) => lb.handle(req, addr, tenant.map(|tenant| tenant.name().to_string())).await,
             Err(e) => {
                 Err(e.context("[Load Balancer] failed to read request"))
             }
@@ -115,11 +204,160 @@ impl LBServer
             tracing::error!(?e, "[Load Balancer] request handle failed");
         }
         let prompt = if resp.is_ok() { "success ✅" } else { "failed ❌" };
-        let response = &resp.unwrap_or_else(|_| {
-            let failed_resp = WordCountResponse::failed_resp();
+        let response = &resp.unwrap_or_else(|err| {
+            let failed_resp = match err.downcast_ref::<ServerBusy>() {
+                Some(_) => WordCountResponse::server_busy_resp(),
+                None => WordCountResponse::failed_resp(),
+            };
             serde_json::to_string(&failed_resp).unwrap_or_default()
         });
         tracing::info!("[Load Balancer] request {}, response = {}", prompt, &response);
         Self::send_response(&mut stream, response).await;
     }
-}
\ No newline at end of file
+
+    // answers the request as rate limited when the bucket is empty
+    async fn rate_limited<S: AsyncWrite + Unpin>(
+        stream: &mut S,
+        addr: SocketAddr,
+        rate_limiter: &Option<Arc<RateLimiter>>,
+        bucket: &str,
+        client: &str,
+    ) -> bool {
+        if rate_limiter.as_ref().is_none_or(|limiter| limiter.allow(bucket)) {
+            return false;
+        }
+        tracing::warn!("[Load Balancer] request from addr={:?} rate limited, client={}", addr, client);
+        RateLimitCounter::rejected(client);
+        let response = serde_json::to_string(&WordCountResponse::rate_limited_resp()).unwrap_or_default();
+        Self::send_response(stream, &response).await;
+        true
+    }
+}
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::TlsConnector;
+
+    use crate::load_balancer::LoadBalancerImpl;
+    use crate::strategy::MockRouteStrategy;
+    use crate::tls::test_certs::TestCerts;
+
+    use super::*;
+
+    fn connector(certs: &TestCerts, client_cert: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut fs::read(certs.path("ca.pem")).unwrap().as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if client_cert {
+            let cert = rustls_pemfile::certs(&mut fs::read(certs.path("client.pem")).unwrap().as_slice()).map(Result::unwrap).collect();
+            let key = rustls_pemfile::private_key(&mut fs::read(certs.path("client.key")).unwrap().as_slice()).unwrap().unwrap();
+            builder.with_client_auth_cert(cert, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    // a request over mutual TLS, answered with a failure since the pool is empty
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let certs = TestCerts::generate("server");
+        let config = format!("cert = {:?}\nkey = {:?}\nclient_ca = {:?}", certs.path("server.pem"), certs.path("server.key"), certs.path("ca.pem"));
+        let acceptor = tls::acceptor(&toml::from_str(&config).unwrap()).unwrap();
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9000);
+        let server_name = ServerName::try_from("localhost").unwrap();
+
+        for client_cert in [true, false] {
+            let lb: Arc<Box<dyn LoadBalancer>> = Arc::new(Box::new(LoadBalancerImpl::new(vec![], Box::new(MockRouteStrategy::new()))));
+            let (client, server) = tokio::io::duplex(4096);
+            let connection = tokio::spawn(LBServer::handle_tls_connection(acceptor.clone(), server, addr, lb, (None, None, None)));
+            let stream = connector(&certs, client_cert).connect(server_name.clone(), client).await;
+            let response = match stream {
+                Ok(mut stream) => {
+                    stream.write_u32(2).await.unwrap();
+                    stream.write_all(b"{}").await.unwrap();
+                    stream.flush().await.unwrap();
+                    LBServer::read_request(&mut stream).await
+                }
+                Err(err) => Err(err.into()),
+            };
+            connection.await.unwrap();
+            match client_cert {
+                true => assert!(response.unwrap().contains("some error occurred")),
+                false => assert!(response.is_err()),
+            }
+        }
+    }
+
+    // the length prefix is refused before the body is allocated or read
+    #[tokio::test]
+    async fn test_read_request_too_large() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_u32(u32::MAX).await.unwrap();
+        assert!(LBServer::read_request(&mut server).await.is_err());
+
+        client.write_u32(MAX_REQUEST_SIZE as u32).await.unwrap();
+        let body = vec![b' '; MAX_REQUEST_SIZE];
+        let (written, read) = tokio::join!(client.write_all(&body), LBServer::read_request(&mut server));
+        written.unwrap();
+        assert_eq!(read.unwrap().len(), MAX_REQUEST_SIZE);
+    }
+
+    // answered by the load balancer unless the api key is known and allows the file, the pool is empty
+    #[tokio::test]
+    async fn test_auth() {
+        let authenticator = Arc::new(Authenticator::new(&AuthConfig::load(Path::new("src/config_test/auth_test.toml")).unwrap()));
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9000);
+        for (req, status) in [
+            (r#"{"word":"a","file_name":"text1.txt","api_key":"acme-key-1"}"#, "\"status_code\":-1"),
+            (r#"{"word":"a","file_name":"text2.txt","api_key":"acme-key-1"}"#, "\"status_code\":403"),
+            (r#"{"word":"a","file_name":"text1.txt","api_key":"unknown"}"#, "\"status_code\":401"),
+            (r#"{"word":"a","file_name":"text1.txt"}"#, "\"status_code\":401"),
+        ] {
+            let lb: Arc<Box<dyn LoadBalancer>> = Arc::new(Box::new(LoadBalancerImpl::new(vec![], Box::new(MockRouteStrategy::new()))));
+            let (mut client, server) = tokio::io::duplex(4096);
+            let connection = tokio::spawn(LBServer::handle_connection(server, addr, lb, (Some(Arc::clone(&authenticator)), None, None)));
+            LBServer::send_response(&mut client, req).await;
+            let response = LBServer::read_request(&mut client).await.unwrap();
+            connection.await.unwrap();
+            assert!(response.contains(status), "{} answered {}", req, response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let authenticator = Arc::new(Authenticator::new(&AuthConfig::load(Path::new("src/config_test/auth_test.toml")).unwrap()));
+        let rate_limiter = Arc::new(RateLimiter::new(&toml::from_str("requests_per_sec = 1\nburst = 1").unwrap()));
+        let acme = r#"{"word":"a","file_name":"text1.txt","api_key":"acme-key-1"}"#;
+        let unknown = r#"{"word":"a","file_name":"text1.txt","api_key":"unknown"}"#;
+        for (ip, req, status) in [
+            // an unknown key takes a token of the client IP too
+            (1, unknown, "\"status_code\":401"),
+            (1, acme, "\"status_code\":429"),
+            (2, acme, "\"status_code\":-1"),
+            // the tenant's bucket is empty, from any IP
+            (3, acme, "\"status_code\":429"),
+            (3, r#"{"word":"a","file_name":"text1.txt","api_key":"admin-key"}"#, "\"status_code\":429"),
+            (4, r#"{"word":"a","file_name":"text1.txt","api_key":"admin-key"}"#, "\"status_code\":-1"),
+        ] {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, ip)), 9000);
+            let lb: Arc<Box<dyn LoadBalancer>> = Arc::new(Box::new(LoadBalancerImpl::new(vec![], Box::new(MockRouteStrategy::new()))));
+            let (mut client, server) = tokio::io::duplex(4096);
+            let admission = (Some(Arc::clone(&authenticator)), Some(Arc::clone(&rate_limiter)), None);
+            let connection = tokio::spawn(LBServer::handle_connection(server, addr, lb, admission));
+            LBServer::send_response(&mut client, req).await;
+            let response = LBServer::read_request(&mut client).await.unwrap();
+            connection.await.unwrap();
+            assert!(response.contains(status), "{} from {} answered {}", req, addr, response);
+        }
+    }
+}
@@ -9,12 +9,13 @@ use crate::strategy::context::StrategyContext;
 pub mod round_robin;
 pub mod weighted_round_robin;
 pub mod hash_lb;
+pub mod sticky_session;
 pub mod context;
 
 #[automock]
 pub trait RouteStrategy: Sync + Send {
     #[allow(dead_code)]
     fn name(&self) -> String;
-    fn pick(&mut self, ctx: &StrategyContext, endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<Arc<Box<dyn Endpoint>>>;
+    fn pick(&mut self, ctx: &StrategyContext, endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<Arc<Box<dyn Endpoint>>>;
 }
 
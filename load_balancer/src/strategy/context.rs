@@ -1,15 +1,28 @@
+use std::net::SocketAddr;
+
 pub struct StrategyContext {
     req: String,
+    client_addr: Option<SocketAddr>,
 }
 
 impl StrategyContext {
     pub fn new(req: String) -> Self {
         StrategyContext {
-            req
+            req,
+            client_addr: None,
         }
     }
 
+    pub fn with_client_addr(mut self, client_addr: SocketAddr) -> Self {
+        self.client_addr = Some(client_addr);
+        self
+    }
+
     pub fn req(&self) -> &str {
         &self.req
     }
-}
\ No newline at end of file
+
+    pub fn client_addr(&self) -> Option<SocketAddr> {
+        self.client_addr
+    }
+}
@@ -25,7 +25,7 @@ impl RouteStrategy for HashByRequest {
         String::from(HASH_BY_REQUEST)
     }
 
-    fn pick(&mut self, ctx: &StrategyContext, endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<Arc<Box<dyn Endpoint>>> {
+    fn pick(&mut self, ctx: &StrategyContext, endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<Arc<Box<dyn Endpoint>>> {
         let hash = Self::hash(&ctx.req());
         let server_idx = (hash as usize) % endpoints.len();
         endpoints.get(server_idx).map(|endpoint| { Arc::clone(endpoint) })
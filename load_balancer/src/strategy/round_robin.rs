@@ -23,7 +23,7 @@ impl RouteStrategy for RoundRobin {
     fn name(&self) -> String {
         String::from(consts::ROUND_ROBIN)
     }
-    fn pick(&mut self, _ctx: &StrategyContext, endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<Arc<Box<dyn Endpoint>>> {
+    fn pick(&mut self, _ctx: &StrategyContext, endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<Arc<Box<dyn Endpoint>>> {
         let curr_idx = self.idx.fetch_add(1, Ordering::SeqCst) % endpoints.len();
         endpoints.get(curr_idx).map(|endpoint| { Arc::clone(endpoint) })
     }
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::consts;
+use crate::endpoint::Endpoint;
+use crate::strategy::context::StrategyContext;
+use crate::strategy::round_robin::RoundRobin;
+use crate::strategy::RouteStrategy;
+
+// picks between two sweeps of the expired affinities, a pick only checks the one it looks up
+const EVICT_INTERVAL: u64 = 1024;
+
+struct Affinity {
+    endpoint_name: String,
+    expire_at: Instant,
+}
+
+/// Pins each client to one endpoint for `ttl` since its last request, so bursts of related
+/// queries hit the same server's warm local cache. Clients are keyed by IP only, since every
+/// request arrives on a new TCP connection with a new source port.
+pub struct StickySession {
+    ttl: Duration,
+    affinities: HashMap<IpAddr, Affinity>,
+    fallback: RoundRobin,
+    picks: u64,
+}
+
+impl StickySession {
+    pub fn new(ttl: Duration) -> Self {
+        StickySession {
+            ttl,
+            affinities: HashMap::new(),
+            fallback: RoundRobin::new(None),
+            picks: 0,
+        }
+    }
+
+    // clients that never come back are dropped every `EVICT_INTERVAL` picks, keeping each pick O(1)
+    fn evict_expired(&mut self, now: Instant) {
+        self.picks = (self.picks + 1) % EVICT_INTERVAL;
+        if self.picks == 0 {
+            self.affinities.retain(|_, affinity| affinity.expire_at > now);
+        }
+    }
+
+    fn pinned(&self, client: &IpAddr, endpoints: &[Arc<Box<dyn Endpoint>>], now: Instant) -> Option<Arc<Box<dyn Endpoint>>> {
+        let affinity = self.affinities.get(client).filter(|affinity| affinity.expire_at > now)?;
+        // the pinned endpoint is missing once it turns unhealthy, fall back in that case
+        endpoints
+            .iter()
+            .find(|endpoint| endpoint.name() == affinity.endpoint_name)
+            .map(Arc::clone)
+    }
+}
+
+impl RouteStrategy for StickySession {
+    fn name(&self) -> String {
+        String::from(consts::STICKY_SESSION)
+    }
+
+    fn pick(&mut self, ctx: &StrategyContext, endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<Arc<Box<dyn Endpoint>>> {
+        let Some(client) = ctx.client_addr().map(|addr| addr.ip()) else {
+            return self.fallback.pick(ctx, endpoints);
+        };
+        let now = Instant::now();
+        self.evict_expired(now);
+
+        let endpoint = match self.pinned(&client, endpoints, now) {
+            Some(endpoint) => endpoint,
+            None => {
+                let endpoint = self.fallback.pick(ctx, endpoints)?;
+                tracing::info!("[StickySession] client {} pinned to endpoint {}", client, endpoint.name());
+                endpoint
+            }
+        };
+        self.affinities.insert(client, Affinity {
+            endpoint_name: endpoint.name(),
+            expire_at: now + self.ttl,
+        });
+        Some(endpoint)
+    }
+}
+
+#[cfg(test)]
+mod sticky_session_test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::thread;
+
+    use crate::consts::STICKY_SESSION;
+    use crate::endpoint::MockEndpoint;
+
+    use super::*;
+
+    fn endpoint(name: &'static str, port: u16) -> Arc<Box<dyn Endpoint>> {
+        let mut endpoint = MockEndpoint::new();
+        endpoint.expect_name().returning(move || name.to_string());
+        endpoint.expect_addr().returning(move || SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port));
+        Arc::new(Box::new(endpoint))
+    }
+
+    fn ctx(client: [u8; 4], port: u16) -> StrategyContext {
+        StrategyContext::new(String::new()).with_client_addr(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(client)), port))
+    }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(STICKY_SESSION, StickySession::new(Duration::from_secs(1)).name());
+    }
+
+    #[test]
+    fn test_pick_same_client() {
+        let endpoints = vec![endpoint("s1", 8080), endpoint("s2", 8081), endpoint("s3", 8082)];
+        let mut sticky = StickySession::new(Duration::from_secs(60));
+
+        let first = sticky.pick(&ctx([10, 0, 0, 1], 40000), &endpoints).unwrap();
+        for port in 40001..40010 {
+            let target = sticky.pick(&ctx([10, 0, 0, 1], port), &endpoints).unwrap();
+            assert_eq!(first.addr(), target.addr());
+        }
+        let other = sticky.pick(&ctx([10, 0, 0, 2], 40000), &endpoints).unwrap();
+        assert_ne!(first.addr(), other.addr());
+    }
+
+    #[test]
+    fn test_pick_after_ttl_expired() {
+        let endpoints = vec![endpoint("s1", 8080), endpoint("s2", 8081)];
+        let mut sticky = StickySession::new(Duration::from_millis(20));
+
+        let first = sticky.pick(&ctx([10, 0, 0, 1], 40000), &endpoints).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(sticky.affinities.contains_key(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        let second = sticky.pick(&ctx([10, 0, 0, 1], 40001), &endpoints).unwrap();
+        assert_ne!(first.addr(), second.addr());
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let endpoints = vec![endpoint("s1", 8080), endpoint("s2", 8081)];
+        let mut sticky = StickySession::new(Duration::from_millis(20));
+
+        sticky.pick(&ctx([10, 0, 0, 1], 40000), &endpoints).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        // swept along with a later pick, not on every one
+        for _ in 1..EVICT_INTERVAL - 1 {
+            sticky.pick(&ctx([10, 0, 0, 2], 40000), &endpoints).unwrap();
+        }
+        assert!(sticky.affinities.contains_key(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        sticky.pick(&ctx([10, 0, 0, 2], 40000), &endpoints).unwrap();
+        assert!(!sticky.affinities.contains_key(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert_eq!(sticky.affinities.len(), 1);
+    }
+
+    #[test]
+    fn test_pick_fallback_on_unhealthy() {
+        let endpoints = vec![endpoint("s1", 8080), endpoint("s2", 8081)];
+        let mut sticky = StickySession::new(Duration::from_secs(60));
+
+        let first = sticky.pick(&ctx([10, 0, 0, 1], 40000), &endpoints).unwrap();
+        assert_eq!(first.name(), "s1");
+        // s1 is filtered out by the load balancer after turning unhealthy
+        let healthy = vec![Arc::clone(&endpoints[1])];
+        let second = sticky.pick(&ctx([10, 0, 0, 1], 40001), &healthy).unwrap();
+        assert_eq!(second.name(), "s2");
+        // the client stays on its new endpoint after s1 recovers
+        let third = sticky.pick(&ctx([10, 0, 0, 1], 40002), &endpoints).unwrap();
+        assert_eq!(third.name(), "s2");
+    }
+
+    #[test]
+    fn test_pick_without_client_addr() {
+        let endpoints = vec![endpoint("s1", 8080), endpoint("s2", 8081)];
+        let mut sticky = StickySession::new(Duration::from_secs(60));
+
+        let first = sticky.pick(&StrategyContext::new(String::new()), &endpoints).unwrap();
+        let second = sticky.pick(&StrategyContext::new(String::new()), &endpoints).unwrap();
+        assert_ne!(first.addr(), second.addr());
+        assert!(sticky.affinities.is_empty());
+    }
+}
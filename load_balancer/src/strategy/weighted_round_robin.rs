@@ -33,10 +33,12 @@ impl WeightedRoundRobin {
         }
     }
 
-    fn cal_gcd(endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<u8> {
+    fn cal_gcd(endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<u8> {
+        // a zero weight would divide by zero in gcd, such endpoints are never picked anyway
         let weights: Vec<u8> = endpoints
             .iter()
             .filter_map(|endpoint| endpoint.weight())
+            .filter(|weight| *weight > 0)
             .collect();
 
         if weights.is_empty() {
@@ -47,14 +49,14 @@ impl WeightedRoundRobin {
 
         if let Some(weights) = weights.get(1..) {
             for weight in weights {
-                result = Self::gcd(result.clone(), weight.clone())
+                result = Self::gcd(result, *weight)
             }
         }
 
         Some(result)
     }
 
-    fn max(endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<u8> {
+    fn max(endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<u8> {
         endpoints
             .iter()
             .filter_map(|endpoint| endpoint.weight())
@@ -64,10 +66,10 @@ impl WeightedRoundRobin {
 
 impl RouteStrategy for WeightedRoundRobin {
     fn name(&self) -> String {
-        return String::from(consts::WEIGHTED_ROUND_ROBIN);
+        String::from(consts::WEIGHTED_ROUND_ROBIN)
     }
 
-    fn pick(&mut self, _ctx: &StrategyContext, endpoints: &Vec<Arc<Box<dyn Endpoint>>>) -> Option<Arc<Box<dyn Endpoint>>> {
+    fn pick(&mut self, _ctx: &StrategyContext, endpoints: &[Arc<Box<dyn Endpoint>>]) -> Option<Arc<Box<dyn Endpoint>>> {
         let guard = self.guard.lock();
         if guard.is_err() {
             return None;
@@ -151,4 +153,21 @@ mod round_robin_test {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_pick_zero_weight() {
+        let mut endpoint1 = MockEndpoint::new();
+        endpoint1.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080));
+        endpoint1.expect_weight().returning(|| Some(0));
+        let mut endpoint2 = MockEndpoint::new();
+        endpoint2.expect_addr().returning(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081));
+        endpoint2.expect_weight().returning(|| Some(4));
+        let endpoints: Vec<Arc<Box<dyn Endpoint>>> = vec![Arc::new(Box::new(endpoint1)), Arc::new(Box::new(endpoint2))];
+
+        let mut weighted_round_robin = WeightedRoundRobin::new();
+        for _ in 0..3 {
+            let target = weighted_round_robin.pick(&StrategyContext::new(String::new()), &endpoints);
+            assert_eq!(target.unwrap().addr(), SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8081));
+        }
+    }
+}
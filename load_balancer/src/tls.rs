@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+use crate::model::endpoints_config::EndpointTlsConfig;
+use crate::model::server_config::ListenerTlsConfig;
+
+// TLS termination of client connections, a client certificate signed by `client_ca` is required when it is set
+pub fn acceptor(config: &ListenerTlsConfig) -> Result<TlsAcceptor> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .context("TLS protocol versions unsupported")?;
+    let builder = match config.client_ca() {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certs(Path::new(client_ca))? {
+                roots.add(cert).with_context(|| format!("invalid client CA certificate in {:?}", client_ca))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("build client certificate verifier failed")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server_config = builder
+        .with_single_cert(certs(Path::new(config.cert()))?, private_key(Path::new(config.key()))?)
+        .context("invalid TLS certificate or key")?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+// TLS of the channel to counter_service, with a client certificate (mutual TLS) when `cert` and `key` are set
+pub fn client_config(config: &EndpointTlsConfig) -> Result<ClientTlsConfig> {
+    let ca_cert = read(Path::new(config.ca_cert()))?;
+    let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_cert));
+    if let Some(domain_name) = config.domain_name() {
+        tls = tls.domain_name(domain_name);
+    }
+    if let (Some(cert), Some(key)) = (config.cert(), config.key()) {
+        tls = tls.identity(Identity::from_pem(read(Path::new(cert))?, read(Path::new(key))?));
+    }
+    Ok(tls)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("failed to read TLS file:{:?}", path))
+}
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut read(path)?.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid PEM certificate in {:?}", path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {:?}", path));
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut read(path)?.as_slice())
+        .with_context(|| format!("invalid PEM private key in {:?}", path))?
+        .ok_or_else(|| anyhow!("no private key found in {:?}", path))
+}
+
+// CA, server and client certificates for `localhost` and 127.0.0.1, written to a directory per test
+#[cfg(test)]
+pub mod test_certs {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    pub struct TestCerts {
+        pub dir: PathBuf,
+    }
+
+    impl TestCerts {
+        pub fn generate(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("lb-tls-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+            for leaf in ["server", "client"] {
+                let key = KeyPair::generate().unwrap();
+                let params = CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()]).unwrap();
+                let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+                fs::write(dir.join(format!("{}.pem", leaf)), cert.pem()).unwrap();
+                fs::write(dir.join(format!("{}.key", leaf)), key.serialize_pem()).unwrap();
+            }
+            TestCerts { dir }
+        }
+
+        pub fn path(&self, file: &str) -> String {
+            self.dir.join(file).to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TestCerts {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}